@@ -0,0 +1,26 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! Records the commit and target being built, for `minilux --version`.
+
+use std::env;
+use std::process::Command;
+
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=MINILUX_COMMIT={}", commit);
+    println!("cargo:rustc-env=MINILUX_TARGET={}", env::var("TARGET").unwrap_or_default());
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -0,0 +1,53 @@
+/*
+ * The Minilux Programming Language Example
+ * Title: Embedding Minilux in a C program
+ * Version: 0.1.0
+ * Author: Alexia Michelle <https://minilux.org>
+ * License: MPL 2.0
+ * SPDX-License-Identifier: MPL-2.0
+ *
+ * Build:  cargo build --release
+ *         cc -Iinclude -o embed examples/embed/embed.c -Ltarget/release -lminilux
+ * Run:    LD_LIBRARY_PATH=target/release ./embed
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include "minilux.h"
+
+static char buffer[64];
+
+/* Scripts call host_twice(n) to get 2 * n from the host application. */
+static const char *host_twice(int argc, const char *const *argv, void *userdata)
+{
+    int *calls = userdata;
+    (*calls)++;
+    snprintf(buffer, sizeof buffer, "%d", argc > 0 ? 2 * atoi(argv[0]) : 0);
+    return buffer;
+}
+
+int main(void)
+{
+    int calls = 0;
+    MiniluxInterpreter *interp = minilux_new();
+
+    minilux_register(interp, "host_twice", host_twice, &calls);
+    minilux_set_var(interp, "user", "embedder");
+    minilux_set_var_int(interp, "base", 20);
+
+    if (minilux_eval(interp, "$answer = number(host_twice($base)) + 2\n"
+                             "printf(\"Hello, \", $user, \"!\")\n") != 0) {
+        fprintf(stderr, "error: %s\n", minilux_last_error(interp));
+    }
+
+    char *answer = minilux_get_var(interp, "answer");
+    printf("answer = %s (host_twice called %d time(s))\n", answer ? answer : "(nil)", calls);
+    minilux_string_free(answer);
+
+    if (minilux_eval(interp, "fail(\"boom\")") != 0) {
+        printf("error reported: %s\n", minilux_last_error(interp));
+    }
+
+    minilux_free(interp);
+    return 0;
+}
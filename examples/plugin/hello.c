@@ -0,0 +1,43 @@
+/*
+ * The Minilux Programming Language Example
+ * Title: Native plugin exposing builtins through loadlib()
+ * Version: 0.1.0
+ * Author: Alexia Michelle <https://minilux.org>
+ * License: MPL 2.0
+ * SPDX-License-Identifier: MPL-2.0
+ *
+ * Build:  cc -shared -fPIC -o examples/plugin/libhello.so examples/plugin/hello.c
+ * Run:    minilux examples/plugin/hello.mi
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+typedef const char *(*minilux_fn)(int argc, const char *const *argv, void *userdata);
+typedef void (*minilux_register_fn)(void *host, const char *name, minilux_fn fn, void *userdata);
+
+static char buffer[256];
+
+/* greet(name) -> "Hello, <name>!" */
+static const char *greet(int argc, const char *const *argv, void *userdata)
+{
+    (void)userdata;
+    snprintf(buffer, sizeof buffer, "Hello, %s!", argc > 0 ? argv[0] : "world");
+    return buffer;
+}
+
+/* count_args(...) -> number of arguments */
+static const char *count_args(int argc, const char *const *argv, void *userdata)
+{
+    (void)argv;
+    (void)userdata;
+    snprintf(buffer, sizeof buffer, "%d", argc);
+    return buffer;
+}
+
+int minilux_plugin_init(void *host, minilux_register_fn register_fn)
+{
+    register_fn(host, "greet", greet, NULL);
+    register_fn(host, "count_args", count_args, NULL);
+    return 0;
+}
@@ -0,0 +1,50 @@
+// The Minilux Programming Language Example
+// Title: JavaScript glue for the wasm build
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+//
+// Build: cargo build --lib --release --target wasm32-unknown-unknown
+// The module is target/wasm32-unknown-unknown/release/minilux.wasm
+
+export async function load(bytes) {
+    const { instance } = await WebAssembly.instantiate(bytes, {});
+    const wasm = instance.exports;
+    const encoder = new TextEncoder();
+    const decoder = new TextDecoder();
+
+    const toWasm = (text) => {
+        const data = encoder.encode(text + "\0");
+        const pointer = wasm.minilux_alloc(data.length);
+        new Uint8Array(wasm.memory.buffer, pointer, data.length).set(data);
+        return [pointer, data.length];
+    };
+    const fromWasm = (pointer) => {
+        if (pointer === 0) {
+            return null;
+        }
+        const memory = new Uint8Array(wasm.memory.buffer);
+        let end = pointer;
+        while (memory[end] !== 0) {
+            end++;
+        }
+        return decoder.decode(memory.subarray(pointer, end));
+    };
+
+    // Run a script in a fresh interpreter and return its output and error.
+    return function run(source) {
+        const interp = wasm.minilux_new();
+        wasm.minilux_capture_output(interp);
+        const [pointer, size] = toWasm(source);
+        const status = wasm.minilux_eval(interp, pointer);
+        wasm.minilux_dealloc(pointer, size);
+
+        const error = status === 0 ? null : fromWasm(wasm.minilux_last_error(interp));
+        const captured = wasm.minilux_take_output(interp);
+        const output = fromWasm(captured);
+        wasm.minilux_string_free(captured);
+        wasm.minilux_free(interp);
+        return { output, error };
+    };
+}
@@ -0,0 +1,57 @@
+/*
+ * The Minilux Programming Language
+ * Version: 0.1.0
+ * Author: Alexia Michelle <https://minilux.org>
+ * License: MPL 2.0
+ * SPDX-License-Identifier: MPL-2.0
+ *
+ * C embedding API. Link against libminilux (target/release/libminilux.so,
+ * .dylib, .dll or the static libminilux.a).
+ */
+
+#ifndef MINILUX_H
+#define MINILUX_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct MiniluxInterpreter MiniluxInterpreter;
+
+/* Host function callable from scripts. Arguments arrive as strings; the
+ * returned string must stay valid until the next call (NULL means nil). */
+typedef const char *(*minilux_fn)(int argc, const char *const *argv, void *userdata);
+
+MiniluxInterpreter *minilux_new(void);
+void minilux_free(MiniluxInterpreter *interp);
+
+/* Run a script; returns 0 on success and -1 on error. The script runs on a
+ * thread with a large stack, so functions registered with minilux_register
+ * are called from that thread while minilux_eval waits for it. */
+int minilux_eval(MiniluxInterpreter *interp, const char *source);
+
+/* Message of the last failed minilux_eval, or NULL. Valid until the next eval. */
+const char *minilux_last_error(const MiniluxInterpreter *interp);
+
+/* Value of $name as a string, or NULL when unset. Free with minilux_string_free. */
+char *minilux_get_var(const MiniluxInterpreter *interp, const char *name);
+void minilux_set_var(MiniluxInterpreter *interp, const char *name, const char *value);
+void minilux_set_var_int(MiniluxInterpreter *interp, const char *name, int64_t value);
+
+/* Make fn callable from scripts as name(...). Returns 0 on success. */
+int minilux_register(MiniluxInterpreter *interp, const char *name, minilux_fn fn, void *userdata);
+
+/* Collect script output instead of printing it; fetch it with minilux_take_output
+ * (free the result with minilux_string_free). */
+void minilux_capture_output(MiniluxInterpreter *interp);
+char *minilux_take_output(MiniluxInterpreter *interp);
+
+void minilux_string_free(char *text);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MINILUX_H */
@@ -0,0 +1,238 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! The syntax tree as JSON, for `minilux --ast=json`. Every node is an
+//! object whose `type` names its variant; statements also carry their `span`.
+
+use crate::lexer::Span;
+use crate::parser::{Expr, Statement, Stmt};
+use serde_json::{json, Value};
+
+pub fn statements(stmts: &[Stmt]) -> Value {
+    Value::Array(stmts.iter().map(statement).collect())
+}
+
+fn span(span: &Span) -> Value {
+    json!({
+        "line": span.line,
+        "column": span.column,
+        "end_line": span.end_line,
+        "end_column": span.end_column,
+    })
+}
+
+fn exprs(exprs: &[Expr]) -> Value {
+    Value::Array(exprs.iter().map(expr).collect())
+}
+
+fn optional_block(stmts: &Option<Vec<Stmt>>) -> Value {
+    stmts.as_deref().map_or(Value::Null, statements)
+}
+
+fn statement(stmt: &Stmt) -> Value {
+    let mut node = match &stmt.kind {
+        Statement::Assignment { var, value } => {
+            json!({ "type": "Assignment", "var": var, "value": expr(value) })
+        }
+        Statement::ArrayAssignment {
+            var,
+            indices,
+            value,
+        } => json!({
+            "type": "ArrayAssignment",
+            "var": var,
+            "indices": exprs(indices),
+            "value": expr(value),
+        }),
+        Statement::If {
+            condition,
+            then_body,
+            elseif_parts,
+            else_body,
+        } => json!({
+            "type": "If",
+            "condition": expr(condition),
+            "then_body": statements(then_body),
+            "elseif_parts": elseif_parts
+                .iter()
+                .map(|(condition, body)| json!({ "condition": expr(condition), "body": statements(body) }))
+                .collect::<Vec<_>>(),
+            "else_body": optional_block(else_body),
+        }),
+        Statement::While { condition, body } => {
+            json!({ "type": "While", "condition": expr(condition), "body": statements(body) })
+        }
+        Statement::For {
+            init,
+            condition,
+            step,
+            body,
+        } => json!({
+            "type": "For",
+            "init": init.as_deref().map_or(Value::Null, statement),
+            "condition": condition.as_ref().map_or(Value::Null, expr),
+            "step": step.as_deref().map_or(Value::Null, statement),
+            "body": statements(body),
+        }),
+        Statement::ForIn {
+            var,
+            iterable,
+            body,
+        } => json!({
+            "type": "ForIn",
+            "var": var,
+            "iterable": expr(iterable),
+            "body": statements(body),
+        }),
+        Statement::Printf { format, args } => {
+            json!({ "type": "Printf", "format": format, "args": exprs(args) })
+        }
+        Statement::Read { var } => json!({ "type": "Read", "var": var }),
+        Statement::Inc { var, value } => json!({ "type": "Inc", "var": var, "value": expr(value) }),
+        Statement::Dec { var, value } => json!({ "type": "Dec", "var": var, "value": expr(value) }),
+        Statement::Push { array, value } => {
+            json!({ "type": "Push", "array": array, "value": expr(value) })
+        }
+        Statement::Pop { array } => json!({ "type": "Pop", "array": array }),
+        Statement::Shift { array } => json!({ "type": "Shift", "array": array }),
+        Statement::Unshift { array, value } => {
+            json!({ "type": "Unshift", "array": array, "value": expr(value) })
+        }
+        Statement::Sockopen { name, host, port } => json!({
+            "type": "Sockopen",
+            "name": name,
+            "host": expr(host),
+            "port": expr(port),
+        }),
+        Statement::Sockclose { name } => json!({ "type": "Sockclose", "name": name }),
+        Statement::Sockwrite { name, data } => {
+            json!({ "type": "Sockwrite", "name": name, "data": expr(data) })
+        }
+        Statement::Sockread { name, var } => {
+            json!({ "type": "Sockread", "name": name, "var": var })
+        }
+        Statement::Serialopen { name, device, baud } => json!({
+            "type": "Serialopen",
+            "name": name,
+            "device": expr(device),
+            "baud": expr(baud),
+        }),
+        Statement::Serialclose { name } => json!({ "type": "Serialclose", "name": name }),
+        Statement::Serialwrite { name, data } => {
+            json!({ "type": "Serialwrite", "name": name, "data": expr(data) })
+        }
+        Statement::Serialread { name, var } => {
+            json!({ "type": "Serialread", "name": name, "var": var })
+        }
+        Statement::Include { path } => json!({ "type": "Include", "path": path }),
+        Statement::FunctionDef { name, params, body } => json!({
+            "type": "FunctionDef",
+            "name": name,
+            "params": params,
+            "body": statements(body),
+        }),
+        Statement::FunctionCall { name, args } => {
+            json!({ "type": "FunctionCall", "name": name, "args": exprs(args) })
+        }
+        Statement::Return { value } => {
+            json!({ "type": "Return", "value": value.as_ref().map_or(Value::Null, expr) })
+        }
+        Statement::Global { vars } => json!({ "type": "Global", "vars": vars }),
+        Statement::Strict => json!({ "type": "Strict" }),
+        Statement::Overflow(mode) => json!({ "type": "Overflow", "mode": format!("{:?}", mode) }),
+        Statement::Try {
+            body,
+            catch_var,
+            catch_body,
+            finally_body,
+        } => json!({
+            "type": "Try",
+            "body": statements(body),
+            "catch_var": catch_var,
+            "catch_body": optional_block(catch_body),
+            "finally_body": optional_block(finally_body),
+        }),
+        Statement::Throw { value } => json!({ "type": "Throw", "value": expr(value) }),
+    };
+    node["span"] = span(&stmt.span);
+    node
+}
+
+fn expr(expr_: &Expr) -> Value {
+    match expr_ {
+        Expr::Int(n) => json!({ "type": "Int", "value": n }),
+        // NaN and infinities have no JSON number, and come out as null.
+        Expr::Float(x) => json!({ "type": "Float", "value": x }),
+        Expr::Bool(b) => json!({ "type": "Bool", "value": b }),
+        Expr::String(s) => json!({ "type": "String", "value": s }),
+        Expr::Regex { pat, flags } => json!({ "type": "Regex", "pat": pat, "flags": flags }),
+        Expr::SubstCall {
+            pat,
+            repl,
+            flags,
+            input,
+        } => json!({
+            "type": "SubstCall",
+            "pat": pat,
+            "repl": repl,
+            "flags": flags,
+            "input": expr(input),
+        }),
+        Expr::Variable(name) => json!({ "type": "Variable", "name": name }),
+        Expr::Binary { left, op, right } => json!({
+            "type": "Binary",
+            "op": format!("{:?}", op),
+            "left": expr(left),
+            "right": expr(right),
+        }),
+        Expr::Unary { op, expr: operand } => {
+            json!({ "type": "Unary", "op": format!("{:?}", op), "expr": expr(operand) })
+        }
+        Expr::Array(items) => json!({ "type": "Array", "items": exprs(items) }),
+        Expr::Map(entries) => json!({
+            "type": "Map",
+            "entries": entries
+                .iter()
+                .map(|(key, value)| json!({ "key": expr(key), "value": expr(value) }))
+                .collect::<Vec<_>>(),
+        }),
+        Expr::Index {
+            expr: target,
+            index,
+            optional,
+        } => json!({
+            "type": "Index",
+            "expr": expr(target),
+            "index": expr(index),
+            "optional": optional,
+        }),
+        Expr::Slice {
+            expr: target,
+            start,
+            end,
+            optional,
+        } => json!({
+            "type": "Slice",
+            "expr": expr(target),
+            "start": start.as_deref().map_or(Value::Null, expr),
+            "end": end.as_deref().map_or(Value::Null, expr),
+            "optional": optional,
+        }),
+        Expr::FunctionCall {
+            name,
+            args,
+            span: at,
+        } => json!({
+            "type": "FunctionCall",
+            "name": name,
+            "args": exprs(args),
+            "span": span(at),
+        }),
+        Expr::Lambda { params, body } => {
+            json!({ "type": "Lambda", "params": params, "body": statements(body) })
+        }
+    }
+}
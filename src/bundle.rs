@@ -0,0 +1,192 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! `minilux build`: a copy of the interpreter with a script and its includes
+//! appended. At startup the interpreter looks for such a bundle at the end of
+//! its own executable and runs it.
+//!
+//! Layout after the executable: entries of `u32 key length, key, u64 content
+//! length, content` (the main script first), then the `u64` length of all
+//! entries and the magic bytes.
+
+use crate::interpreter::Interpreter;
+use crate::parser::{Parser, Statement, Stmt};
+use crate::{lint, stdlib};
+use minilux::host::{FileSystem, OsFileSystem};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"MLXBNDL1";
+
+/// Directory bundled files appear under while the program runs
+const ROOT: &str = "/minilux-bundle";
+
+/// Scripts carried by a built executable, keyed by path relative to the main script
+pub struct Bundle {
+    pub main: String,
+    files: Vec<(String, String)>,
+}
+
+impl Bundle {
+    pub fn main_source(&self) -> &str {
+        &self.files[0].1
+    }
+
+    /// Filesystem that serves bundled files under ROOT and everything else from disk
+    pub fn file_system(&self) -> BundleFileSystem {
+        BundleFileSystem {
+            files: self
+                .files
+                .iter()
+                .map(|(key, content)| (Path::new(ROOT).join(key), content.clone()))
+                .collect(),
+        }
+    }
+
+    /// Base directory the main script runs in
+    pub fn root() -> PathBuf {
+        PathBuf::from(ROOT)
+    }
+}
+
+pub struct BundleFileSystem {
+    files: HashMap<PathBuf, String>,
+}
+
+impl FileSystem for BundleFileSystem {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        match self.files.get(path) {
+            Some(content) => Ok(content.clone()),
+            None => OsFileSystem.read_to_string(path),
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(path) || OsFileSystem.exists(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8], append: bool) -> io::Result<()> {
+        OsFileSystem.write(path, data, append)
+    }
+}
+
+/// Write `output`: this executable followed by `script` and every file it includes.
+pub fn build(script: &Path, output: &Path, resolver: &mut Interpreter) -> Result<usize, String> {
+    let content = fs::read_to_string(script).map_err(|e| format!("Failed to read file: {}", e))?;
+    let name = script
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "main.mi".to_string());
+    let dir = fs::canonicalize(script)
+        .ok()
+        .and_then(|p| p.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    let mut files = vec![(name, content.clone())];
+    let mut seen = Vec::new();
+    collect_includes(&content, Path::new(""), &dir, resolver, &mut files, &mut seen)?;
+
+    let exe = env::current_exe().map_err(|e| format!("Cannot locate the interpreter: {}", e))?;
+    fs::copy(&exe, output).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+
+    let mut payload = Vec::new();
+    for (key, content) in &files {
+        payload.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        payload.extend_from_slice(key.as_bytes());
+        payload.extend_from_slice(&(content.len() as u64).to_le_bytes());
+        payload.extend_from_slice(content.as_bytes());
+    }
+    payload.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    payload.extend_from_slice(MAGIC);
+
+    fs::OpenOptions::new()
+        .append(true)
+        .open(output)
+        .and_then(|mut out| out.write_all(&payload))
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    Ok(files.len())
+}
+
+/// Resolve includes the way the interpreter will, recording each file under
+/// the key it will be looked up with inside the bundle.
+fn collect_includes(
+    source: &str,
+    key_dir: &Path,
+    real_dir: &Path,
+    resolver: &mut Interpreter,
+    files: &mut Vec<(String, String)>,
+    seen: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let statements: Vec<Stmt> = Parser::new(source).parse();
+    let mut includes = Vec::new();
+    lint::walk(&statements, &mut |stmt| {
+        if let Statement::Include { path } = &stmt.kind {
+            includes.push(path.clone());
+        }
+    });
+
+    for include in includes {
+        if stdlib::source(&include).is_some() || Path::new(&include).is_absolute() {
+            continue;
+        }
+        // Found next to the including file, or through the search path.
+        let (key, resolved) = if real_dir.join(&include).exists() {
+            (key_dir.join(&include), real_dir.join(&include))
+        } else {
+            resolver.push_base_dir(real_dir.to_path_buf());
+            let resolved = resolver.resolve_include_path(&include);
+            resolver.pop_base_dir();
+            (PathBuf::from(&include), resolved)
+        };
+        let canonical = fs::canonicalize(&resolved).unwrap_or(resolved.clone());
+        if seen.contains(&canonical) {
+            continue;
+        }
+        seen.push(canonical.clone());
+
+        let content = fs::read_to_string(&canonical)
+            .map_err(|e| format!("Failed to read include '{}': {}", include, e))?;
+        let key_parent = key.parent().map(Path::to_path_buf).unwrap_or_default();
+        let real_parent = canonical.parent().map(Path::to_path_buf).unwrap_or_default();
+        files.push((key.to_string_lossy().into_owned(), content.clone()));
+        collect_includes(&content, &key_parent, &real_parent, resolver, files, seen)?;
+    }
+    Ok(())
+}
+
+/// The bundle appended to the running executable, if there is one.
+pub fn from_current_exe() -> Option<Bundle> {
+    let exe = env::current_exe().ok()?;
+    let mut file = File::open(exe).ok()?;
+
+    let mut trailer = [0u8; 16];
+    file.seek(SeekFrom::End(-16)).ok()?;
+    file.read_exact(&mut trailer).ok()?;
+    if &trailer[8..] != MAGIC {
+        return None;
+    }
+    let length = u64::from_le_bytes(trailer[..8].try_into().ok()?);
+    file.seek(SeekFrom::End(-16 - length as i64)).ok()?;
+    let mut payload = vec![0u8; length as usize];
+    file.read_exact(&mut payload).ok()?;
+
+    let mut files = Vec::new();
+    let mut rest = payload.as_slice();
+    while !rest.is_empty() {
+        let key_len = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let key = String::from_utf8(rest.get(4..4 + key_len)?.to_vec()).ok()?;
+        rest = &rest[4 + key_len..];
+        let content_len = u64::from_le_bytes(rest.get(..8)?.try_into().ok()?) as usize;
+        let content = String::from_utf8(rest.get(8..8 + content_len)?.to_vec()).ok()?;
+        rest = &rest[8 + content_len..];
+        files.push((key, content));
+    }
+    let main = files.first()?.0.clone();
+    Some(Bundle { main, files })
+}
@@ -0,0 +1,244 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! C embedding API, declared in `include/minilux.h`.
+
+use crate::host::OutputBuffer;
+use crate::interpreter::Interpreter;
+#[cfg(not(target_family = "wasm"))]
+use crate::interpreter::SCRIPT_STACK_SIZE;
+use crate::plugin::{self, PluginFn};
+use crate::value::Value;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ptr;
+#[cfg(not(target_family = "wasm"))]
+use std::thread;
+
+/// Opaque handle handed out to C callers
+pub struct MiniluxInterpreter {
+    interpreter: Interpreter,
+    last_error: Option<CString>,
+    /// Script output collected for `minilux_take_output`
+    output: Option<OutputBuffer>,
+}
+
+unsafe fn to_str<'a>(text: *const c_char) -> Option<&'a str> {
+    if text.is_null() {
+        None
+    } else {
+        CStr::from_ptr(text).to_str().ok()
+    }
+}
+
+/// Create an interpreter. Free it with `minilux_free`.
+#[no_mangle]
+pub extern "C" fn minilux_new() -> *mut MiniluxInterpreter {
+    Box::into_raw(Box::new(MiniluxInterpreter {
+        interpreter: Interpreter::new(),
+        last_error: None,
+        output: None,
+    }))
+}
+
+/// # Safety
+/// `handle` must come from `minilux_new` (or be NULL) and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn minilux_free(handle: *mut MiniluxInterpreter) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Run `source`; returns 0 on success, -1 on error (see `minilux_last_error`).
+///
+/// # Safety
+/// `handle` must be a live interpreter and `source` a NUL-terminated UTF-8 string.
+#[no_mangle]
+pub unsafe extern "C" fn minilux_eval(handle: *mut MiniluxInterpreter, source: *const c_char) -> c_int {
+    let Some(handle) = handle.as_mut() else {
+        return -1;
+    };
+    let result = match to_str(source) {
+        Some(source) => execute(&mut handle.interpreter, source),
+        None => Err("source is not valid UTF-8".to_string()),
+    };
+    match result {
+        Ok(()) => {
+            handle.last_error = None;
+            0
+        }
+        Err(e) => {
+            handle.last_error = CString::new(e).ok();
+            -1
+        }
+    }
+}
+
+/// Run `source` on a thread with SCRIPT_STACK_SIZE, as the minilux binary
+/// does, so deep recursion stops at the call depth limit instead of
+/// overflowing the caller's (usually much smaller) stack. The caller waits,
+/// so the interpreter is still only used by one thread at a time.
+#[cfg(not(target_family = "wasm"))]
+fn execute(interpreter: &mut Interpreter, source: &str) -> Result<(), String> {
+    struct Borrowed<'a>(&'a mut Interpreter);
+    // SAFETY: the interpreter moves to the script thread and back, and is
+    // never touched by two threads at once: the calling thread blocks in
+    // join() until the script thread is done with it.
+    unsafe impl Send for Borrowed<'_> {}
+
+    let borrowed = Borrowed(interpreter);
+    thread::scope(|scope| {
+        let script = thread::Builder::new()
+            .stack_size(SCRIPT_STACK_SIZE)
+            .spawn_scoped(scope, move || {
+                let borrowed = borrowed;
+                borrowed.0.execute_source(source)
+            })
+            .map_err(|e| format!("failed to start interpreter thread: {}", e))?;
+        script
+            .join()
+            .unwrap_or_else(|_| Err("the interpreter panicked".to_string()))
+    })
+}
+
+/// wasm has no threads; scripts run on the caller's stack.
+#[cfg(target_family = "wasm")]
+fn execute(interpreter: &mut Interpreter, source: &str) -> Result<(), String> {
+    interpreter.execute_source(source)
+}
+
+/// Message of the last failed `minilux_eval`, or NULL. Valid until the next eval.
+///
+/// # Safety
+/// `handle` must be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn minilux_last_error(handle: *const MiniluxInterpreter) -> *const c_char {
+    match handle.as_ref().and_then(|h| h.last_error.as_ref()) {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Value of `$name` as a string, or NULL when unset. Free it with `minilux_string_free`.
+///
+/// # Safety
+/// `handle` must be a live interpreter and `name` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn minilux_get_var(handle: *const MiniluxInterpreter, name: *const c_char) -> *mut c_char {
+    let (Some(handle), Some(name)) = (handle.as_ref(), to_str(name)) else {
+        return ptr::null_mut();
+    };
+    match handle.interpreter.get_var(name) {
+        Value::Nil => ptr::null_mut(),
+        value => CString::new(value.to_string()).map_or(ptr::null_mut(), CString::into_raw),
+    }
+}
+
+/// Set `$name` to a string value.
+///
+/// # Safety
+/// `handle` must be a live interpreter; `name` and `value` NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn minilux_set_var(
+    handle: *mut MiniluxInterpreter,
+    name: *const c_char,
+    value: *const c_char,
+) {
+    if let (Some(handle), Some(name), Some(value)) = (handle.as_mut(), to_str(name), to_str(value)) {
+        handle.interpreter.set_var(name, Value::String(value.to_string()));
+    }
+}
+
+/// Set `$name` to an integer value.
+///
+/// # Safety
+/// `handle` must be a live interpreter and `name` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn minilux_set_var_int(handle: *mut MiniluxInterpreter, name: *const c_char, value: i64) {
+    if let (Some(handle), Some(name)) = (handle.as_mut(), to_str(name)) {
+        handle.interpreter.set_var(name, Value::Int(value));
+    }
+}
+
+/// Make `function` callable from scripts as `name(...)`, with the same calling
+/// convention as plugin functions. Returns 0 on success.
+///
+/// # Safety
+/// `handle` must be a live interpreter, `name` a NUL-terminated string, and
+/// `function` must stay callable with `userdata` for the interpreter's lifetime.
+#[no_mangle]
+pub unsafe extern "C" fn minilux_register(
+    handle: *mut MiniluxInterpreter,
+    name: *const c_char,
+    function: PluginFn,
+    userdata: *mut c_void,
+) -> c_int {
+    let (Some(handle), Some(name)) = (handle.as_mut(), to_str(name)) else {
+        return -1;
+    };
+    let host = plugin::host_function(name.to_string(), function, userdata);
+    handle.interpreter.register_function(name, host);
+    0
+}
+
+/// Collect script output instead of writing it to stdout. Needed where there
+/// is no stdout, such as a browser.
+///
+/// # Safety
+/// `handle` must be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn minilux_capture_output(handle: *mut MiniluxInterpreter) {
+    if let Some(handle) = handle.as_mut() {
+        let buffer = OutputBuffer::new();
+        handle.interpreter.set_stdout(Box::new(buffer.clone()));
+        handle.output = Some(buffer);
+    }
+}
+
+/// Output captured since the last call, or NULL when capturing is off.
+/// Free it with `minilux_string_free`.
+///
+/// # Safety
+/// `handle` must be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn minilux_take_output(handle: *mut MiniluxInterpreter) -> *mut c_char {
+    let Some(buffer) = handle.as_ref().and_then(|h| h.output.as_ref()) else {
+        return ptr::null_mut();
+    };
+    let text = buffer.take_string().replace('\0', "");
+    CString::new(text).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Free a string returned by `minilux_get_var` or `minilux_take_output`.
+///
+/// # Safety
+/// `text` must come from `minilux_get_var` (or be NULL) and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn minilux_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+/// Allocate `size` bytes in wasm memory, so a JavaScript host can pass strings in.
+#[cfg(target_family = "wasm")]
+#[no_mangle]
+pub extern "C" fn minilux_alloc(size: usize) -> *mut u8 {
+    let mut buffer = Vec::<u8>::with_capacity(size);
+    let pointer = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    pointer
+}
+
+/// Release memory from `minilux_alloc`.
+///
+/// # Safety
+/// `pointer` and `size` must come from one `minilux_alloc` call.
+#[cfg(target_family = "wasm")]
+#[no_mangle]
+pub unsafe extern "C" fn minilux_dealloc(pointer: *mut u8, size: usize) {
+    drop(Vec::from_raw_parts(pointer, 0, size));
+}
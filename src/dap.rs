@@ -0,0 +1,373 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::diagnostics;
+use crate::interpreter::{DebugEvent, DebugHook, Interpreter, SCRIPT_STACK_SIZE};
+use crate::lsp::{read_message, write_message};
+use serde_json::{json, Value as Json};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Single thread id reported to the client
+const THREAD_ID: i64 = 1;
+
+/// How the paused script should resume
+enum Resume {
+    Continue,
+    StepIn,
+    StepOver,
+    StepOut,
+    Stop,
+}
+
+/// Where the script is paused and what it can see
+struct Paused {
+    path: PathBuf,
+    line: usize,
+    column: usize,
+    variables: Vec<(String, String)>,
+}
+
+/// State shared between the protocol loop and the script thread
+struct Shared {
+    seq: AtomicI64,
+    breakpoints: Mutex<HashMap<PathBuf, HashSet<usize>>>,
+    paused: Mutex<Option<Paused>>,
+    pause_requested: AtomicBool,
+}
+
+impl Shared {
+    fn send_event(&self, event: &str, body: Json) {
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+        write_message(&json!({ "seq": seq, "type": "event", "event": event, "body": body })).ok();
+    }
+
+    fn send_response(&self, request: &Json, success: bool, body: Json) {
+        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
+        write_message(&json!({
+            "seq": seq,
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": success,
+            "body": body,
+        }))
+        .ok();
+    }
+}
+
+/// Serve the Debug Adapter Protocol over stdin/stdout.
+pub fn run(modules_spec: Option<&str>) -> Result<(), String> {
+    let shared = Arc::new(Shared {
+        seq: AtomicI64::new(1),
+        breakpoints: Mutex::new(HashMap::new()),
+        paused: Mutex::new(None),
+        pause_requested: AtomicBool::new(false),
+    });
+    let mut program: Option<PathBuf> = None;
+    let mut stop_on_entry = false;
+    let mut resume: Option<Sender<Resume>> = None;
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    while let Some(request) = read_message(&mut input)? {
+        let command = request["command"].as_str().unwrap_or("");
+        let args = &request["arguments"];
+        match command {
+            "initialize" => {
+                shared.send_response(
+                    &request,
+                    true,
+                    json!({ "supportsConfigurationDoneRequest": true }),
+                );
+                shared.send_event("initialized", json!({}));
+            }
+            "launch" => {
+                program = args["program"].as_str().map(absolute_path);
+                stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                let success = program.is_some();
+                shared.send_response(&request, success, json!({}));
+            }
+            "setBreakpoints" => {
+                let path = absolute_path(args["source"]["path"].as_str().unwrap_or(""));
+                let lines: Vec<usize> = args["breakpoints"]
+                    .as_array()
+                    .map(|bps| {
+                        bps.iter()
+                            .filter_map(|bp| bp["line"].as_u64().map(|l| l as usize))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let verified: Vec<Json> = lines
+                    .iter()
+                    .map(|line| json!({ "verified": true, "line": line }))
+                    .collect();
+                if let Ok(mut breakpoints) = shared.breakpoints.lock() {
+                    breakpoints.insert(path, lines.into_iter().collect());
+                }
+                shared.send_response(&request, true, json!({ "breakpoints": verified }));
+            }
+            "configurationDone" => {
+                shared.send_response(&request, true, json!({}));
+                if let Some(path) = program.clone() {
+                    let (sender, receiver) = mpsc::channel();
+                    resume = Some(sender);
+                    spawn_script(
+                        path,
+                        modules_spec.map(String::from),
+                        stop_on_entry,
+                        Arc::clone(&shared),
+                        receiver,
+                    );
+                }
+            }
+            "threads" => {
+                shared.send_response(
+                    &request,
+                    true,
+                    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+                );
+            }
+            "stackTrace" => {
+                let frames: Vec<Json> = match shared.paused.lock().ok().as_deref() {
+                    Some(Some(paused)) => vec![json!({
+                        "id": 0,
+                        "name": "main",
+                        "line": paused.line,
+                        "column": paused.column,
+                        "source": { "path": paused.path.display().to_string() },
+                    })],
+                    _ => Vec::new(),
+                };
+                let total = frames.len();
+                shared.send_response(
+                    &request,
+                    true,
+                    json!({ "stackFrames": frames, "totalFrames": total }),
+                );
+            }
+            "scopes" => {
+                shared.send_response(
+                    &request,
+                    true,
+                    json!({ "scopes": [{ "name": "Variables", "variablesReference": 1, "expensive": false }] }),
+                );
+            }
+            "variables" => {
+                let variables: Vec<Json> = match shared.paused.lock().ok().as_deref() {
+                    Some(Some(paused)) => paused
+                        .variables
+                        .iter()
+                        .map(|(name, value)| {
+                            json!({ "name": format!("${}", name), "value": value, "variablesReference": 0 })
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                shared.send_response(&request, true, json!({ "variables": variables }));
+            }
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                let mode = match command {
+                    "continue" => Resume::Continue,
+                    "next" => Resume::StepOver,
+                    "stepIn" => Resume::StepIn,
+                    _ => Resume::StepOut,
+                };
+                shared.send_response(&request, true, json!({ "allThreadsContinued": true }));
+                if let Some(sender) = &resume {
+                    sender.send(mode).ok();
+                }
+            }
+            "pause" => {
+                shared.pause_requested.store(true, Ordering::SeqCst);
+                shared.send_response(&request, true, json!({}));
+            }
+            "disconnect" | "terminate" => {
+                if let Some(sender) = &resume {
+                    sender.send(Resume::Stop).ok();
+                }
+                shared.send_response(&request, true, json!({}));
+                break;
+            }
+            _ => shared.send_response(&request, false, json!({})),
+        }
+    }
+    Ok(())
+}
+
+fn absolute_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn spawn_script(
+    path: PathBuf,
+    modules_spec: Option<String>,
+    stop_on_entry: bool,
+    shared: Arc<Shared>,
+    resume: Receiver<Resume>,
+) {
+    let events = Arc::clone(&shared);
+    let script = thread::Builder::new().stack_size(SCRIPT_STACK_SIZE);
+    let spawned = script.spawn(move || {
+        let mut interpreter = Interpreter::new();
+        if let Some(spec) = modules_spec.as_deref() {
+            interpreter.set_modules_path(spec);
+        }
+        interpreter.set_stdout(Box::new(OutputEvents {
+            shared: Arc::clone(&shared),
+            category: "stdout",
+        }));
+        interpreter.set_stderr(Box::new(OutputEvents {
+            shared: Arc::clone(&shared),
+            category: "stderr",
+        }));
+        interpreter.set_debug_hook(Box::new(Stepper {
+            shared: Arc::clone(&shared),
+            resume,
+            main_path: path.clone(),
+            mode: if stop_on_entry { Step::Entry } else { Step::Run },
+        }));
+
+        let result = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read file: {}", e))
+            .and_then(|content| {
+                if let Some(dir) = path.parent() {
+                    interpreter.push_base_dir(dir.to_path_buf());
+                }
+                interpreter.execute_source(&content)
+            });
+
+        let exit_code = match result {
+            Ok(()) => 0,
+            Err(e) => {
+                let trace = interpreter.take_error_trace();
+                let location = match interpreter.take_error_location() {
+                    Some((file, span)) => {
+                        format!(" ({}:{})", file.as_ref().unwrap_or(&path).display(), span.line)
+                    }
+                    None => String::new(),
+                };
+                let trace = diagnostics::render_trace(&trace, &path.display().to_string(), false);
+                shared.send_event(
+                    "output",
+                    json!({ "category": "stderr", "output": format!("Error: {}{}{}\n", e, location, trace) }),
+                );
+                1
+            }
+        };
+        shared.send_event("exited", json!({ "exitCode": exit_code }));
+        shared.send_event("terminated", json!({}));
+    });
+    if let Err(e) = spawned {
+        events.send_event(
+            "output",
+            json!({ "category": "stderr", "output": format!("Error: failed to start script: {}\n", e) }),
+        );
+        events.send_event("terminated", json!({}));
+    }
+}
+
+/// Forwards script output to the client as `output` events
+struct OutputEvents {
+    shared: Arc<Shared>,
+    /// DAP output category: "stdout" or "stderr"
+    category: &'static str,
+}
+
+impl Write for OutputEvents {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.shared.send_event(
+            "output",
+            json!({ "category": self.category, "output": String::from_utf8_lossy(buf) }),
+        );
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Step {
+    Run,
+    Entry,
+    In,
+    Over(usize),
+    Out(usize),
+}
+
+/// Debug hook that pauses on breakpoints and steps, then waits for the client
+struct Stepper {
+    shared: Arc<Shared>,
+    resume: Receiver<Resume>,
+    main_path: PathBuf,
+    mode: Step,
+}
+
+impl DebugHook for Stepper {
+    fn before_statement(&mut self, event: &DebugEvent) -> Result<(), String> {
+        let path = event.file.unwrap_or(&self.main_path);
+        let line = event.span.line;
+
+        let reason = if self.shared.pause_requested.swap(false, Ordering::SeqCst) {
+            Some("pause")
+        } else {
+            match self.mode {
+                Step::Entry => Some("entry"),
+                Step::In => Some("step"),
+                Step::Over(depth) if event.depth <= depth => Some("step"),
+                Step::Out(depth) if event.depth < depth => Some("step"),
+                _ => None,
+            }
+        };
+        let reason = reason.or_else(|| {
+            let breakpoints = self.shared.breakpoints.lock().ok()?;
+            breakpoints
+                .get(path)
+                .filter(|lines| lines.contains(&line))
+                .map(|_| "breakpoint")
+        });
+        let reason = match reason {
+            Some(reason) => reason,
+            None => return Ok(()),
+        };
+
+        let mut variables: Vec<(String, String)> = event
+            .variables
+            .iter()
+            .map(|(name, value)| (name.clone(), value.to_string()))
+            .collect();
+        variables.sort();
+        if let Ok(mut paused) = self.shared.paused.lock() {
+            *paused = Some(Paused {
+                path: path.to_path_buf(),
+                line,
+                column: event.span.column,
+                variables,
+            });
+        }
+        self.shared.send_event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        );
+
+        self.mode = match self.resume.recv() {
+            Ok(Resume::Continue) => Step::Run,
+            Ok(Resume::StepIn) => Step::In,
+            Ok(Resume::StepOver) => Step::Over(event.depth),
+            Ok(Resume::StepOut) => Step::Out(event.depth),
+            Ok(Resume::Stop) | Err(_) => return Err("Debugger disconnected".to_string()),
+        };
+        Ok(())
+    }
+}
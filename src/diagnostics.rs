@@ -0,0 +1,150 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::interpreter::TraceFrame;
+use crate::lexer::Span;
+use crate::parser::ParseError;
+use std::env;
+use std::io::{self, IsTerminal};
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether diagnostics on stderr should use ANSI colors.
+/// Honors `--no-color` and the NO_COLOR convention (https://no-color.org).
+pub fn use_color(no_color_flag: bool) -> bool {
+    !no_color_flag
+        && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        && io::stderr().is_terminal()
+}
+
+/// Source text an error points into
+pub struct Location<'a> {
+    pub name: &'a str,
+    pub source: &'a str,
+    pub span: Span,
+}
+
+/// Render an error message, followed by the offending source line with a
+/// caret under the span when a location is known.
+pub fn render_error(message: &str, location: Option<Location>, color: bool) -> String {
+    let paint = |style: &str, text: &str| {
+        if color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    };
+
+    let mut out = format!("{}{}", paint(RED, "error"), paint(BOLD, &format!(": {}", message)));
+
+    let location = match location {
+        Some(location) => location,
+        None => return out,
+    };
+    let span = location.span;
+    out.push_str(&format!(
+        "\n  {} {}:{}:{}",
+        paint(BLUE, "-->"),
+        location.name,
+        span.line,
+        span.column
+    ));
+
+    let line_text = match location.source.lines().nth(span.line.saturating_sub(1)) {
+        Some(text) => text.trim_end(),
+        None => return out,
+    };
+    let width = span.line.to_string().len();
+    let gutter = " ".repeat(width);
+    let line_len = line_text.chars().count();
+    let start = span.column.max(1).min(line_len + 1);
+    let end = if span.end_line == span.line {
+        span.end_column.max(start + 1).min(line_len + 1)
+    } else {
+        line_len + 1
+    };
+    let carets = "^".repeat((end - start).max(1));
+    // Keep tabs so the caret lines up with the source line.
+    let padding: String = line_text
+        .chars()
+        .take(start - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    out.push_str(&format!("\n{} {}", gutter, paint(BLUE, "|")));
+    out.push_str(&format!(
+        "\n{} {} {}",
+        paint(BLUE, &span.line.to_string()),
+        paint(BLUE, "|"),
+        line_text
+    ));
+    out.push_str(&format!(
+        "\n{} {} {}{}",
+        gutter,
+        paint(BLUE, "|"),
+        padding,
+        paint(RED, &carets)
+    ));
+    out
+}
+
+/// Render every syntax error found in `source`, one after another.
+pub fn render_syntax_errors(errors: &[ParseError], name: &str, source: &str, color: bool) -> String {
+    errors
+        .iter()
+        .map(|e| render_error(&e.message, Some(Location { name, source, span: e.span }), color))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Render the calls and includes an error happened in, innermost first, as
+/// `note:` lines. `main_name` names the main script.
+pub fn render_trace(trace: &[TraceFrame], main_name: &str, color: bool) -> String {
+    let mut out = String::new();
+    // Deep recursion repeats one call site many times; show it once with a count.
+    let mut last: Option<String> = None;
+    let mut repeats = 0;
+    for frame in trace.iter().rev() {
+        // Functions called by the host (such as test_* functions) have no call site.
+        if frame.span.line == 0 {
+            continue;
+        }
+        let caller = match &frame.caller {
+            Some(file) => file.display().to_string(),
+            None => main_name.to_string(),
+        };
+        let entered = match (&frame.function, &frame.file) {
+            (Some(function), _) => format!("in function '{}', called", function),
+            (None, Some(file)) => format!("in {}, included", file.display()),
+            (None, None) => "included".to_string(),
+        };
+        let note = format!("{} from {}:{}:{}", entered, caller, frame.span.line, frame.span.column);
+        if last.as_ref() == Some(&note) {
+            repeats += 1;
+            continue;
+        }
+        push_repeats(&mut out, repeats, color);
+        repeats = 0;
+        push_note(&mut out, &note, color);
+        last = Some(note);
+    }
+    push_repeats(&mut out, repeats, color);
+    out
+}
+
+fn push_note(out: &mut String, note: &str, color: bool) {
+    let equals = if color { format!("{}={}", BLUE, RESET) } else { "=".to_string() };
+    out.push_str(&format!("\n  {} note: {}", equals, note));
+}
+
+fn push_repeats(out: &mut String, repeats: usize, color: bool) {
+    if repeats > 0 {
+        push_note(out, &format!("(previous note repeated {} more times)", repeats), color);
+    }
+}
@@ -0,0 +1,143 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::parser::{Parser, Statement};
+
+/// Documentation extracted from one script
+#[derive(Debug, Clone)]
+pub struct ModuleDoc {
+    pub name: String,
+    pub summary: Vec<String>,
+    pub functions: Vec<FunctionDoc>,
+}
+
+/// A documented (or undocumented) top-level function
+#[derive(Debug, Clone)]
+pub struct FunctionDoc {
+    pub name: String,
+    pub params: Vec<String>,
+    pub doc: Vec<String>,
+}
+
+impl FunctionDoc {
+    fn signature(&self) -> String {
+        let params: Vec<String> = self.params.iter().map(|p| format!("${}", p)).collect();
+        format!("{}({})", self.name, params.join(", "))
+    }
+}
+
+/// Collect `##` doc comments from a script. A `##` block directly above a
+/// `function` documents it; a `##` block at the top of the file followed by
+/// a blank line, before any code, describes the module itself.
+pub fn document_source(name: &str, source: &str) -> ModuleDoc {
+    let lines: Vec<&str> = source.lines().collect();
+
+    // Skip the shebang, blank lines and plain `#` header comments.
+    let mut summary = Vec::new();
+    let first = lines
+        .iter()
+        .position(|l| {
+            let l = l.trim();
+            !l.is_empty() && (!l.starts_with('#') || l.starts_with("##"))
+        })
+        .unwrap_or(lines.len());
+    let mut end = first;
+    while end < lines.len() && doc_line(lines[end]).is_some() {
+        end += 1;
+    }
+    if end > first && lines.get(end).is_none_or(|l| l.trim().is_empty()) {
+        summary = lines[first..end].iter().filter_map(|l| doc_line(l)).collect();
+    }
+
+    let functions = Parser::new(source)
+        .parse()
+        .into_iter()
+        .filter_map(|stmt| match stmt.kind {
+            Statement::FunctionDef { name, params, .. } => {
+                let mut start = stmt.span.line.saturating_sub(1);
+                while start > 0 && doc_line(lines[start - 1]).is_some() {
+                    start -= 1;
+                }
+                let doc = lines[start..stmt.span.line.saturating_sub(1)]
+                    .iter()
+                    .filter_map(|l| doc_line(l))
+                    .collect();
+                Some(FunctionDoc { name, params, doc })
+            }
+            _ => None,
+        })
+        .collect();
+
+    ModuleDoc {
+        name: name.to_string(),
+        summary,
+        functions,
+    }
+}
+
+fn doc_line(line: &str) -> Option<String> {
+    let text = line.trim_start().strip_prefix("##")?;
+    Some(text.strip_prefix(' ').unwrap_or(text).trim_end().to_string())
+}
+
+/// Render modules as a Markdown document.
+pub fn render_markdown(modules: &[ModuleDoc]) -> String {
+    let mut out = String::new();
+    for module in modules {
+        out.push_str(&format!("# {}\n\n", module.name));
+        push_paragraphs(&mut out, &module.summary);
+        for function in &module.functions {
+            out.push_str(&format!("## `{}`\n\n", function.signature()));
+            push_paragraphs(&mut out, &function.doc);
+        }
+    }
+    out
+}
+
+fn push_paragraphs(out: &mut String, doc: &[String]) {
+    if doc.is_empty() {
+        return;
+    }
+    out.push_str(&doc.join("\n"));
+    out.push_str("\n\n");
+}
+
+/// Render modules as a standalone HTML page.
+pub fn render_html(modules: &[ModuleDoc]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Minilux API</title>\n</head>\n<body>\n",
+    );
+    for module in modules {
+        out.push_str(&format!("<h1>{}</h1>\n", escape_html(&module.name)));
+        push_html_paragraphs(&mut out, &module.summary);
+        for function in &module.functions {
+            out.push_str(&format!(
+                "<h2><code>{}</code></h2>\n",
+                escape_html(&function.signature())
+            ));
+            push_html_paragraphs(&mut out, &function.doc);
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Blank doc lines separate paragraphs, as in Markdown.
+fn push_html_paragraphs(out: &mut String, doc: &[String]) {
+    for paragraph in doc.split(|line| line.is_empty()) {
+        if paragraph.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("<p>{}</p>\n", escape_html(&paragraph.join("\n"))));
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,224 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::lexer::{Lexer, Token};
+
+const INDENT: &str = "    ";
+
+/// Re-print a script with canonical indentation and spacing.
+///
+/// Works on the comment-preserving token stream rather than the AST so that
+/// comments, blank lines and the original spelling of every token survive.
+pub fn format_source(source: &str) -> Result<String, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut line_starts = vec![0];
+    for (i, ch) in chars.iter().enumerate() {
+        if *ch == '\n' {
+            line_starts.push(i + 1);
+        }
+    }
+    let offset = |line: usize, column: usize| -> usize {
+        line_starts
+            .get(line - 1)
+            .map(|start| start + column - 1)
+            .unwrap_or(chars.len())
+            .min(chars.len())
+    };
+
+    let mut lines: Vec<Vec<(Token, String)>> = vec![Vec::new()];
+    let mut prev_end = 0;
+    for (token, span) in Lexer::with_comments(source).tokenize_with_spans() {
+        let start = offset(span.line, span.column);
+        let end = offset(span.end_line, span.end_column);
+
+        // The lexer silently drops characters it does not understand; refuse
+        // to format rather than deleting them from the user's file.
+        if let Some(ch) = chars[prev_end..start].iter().find(|c| !c.is_whitespace()) {
+            return Err(format!(
+                "unrecognized character '{}' before line {}, column {}",
+                ch, span.line, span.column
+            ));
+        }
+        prev_end = end;
+
+        match token {
+            Token::Eof => break,
+            Token::Error(message) => {
+                return Err(format!("{} at line {}, column {}", message, span.line, span.column))
+            }
+            Token::Newline => lines.push(Vec::new()),
+            _ => {
+                let text: String = chars[start..end].iter().collect();
+                let text = text.trim_end().to_string();
+                if let Some(line) = lines.last_mut() {
+                    line.push((token, text));
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let mut depth: usize = 0;
+    let mut blank_run = false;
+    let mut started = false;
+    // For each open bracket, whether it is a '[' (where ':' separates slice bounds)
+    let mut squares: Vec<bool> = Vec::new();
+
+    for line in lines {
+        if line.is_empty() {
+            blank_run = started;
+            continue;
+        }
+        if blank_run {
+            out.push('\n');
+            blank_run = false;
+        }
+        started = true;
+
+        let leading_closers = line.iter().take_while(|(t, _)| is_closer(t)).count();
+        out.push_str(&INDENT.repeat(depth.saturating_sub(leading_closers)));
+
+        let mut prev: Option<&Token> = None;
+        let mut prev_unary = false;
+        for (token, text) in &line {
+            if let Some(p) = prev {
+                let slice_colon = *p == Token::Colon && squares.last() == Some(&true);
+                if needs_space(p, prev_unary, token) && !slice_colon {
+                    out.push(' ');
+                }
+            }
+            out.push_str(text);
+
+            prev_unary = match token {
+                Token::Not => true,
+                Token::Minus => !prev.is_some_and(can_end_expr),
+                _ => false,
+            };
+            if is_opener(token) {
+                depth += 1;
+                squares.push(matches!(token, Token::LeftBracket | Token::QuestionBracket));
+            } else if is_closer(token) {
+                depth = depth.saturating_sub(1);
+                squares.pop();
+            }
+            prev = Some(token);
+        }
+        out.push('\n');
+    }
+
+    if significant_tokens(source) != significant_tokens(&out) {
+        return Err("formatting would change the meaning of the program".to_string());
+    }
+
+    Ok(out)
+}
+
+fn significant_tokens(source: &str) -> Vec<Token> {
+    Lexer::new(source)
+        .tokenize()
+        .into_iter()
+        .filter(|t| *t != Token::Newline)
+        .collect()
+}
+
+fn is_opener(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::LeftBrace | Token::LeftParen | Token::LeftBracket | Token::QuestionBracket
+    )
+}
+
+fn is_closer(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::RightBrace | Token::RightParen | Token::RightBracket
+    )
+}
+
+fn can_end_expr(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Int(_)
+            | Token::Float(_)
+            | Token::True
+            | Token::False
+            | Token::String(_)
+            | Token::Regex { .. }
+            | Token::Subst { .. }
+            | Token::Variable(_)
+            | Token::RightParen
+            | Token::RightBracket
+    )
+}
+
+/// Keywords that are written like function calls, e.g. `printf(...)`
+fn is_callable_keyword(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Printf
+            | Token::Shell
+            | Token::Len
+            | Token::Sleep
+            | Token::Array
+            | Token::Push
+            | Token::Pop
+            | Token::Shift
+            | Token::Unshift
+            | Token::Sockopen
+            | Token::Sockclose
+            | Token::Sockwrite
+            | Token::Sockread
+            | Token::Sockstatus
+            | Token::Serialopen
+            | Token::Serialclose
+            | Token::Serialwrite
+            | Token::Serialread
+            | Token::Read
+            | Token::Lower
+            | Token::Upper
+            | Token::Number
+    )
+}
+
+fn needs_space(prev: &Token, prev_unary: bool, token: &Token) -> bool {
+    if prev_unary {
+        return false;
+    }
+    if matches!(
+        token,
+        Token::RightParen
+            | Token::RightBracket
+            | Token::Comma
+            | Token::Colon
+            | Token::Semicolon
+            | Token::Dot
+            | Token::DotDot
+            | Token::DotDotLess
+            | Token::QuestionBracket
+    ) {
+        return false;
+    }
+    if matches!(
+        prev,
+        Token::LeftParen
+            | Token::LeftBracket
+            | Token::QuestionBracket
+            | Token::At
+            | Token::Dot
+            | Token::DotDot
+            | Token::DotDotLess
+    ) {
+        return false;
+    }
+    match token {
+        Token::LeftParen => !(matches!(
+            prev,
+            Token::Variable(_) | Token::Subst { .. } | Token::RightParen | Token::RightBracket
+        ) || is_callable_keyword(prev)),
+        Token::LeftBracket => !can_end_expr(prev),
+        _ => true,
+    }
+}
@@ -0,0 +1,189 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! I/O the runtime performs on behalf of scripts. The defaults talk to the
+//! operating system; hosts without one (a browser playground, another wasm
+//! host) or applications embedding Minilux can plug in their own.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Where `read` gets its lines from
+pub trait Input {
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize>;
+
+    /// Whether this is an interactive terminal that `readpass()` and
+    /// `getch()` can read from directly
+    fn is_terminal(&self) -> bool {
+        false
+    }
+
+    /// Read one character, None at end of input. Used by `getch()` when
+    /// this is not a terminal; the default consumes a whole line.
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        let mut line = String::new();
+        self.read_line(&mut line)?;
+        Ok(line.chars().next())
+    }
+}
+
+impl<T: BufRead> Input for T {
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        BufRead::read_line(self, buf)
+    }
+
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        read_utf8_char(self)
+    }
+}
+
+/// Read one UTF-8 encoded character without consuming anything after it.
+fn read_utf8_char(reader: &mut impl BufRead) -> io::Result<Option<char>> {
+    let mut bytes = [0u8; 4];
+    if reader.read(&mut bytes[..1])? == 0 {
+        return Ok(None);
+    }
+    let len = match bytes[0] {
+        0xf0.. => 4,
+        0xe0.. => 3,
+        0xc0.. => 2,
+        _ => 1,
+    };
+    let mut read = 1;
+    while read < len {
+        match reader.read(&mut bytes[read..len])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(String::from_utf8_lossy(&bytes[..read]).chars().next())
+}
+
+/// The process stdin. Unlike a `BufReader`, it does not hold on to input
+/// past the line it returns, so other readers (the REPL) see the rest.
+pub struct OsStdin;
+
+impl Input for OsStdin {
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        io::stdin().read_line(buf)
+    }
+
+    fn is_terminal(&self) -> bool {
+        io::stdin().is_terminal()
+    }
+
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        read_utf8_char(&mut io::stdin().lock())
+    }
+}
+
+/// Files read by `include`, and by and for scripts through `read_file()`,
+/// `write_file()` and the like
+pub trait FileSystem {
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Replace the contents of `path` with `data`, or add `data` to its end
+    /// with `append`, creating it if need be. Read-only by default.
+    fn write(&self, path: &Path, _data: &[u8], _append: bool) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("files cannot be written here ({})", path.display()),
+        ))
+    }
+}
+
+/// Connections opened by `sockopen`, and serial devices opened by
+/// `serialopen`, which scripts use the same way
+pub trait Network {
+    fn connect(&mut self, addr: &str) -> io::Result<Box<dyn Socket>>;
+
+    fn open_serial(&mut self, device: &str, _baud: u32) -> io::Result<Box<dyn Socket>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("serial ports are not available here ({})", device),
+        ))
+    }
+}
+
+/// An open connection or serial port
+pub trait Socket: Read + Write {}
+
+impl<T: Read + Write> Socket for T {}
+
+/// The real filesystem
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn write(&self, path: &Path, data: &[u8], append: bool) -> io::Result<()> {
+        fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?
+            .write_all(data)
+    }
+}
+
+/// TCP and serial ports through the operating system
+pub struct OsNetwork;
+
+impl Network for OsNetwork {
+    fn connect(&mut self, addr: &str) -> io::Result<Box<dyn Socket>> {
+        Ok(Box::new(TcpStream::connect(addr)?))
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    fn open_serial(&mut self, device: &str, baud: u32) -> io::Result<Box<dyn Socket>> {
+        // Reads give up after a second so scripts polling a quiet device don't hang.
+        let port = serialport::new(device, baud)
+            .timeout(std::time::Duration::from_secs(1))
+            .open()?;
+        Ok(Box::new(port))
+    }
+}
+
+/// In-memory sink for script output. Clones share the same buffer, so a host
+/// can hand one to `Interpreter::set_stdout` (or `set_stderr`) and read what
+/// the script wrote from the other.
+#[derive(Clone, Default)]
+pub struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written since the last call, as text
+    pub fn take_string(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.borrow_mut());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -4,17 +4,215 @@
 // License: MPL 2.0
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::parser::{BinOp, Expr, Statement, UnaryOp};
-use crate::runtime::Runtime;
-use crate::value::Value;
+use crate::host::{FileSystem, Input, Network};
+use crate::lexer::Span;
+use crate::log::{Level, Logger};
+use crate::parser::{syntax_error_message, BinOp, Expr, Parser, Statement, Stmt, UnaryOp};
+use crate::printf;
+use crate::process;
+use crate::remote;
+use crate::runtime::{Function, Runtime};
+use crate::value::{Callable, Overflow, Value};
+use num_bigint::{BigInt, Sign};
+use num_traits::{FromPrimitive, Pow, Signed, ToPrimitive};
 use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
 use std::env;
 use std::fs;
-use std::io::{self, Read, Write};
-use std::net::TcpStream;
+use std::io::{IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::atomic::{self, AtomicBool};
+
+/// The most items a range such as `1..$n`, or assigning past the end of an
+/// array, may produce
+const MAX_ARRAY_LEN: i64 = 10_000_000;
+
+/// The most compiled regexes kept; the cache starts over when it is full
+const MAX_CACHED_REGEXES: usize = 256;
+
+/// The most bits an exact integer power such as `2 ** 100` may have
+const MAX_POWER_BITS: u64 = 1 << 24;
+
+/// Functions handled by the interpreter itself rather than user code
+pub const BUILTIN_FUNCTIONS: &[&str] = &[
+    "len",
+    "strlen",
+    "bytelen",
+    "grapheme_len",
+    "graphemes",
+    "shell",
+    "shell_result",
+    "run",
+    "spawn",
+    "proc_readline",
+    "proc_write",
+    "proc_close",
+    "proc_wait",
+    "proc_kill",
+    "number",
+    "bigint",
+    "regmatch",
+    "match_all",
+    "gsub",
+    "lower",
+    "upper",
+    "casefold",
+    "nfc",
+    "nfd",
+    "substr",
+    "trim",
+    "ltrim",
+    "rtrim",
+    "replace",
+    "starts_with",
+    "ends_with",
+    "repeat",
+    "pad_left",
+    "pad_right",
+    "ord",
+    "chr",
+    "chars",
+    "format_number",
+    "sprintf",
+    "pow",
+    "sqrt",
+    "abs",
+    "min",
+    "max",
+    "sum",
+    "avg",
+    "sleep",
+    "exit",
+    "read_file",
+    "write_file",
+    "append_file",
+    "file_exists",
+    "env",
+    "setenv",
+    "environ",
+    "is_nil",
+    "copy",
+    "keys",
+    "values",
+    "has_key",
+    "map",
+    "filter",
+    "reduce",
+    "sort",
+    "sort_by",
+    "reverse",
+    "unique",
+    "contains",
+    "index_of",
+    "slice",
+    "concat",
+    "flatten",
+    "split",
+    "resplit",
+    "join",
+    "pop",
+    "shift",
+    "insert_at",
+    "remove_at",
+    "swap",
+    "assert_eq",
+    "assert_match",
+    "fail",
+    "loadlib",
+    "prompt",
+    "readpass",
+    "getch",
+    "read_all_stdin",
+    "is_tty",
+    "term_size",
+    "is_color",
+    "color",
+    "style",
+    "cursor_to",
+    "clear_screen",
+    "hide_cursor",
+    "show_cursor",
+    "redraw",
+    "log_init",
+    "log_debug",
+    "log_info",
+    "log_warn",
+    "log_error",
+];
+
+/// Nested user function calls allowed before a script is stopped
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// Stack size for threads that run scripts, enough for
+/// DEFAULT_MAX_CALL_DEPTH nested calls even in unoptimized builds
+pub const SCRIPT_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Error a script stops with after an interrupt
+pub const INTERRUPTED_MESSAGE: &str = "Interrupted";
+
+/// Set by Ctrl-C; running code stops before its next statement
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Stop running code before its next statement, as Ctrl-C does. Safe to
+/// call from a signal handler. Returns whether an interrupt was already
+/// pending, so a second Ctrl-C can end a script stuck waiting.
+pub fn interrupt() -> bool {
+    INTERRUPTED.swap(true, atomic::Ordering::SeqCst)
+}
+
+/// Whether an interrupt is pending. `try` does not catch one.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(atomic::Ordering::SeqCst)
+}
+
+/// Let code run again once an interrupt has been reported
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, atomic::Ordering::SeqCst);
+}
+
+/// Fails once an interrupt is pending
+fn check_interrupt() -> Result<(), String> {
+    if interrupted() {
+        Err(INTERRUPTED_MESSAGE.to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Function provided by the host application or a native plugin
+pub type HostFunction = Box<dyn FnMut(&[Value]) -> Result<Value, String>>;
+
+/// What a debugger sees right before a statement runs
+pub struct DebugEvent<'a> {
+    /// Included file being executed, None for the main script
+    pub file: Option<&'a Path>,
+    pub span: Span,
+    /// Number of user function calls currently active
+    pub depth: usize,
+    /// Globals plus the locals of the current function call
+    pub variables: &'a HashMap<String, Value>,
+}
+
+/// An active function call or include, recorded where it was entered from
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    /// Function called, None for an include
+    pub function: Option<String>,
+    /// File entered: the function's file or the included file (None for the main script)
+    pub file: Option<PathBuf>,
+    /// File of the calling statement, None for the main script
+    pub caller: Option<PathBuf>,
+    /// Span of the calling statement
+    pub span: Span,
+}
+
+/// Hook invoked before every statement; returning an error aborts the script
+pub trait DebugHook {
+    fn before_statement(&mut self, event: &DebugEvent) -> Result<(), String>;
+}
 
 pub struct Interpreter {
     runtime: Runtime,
@@ -22,6 +220,40 @@ pub struct Interpreter {
     base_dirs: Vec<PathBuf>,
     modules_paths: Vec<PathBuf>,
     include_in_progress: HashSet<PathBuf>,
+    included_files: Vec<PathBuf>,
+    /// Parsed includes by canonical path, so a module included from several
+    /// places is read and parsed once per run
+    parse_cache: HashMap<PathBuf, Rc<Vec<Stmt>>>,
+    /// Compiled regexes by pattern, flags included, so a pattern used in a
+    /// loop is compiled once
+    regex_cache: HashMap<String, Regex>,
+    remote_includes: bool,
+    current_file: Option<PathBuf>,
+    current_span: Span,
+    frames: Vec<TraceFrame>,
+    error_location: Option<(Option<PathBuf>, Span)>,
+    error_trace: Vec<TraceFrame>,
+    /// Value of the last `throw` and the error message it became
+    thrown: Option<(String, Value)>,
+    /// Status given to `exit()`, while its error unwinds the script
+    exit_status: Option<i32>,
+    debug_hook: Option<Box<dyn DebugHook>>,
+    call_depth: usize,
+    max_call_depth: usize,
+    strict: bool,
+    strict_index: bool,
+    overflow: Overflow,
+    host_functions: HashMap<String, HostFunction>,
+    logger: Logger,
+    // Declared after host_functions so plugin code outlives the closures calling it.
+    #[cfg(not(target_family = "wasm"))]
+    plugins: Vec<libloading::Library>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
@@ -37,6 +269,27 @@ impl Interpreter {
             base_dirs: vec![env::current_dir().unwrap_or_else(|_| PathBuf::from("."))],
             modules_paths,
             include_in_progress: HashSet::new(),
+            included_files: Vec::new(),
+            parse_cache: HashMap::new(),
+            regex_cache: HashMap::new(),
+            remote_includes: false,
+            current_file: None,
+            current_span: Span::default(),
+            frames: Vec::new(),
+            error_location: None,
+            error_trace: Vec::new(),
+            thrown: None,
+            exit_status: None,
+            debug_hook: None,
+            call_depth: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            strict: false,
+            strict_index: false,
+            overflow: Overflow::Error,
+            host_functions: HashMap::new(),
+            logger: Logger::default(),
+            #[cfg(not(target_family = "wasm"))]
+            plugins: Vec::new(),
         }
     }
 
@@ -54,10 +307,127 @@ impl Interpreter {
         }
     }
 
+    /// Every file the script tried to include so far (resolved paths)
+    pub fn included_files(&self) -> &[PathBuf] {
+        &self.included_files
+    }
+
+    /// Allow `include` of http(s) URLs (off by default)
+    pub fn set_remote_includes(&mut self, allow: bool) {
+        self.remote_includes = allow;
+    }
+
+    /// Limit how deeply user functions may call each other; exceeding it is a
+    /// script error. Limits above the default may need a larger thread stack.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Make reading or writing past the end of an array or string an error
+    /// instead of giving nil or dropping the write.
+    pub fn set_strict_index(&mut self, strict_index: bool) {
+        self.strict_index = strict_index;
+    }
+
+    /// Make reads of undefined variables and calls to undefined functions
+    /// errors instead of warnings, as the `strict` statement does.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Choose what integer overflow does, as the `overflow` statement does.
+    /// By default it is an error.
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    pub fn set_debug_hook(&mut self, hook: Box<dyn DebugHook>) {
+        self.debug_hook = Some(hook);
+    }
+
+    /// Send script output somewhere other than the process stdout
+    pub fn set_stdout(&mut self, out: Box<dyn Write>) {
+        self.runtime.set_stdout(out);
+    }
+
+    /// Send warnings and log records somewhere other than the process stderr
+    pub fn set_stderr(&mut self, err: Box<dyn Write>) {
+        self.runtime.set_stderr(err);
+    }
+
+    /// Read `read` input from somewhere other than the process stdin
+    pub fn set_stdin(&mut self, input: Box<dyn Input>) {
+        self.runtime.set_stdin(input);
+    }
+
+    /// Serve `include` from something other than the real filesystem
+    pub fn set_file_system(&mut self, file_system: Box<dyn FileSystem>) {
+        self.runtime.set_file_system(file_system);
+    }
+
+    /// Open `sockopen` connections through something other than OS TCP
+    pub fn set_network(&mut self, network: Box<dyn Network>) {
+        self.runtime.set_network(network);
+    }
+
+    /// Value of a global variable (nil when unset)
+    pub fn get_var(&self, name: &str) -> Value {
+        self.runtime.get_var(name)
+    }
+
+    pub fn set_var(&mut self, name: &str, value: Value) {
+        self.runtime.set_var(name.to_string(), value);
+    }
+
+    /// Names of the variables visible outside any function
+    pub fn variable_names(&self) -> Vec<String> {
+        self.runtime.visible_variables().into_keys().collect()
+    }
+
+    /// Names of the functions the script or the host defined
+    pub fn function_names(&self) -> Vec<String> {
+        let mut names = self.runtime.function_names();
+        names.extend(self.host_functions.keys().cloned());
+        names
+    }
+
+    /// Make `name` callable from scripts. Host functions take precedence
+    /// over user-defined functions, like builtins do.
+    pub fn register_function(&mut self, name: &str, function: HostFunction) {
+        self.host_functions.insert(name.to_string(), function);
+    }
+
+    /// Where the last runtime error happened: the included file it came from
+    /// (None for the main script) and the span of the failing statement
+    pub fn take_error_location(&mut self) -> Option<(Option<PathBuf>, Span)> {
+        self.error_location.take()
+    }
+
+    /// The status the script passed to `exit()`, if the error it returned
+    /// came from that rather than from a failure
+    pub fn take_exit_status(&mut self) -> Option<i32> {
+        self.exit_status.take()
+    }
+
+    /// Calls and includes that were active when the last runtime error
+    /// happened, outermost first
+    pub fn take_error_trace(&mut self) -> Vec<TraceFrame> {
+        std::mem::take(&mut self.error_trace)
+    }
+
     fn current_base_dir(&self) -> Option<&PathBuf> {
         self.base_dirs.last()
     }
 
+    /// Where a file a script reads or writes is: a relative path starts from
+    /// the directory of the script or module running, as for `include`
+    fn resolve_data_path(&self, path: &str) -> PathBuf {
+        match self.current_base_dir() {
+            Some(base) => base.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+
         fn parse_modules_path_list(spec: &str) -> Vec<PathBuf> {
         // Supports multiple paths separated by ':' (Unix) or ';' (Windows).
         let normalized = spec.replace(';', ":");
@@ -74,7 +444,12 @@ impl Interpreter {
         self.modules_paths = Self::parse_modules_path_list(spec);
     }
 
-fn resolve_include_path(&self, path: &str) -> PathBuf {
+    /// Search `dir` for includes before the other module paths.
+    pub fn add_modules_dir(&mut self, dir: PathBuf) {
+        self.modules_paths.insert(0, dir);
+    }
+
+pub fn resolve_include_path(&self, path: &str) -> PathBuf {
         let specified = Path::new(path);
         if specified.is_absolute() {
             return specified.to_path_buf();
@@ -82,14 +457,22 @@ fn resolve_include_path(&self, path: &str) -> PathBuf {
 
         if let Some(base) = self.current_base_dir() {
             let candidate = base.join(specified);
-            if candidate.exists() {
+            if self.runtime.file_system().exists(&candidate) {
                 return candidate;
             }
         }
 
         for base in &self.modules_paths {
             let candidate = base.join(specified);
-            if candidate.exists() {
+            if self.runtime.file_system().exists(&candidate) {
+                return candidate;
+            }
+        }
+
+        // Packages installed with `minilux pkg install`
+        if let Some(base) = crate::pkg::user_modules_dir() {
+            let candidate = base.join(specified);
+            if self.runtime.file_system().exists(&candidate) {
                 return candidate;
             }
         }
@@ -97,7 +480,7 @@ fn resolve_include_path(&self, path: &str) -> PathBuf {
         match env::current_dir() {
             Ok(cwd) => {
                 let candidate = cwd.join(specified);
-                if candidate.exists() {
+                if self.runtime.file_system().exists(&candidate) {
                     return candidate;
                 }
                 candidate
@@ -106,30 +489,135 @@ fn resolve_include_path(&self, path: &str) -> PathBuf {
         }
     }
 
-    pub fn execute(&mut self, statements: Vec<Statement>) -> Result<(), String> {
+    pub fn execute(&mut self, statements: Vec<Stmt>) -> Result<(), String> {
+        // An exit() from an earlier run that nobody took is over.
+        self.exit_status = None;
+        self.execute_block(&statements)
+    }
+
+    /// Parse and run source text. Nothing runs if it has syntax errors; the
+    /// first one becomes the error location.
+    pub fn execute_source(&mut self, source: &str) -> Result<(), String> {
+        match Parser::new(source).parse_checked() {
+            Ok(statements) => self.execute(statements),
+            Err(errors) => {
+                self.error_location = Some((self.current_file.clone(), errors[0].span));
+                self.error_trace.clear();
+                Err(syntax_error_message(&errors))
+            }
+        }
+    }
+
+    /// Parse and evaluate source text holding a single expression
+    pub fn eval_source(&mut self, source: &str) -> Result<Value, String> {
+        match Parser::new(source).parse_expression() {
+            Ok((expr, span)) => {
+                self.exit_status = None;
+                let result = self.eval_expr(&expr);
+                if result.is_err() && self.error_location.is_none() {
+                    self.error_location = Some((self.current_file.clone(), span));
+                    self.error_trace = self.frames.clone();
+                }
+                result
+            }
+            Err(errors) => {
+                self.error_location = Some((self.current_file.clone(), errors[0].span));
+                self.error_trace.clear();
+                Err(syntax_error_message(&errors))
+            }
+        }
+    }
+
+    /// Run the top-level statements of a script or module. Its functions are
+    /// defined up front, so they can be called before the line defining them.
+    fn execute_block(&mut self, statements: &[Stmt]) -> Result<(), String> {
         for stmt in statements {
-            self.execute_statement(&stmt)?;
+            if let Statement::FunctionDef { name, params, body } = &stmt.kind {
+                self.define_function(name, params, body);
+            }
+        }
+        for stmt in statements {
+            self.execute_statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn execute_statement(&mut self, stmt: &Stmt) -> Result<Option<Value>, String> {
+        let outer_span = std::mem::replace(&mut self.current_span, stmt.span);
+        let result = check_interrupt()
+            .and_then(|_| self.notify_debugger(stmt.span))
+            .and_then(|_| self.execute_statement_kind(stmt));
+        if result.is_err() && self.error_location.is_none() {
+            self.error_location = Some((self.current_file.clone(), stmt.span));
+            self.error_trace = self.frames.clone();
+        }
+        self.current_span = outer_span;
+        result
+    }
+
+    /// Run statements until one returns a value.
+    fn execute_body(&mut self, body: &[Stmt]) -> Result<Option<Value>, String> {
+        for stmt in body {
+            if let Some(v) = self.execute_statement(stmt)? {
+                return Ok(Some(v));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Start executing code from `file`, remembering where it was entered from.
+    fn enter_frame(&mut self, function: Option<&str>, file: Option<PathBuf>) {
+        let caller = std::mem::replace(&mut self.current_file, file.clone());
+        self.frames.push(TraceFrame {
+            function: function.map(String::from),
+            file,
+            caller,
+            span: self.current_span,
+        });
+    }
+
+    fn leave_frame(&mut self) {
+        if let Some(frame) = self.frames.pop() {
+            self.current_file = frame.caller;
+        }
+    }
+
+    fn notify_debugger(&mut self, span: Span) -> Result<(), String> {
+        if let Some(mut hook) = self.debug_hook.take() {
+            let variables = self.runtime.visible_variables();
+            let event = DebugEvent {
+                file: self.current_file.as_deref(),
+                span,
+                depth: self.call_depth,
+                variables: &variables,
+            };
+            let result = hook.before_statement(&event);
+            self.debug_hook = Some(hook);
+            result?;
         }
         Ok(())
     }
 
-    fn execute_statement(&mut self, stmt: &Statement) -> Result<Option<Value>, String> {
-        match stmt {
+    fn execute_statement_kind(&mut self, stmt: &Stmt) -> Result<Option<Value>, String> {
+        match &stmt.kind {
             Statement::Assignment { var, value } => {
                 let val = self.eval_expr(value)?;
                 self.runtime.set_var(var.clone(), val);
                 Ok(None)
             }
-            Statement::ArrayAssignment { var, index, value } => {
-                let idx = self.eval_expr(index)?.to_int() as usize;
+            Statement::ArrayAssignment {
+                var,
+                indices,
+                value,
+            } => {
+                let mut keys = Vec::with_capacity(indices.len());
+                for index in indices {
+                    keys.push(self.eval_expr(index)?);
+                }
                 let val = self.eval_expr(value)?;
 
                 let mut array = self.runtime.get_var(var);
-                if let Value::Array(ref mut arr) = array {
-                    if idx < arr.len() {
-                        arr[idx] = val;
-                    }
-                }
+                self.assign_index(&mut array, &keys, val)?;
                 self.runtime.set_var(var.clone(), array);
                 Ok(None)
             }
@@ -142,7 +630,7 @@ fn resolve_include_path(&self, path: &str) -> PathBuf {
                 let cond = self.eval_expr(condition)?;
                 if cond.is_truthy() {
                     for s in then_body {
-                        if let Ok(Some(v)) = self.execute_statement(s) {
+                        if let Some(v) = self.execute_statement(s)? {
                             return Ok(Some(v));
                         }
                     }
@@ -152,7 +640,7 @@ fn resolve_include_path(&self, path: &str) -> PathBuf {
                         let elif_cond_val = self.eval_expr(elif_cond)?;
                         if elif_cond_val.is_truthy() {
                             for s in elif_body {
-                                if let Ok(Some(v)) = self.execute_statement(s) {
+                                if let Some(v) = self.execute_statement(s)? {
                                     return Ok(Some(v));
                                 }
                             }
@@ -164,7 +652,7 @@ fn resolve_include_path(&self, path: &str) -> PathBuf {
                     if !executed {
                         if let Some(else_stmts) = else_body {
                             for s in else_stmts {
-                                if let Ok(Some(v)) = self.execute_statement(s) {
+                                if let Some(v) = self.execute_statement(s)? {
                                     return Ok(Some(v));
                                 }
                             }
@@ -175,116 +663,185 @@ fn resolve_include_path(&self, path: &str) -> PathBuf {
             }
             Statement::While { condition, body } => {
                 while self.eval_expr(condition)?.is_truthy() {
+                    // An empty body has no statements to stop at.
+                    check_interrupt()?;
                     for s in body {
-                        if let Ok(Some(v)) = self.execute_statement(s) {
+                        if let Some(v) = self.execute_statement(s)? {
                             return Ok(Some(v));
                         }
                     }
                 }
                 Ok(None)
             }
-            Statement::Printf { format, args } => {
-                let mut output = String::new();
-
-                if !format.is_empty() {
-                    output.push_str(&format);
+            Statement::Try {
+                body,
+                catch_var,
+                catch_body,
+                finally_body,
+            } => {
+                let mut result = self.execute_body(body);
+                // Neither an interrupt nor exit() is the script's to catch.
+                let stopping = interrupted() || self.exit_status.is_some();
+                if let (Err(message), Some(catch_body), false) = (&result, catch_body, stopping) {
+                    // A thrown value is caught as is; runtime errors as their message.
+                    let error = match self.thrown.take() {
+                        Some((thrown_message, value)) if thrown_message == *message => value,
+                        _ => Value::String(message.clone()),
+                    };
+                    self.error_location = None;
+                    self.error_trace.clear();
+                    if let Some(var) = catch_var {
+                        self.runtime.set_var(var.clone(), error);
+                    }
+                    result = self.execute_body(catch_body);
                 }
-
-                for arg in args {
-                    let val = self.eval_expr(arg)?;
-                    match val {
-                        Value::Int(n) => output.push_str(&n.to_string()),
-                        Value::String(s) => output.push_str(&s),
-                        Value::Array(arr) => output.push_str(&format!("[Array({})]", arr.len())),
-                        Value::Regex(p) => output.push_str(&format!("/{}/", p)),
-                        Value::Nil => (),
+                if let Some(finally_body) = finally_body {
+                    // A return there does not cancel exit().
+                    if let Some(v) = self.execute_body(finally_body)?.filter(|_| self.exit_status.is_none()) {
+                        return Ok(Some(v));
+                    }
+                }
+                result
+            }
+            Statement::Throw { value } => {
+                let value = self.eval_expr(value)?;
+                let message = value.to_string();
+                self.thrown = Some((message.clone(), value));
+                Err(message)
+            }
+            Statement::Global { vars } => {
+                for var in vars {
+                    self.runtime.declare_global(var);
+                }
+                Ok(None)
+            }
+            Statement::Strict => {
+                self.strict = true;
+                Ok(None)
+            }
+            Statement::Overflow(mode) => {
+                self.overflow = *mode;
+                Ok(None)
+            }
+            Statement::For {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                if let Some(init) = init {
+                    self.execute_statement(init)?;
+                }
+                loop {
+                    check_interrupt()?;
+                    if let Some(condition) = condition {
+                        if !self.eval_expr(condition)?.is_truthy() {
+                            break;
+                        }
+                    }
+                    for s in body {
+                        if let Some(v) = self.execute_statement(s)? {
+                            return Ok(Some(v));
+                        }
                     }
+                    if let Some(step) = step {
+                        self.execute_statement(step)?;
+                    }
+                }
+                Ok(None)
+            }
+            Statement::ForIn {
+                var,
+                iterable,
+                body,
+            } => {
+                let items = match self.eval_expr(iterable)? {
+                    // The loop walks a snapshot, so the body may change the array.
+                    Value::Array(elements) => elements.borrow().clone(),
+                    Value::Map(map) => map.borrow().keys().map(|k| Value::String(k.clone())).collect(),
+                    Value::String(s) => s.chars().map(|c| Value::String(c.to_string())).collect(),
+                    Value::Nil => Vec::new(),
+                    other => return Err(format!("Cannot iterate over '{}'", other)),
+                };
+                for item in items {
+                    self.runtime.set_var(var.clone(), item);
+                    for s in body {
+                        if let Some(v) = self.execute_statement(s)? {
+                            return Ok(Some(v));
+                        }
+                    }
+                }
+                Ok(None)
+            }
+            Statement::Printf { format, args } => {
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.eval_expr(arg)?);
                 }
+                let mut output =
+                    printf::format(format, &values).map_err(|e| format!("printf(): {}", e))?;
 
                 output = output.replace("\\n", "\n").replace("\\t", "\t");
 
-                print!("{}", output);
-
                 if !output.ends_with('\n') {
-                    println!();
+                    output.push('\n');
                 }
+                let stdout = self.runtime.stdout();
+                stdout
+                    .write_all(output.as_bytes())
+                    .and_then(|_| stdout.flush())
+                    .map_err(|e| format!("Failed to write output: {}", e))?;
 
                 Ok(None)
             }
             Statement::Read { var } => {
-                let mut input = String::new();
-                io::stdin()
-                    .read_line(&mut input)
-                    .map_err(|e| format!("Failed to read input: {}", e))?;
-
-                let trimmed = input
-                    .trim_end_matches(|c| c == '\n' || c == '\r')
-                    .to_string();
-                self.runtime.set_var(var.clone(), Value::String(trimmed));
+                let line = self.read_input_line()?;
+                self.runtime.set_var(var.clone(), Value::String(line));
                 Ok(None)
             }
             Statement::Inc { var, value } => {
                 let current = self.runtime.get_var(var);
                 let inc_val = self.eval_expr(value)?;
-                let result = current.add(&inc_val);
+                let result = self.arithmetic(&BinOp::Add, &current, &inc_val)?;
                 self.runtime.set_var(var.clone(), result);
                 Ok(None)
             }
             Statement::Dec { var, value } => {
                 let current = self.runtime.get_var(var);
                 let dec_val = self.eval_expr(value)?;
-                let result = current.subtract(&dec_val);
+                let result = self.arithmetic(&BinOp::Subtract, &current, &dec_val)?;
                 self.runtime.set_var(var.clone(), result);
                 Ok(None)
             }
             Statement::Push { array, value } => {
-                let mut arr = self.runtime.get_var(array);
                 let val = self.eval_expr(value)?;
-
-                match arr {
-                    Value::Array(ref mut elements) => {
-                        elements.push(val);
-                    }
-                    _ => {
-                        arr = Value::Array(vec![val]);
-                    }
+                match self.runtime.get_var(array) {
+                    Value::Array(elements) => elements.borrow_mut().push(val),
+                    _ => self.runtime.set_var(array.clone(), Value::array(vec![val])),
                 }
-
-                self.runtime.set_var(array.clone(), arr);
                 Ok(None)
             }
             Statement::Pop { array } => {
-                let mut arr = self.runtime.get_var(array);
-                if let Value::Array(ref mut elements) = arr {
-                    elements.pop();
+                if let Value::Array(elements) = self.runtime.get_var(array) {
+                    elements.borrow_mut().pop();
                 }
-                self.runtime.set_var(array.clone(), arr);
                 Ok(None)
             }
             Statement::Shift { array } => {
-                let mut arr = self.runtime.get_var(array);
-                if let Value::Array(ref mut elements) = arr {
+                if let Value::Array(elements) = self.runtime.get_var(array) {
+                    let mut elements = elements.borrow_mut();
                     if !elements.is_empty() {
                         elements.remove(0);
                     }
                 }
-                self.runtime.set_var(array.clone(), arr);
                 Ok(None)
             }
             Statement::Unshift { array, value } => {
-                let mut arr = self.runtime.get_var(array);
                 let val = self.eval_expr(value)?;
-
-                match arr {
-                    Value::Array(ref mut elements) => {
-                        elements.insert(0, val);
-                    }
-                    _ => {
-                        arr = Value::Array(vec![val]);
-                    }
+                match self.runtime.get_var(array) {
+                    Value::Array(elements) => elements.borrow_mut().insert(0, val),
+                    _ => self.runtime.set_var(array.clone(), Value::array(vec![val])),
                 }
-
-                self.runtime.set_var(array.clone(), arr);
                 Ok(None)
             }
             Statement::Sockopen { name, host, port } => {
@@ -292,7 +849,7 @@ fn resolve_include_path(&self, path: &str) -> PathBuf {
                 let port_val = self.eval_expr(port)?.to_int() as u16;
                 let addr = format!("{}:{}", host_val, port_val);
 
-                match TcpStream::connect(&addr) {
+                match self.runtime.network().connect(&addr) {
                     Ok(stream) => {
                         self.runtime.set_socket(name.clone(), stream);
                         Ok(None)
@@ -330,11 +887,94 @@ fn resolve_include_path(&self, path: &str) -> PathBuf {
                     }
                 }
 
+                Ok(None)
+            }
+            Statement::Serialopen { name, device, baud } => {
+                let device = self.eval_expr(device)?.to_string();
+                let baud = self.eval_expr(baud)?.to_int();
+                let baud = u32::try_from(baud).map_err(|_| format!("Invalid baud rate: {}", baud))?;
+
+                match self.runtime.network().open_serial(&device, baud) {
+                    Ok(port) => {
+                        self.runtime.set_serial_port(name.clone(), port);
+                        Ok(None)
+                    }
+                    Err(e) => Err(format!("Failed to open serial port {}: {}", device, e)),
+                }
+            }
+            Statement::Serialclose { name } => {
+                self.runtime.remove_serial_port(name);
+                Ok(None)
+            }
+            Statement::Serialwrite { name, data } => {
+                let data = self.eval_expr(data)?.to_string();
+                match self.runtime.get_serial_port(name) {
+                    Some(port) => port
+                        .write_all(data.as_bytes())
+                        .and_then(|_| port.flush())
+                        .map_err(|e| format!("Failed to write to serial port '{}': {}", name, e))?,
+                    None => return Err(format!("Serial port '{}' is not open", name)),
+                }
+                Ok(None)
+            }
+            Statement::Serialread { name, var } => {
+                let port = match self.runtime.get_serial_port(name) {
+                    Some(port) => port,
+                    None => return Err(format!("Serial port '{}' is not open", name)),
+                };
+                let mut buffer = [0; 1024];
+                // A read that times out yields an empty string.
+                let data = match port.read(&mut buffer) {
+                    Ok(n) => String::from_utf8_lossy(&buffer[..n]).to_string(),
+                    Err(e) if e.kind() == std::io::ErrorKind::TimedOut => String::new(),
+                    Err(e) => return Err(format!("Failed to read from serial port '{}': {}", name, e)),
+                };
+                self.runtime.set_var(var.clone(), Value::String(data));
                 Ok(None)
             }
                         Statement::Include { path } => {
-                let resolved_path = self.resolve_include_path(path);
-                let canonical = fs::canonicalize(&resolved_path).unwrap_or_else(|_| resolved_path.clone());
+                // The embedded standard library takes precedence over the filesystem.
+                let embedded = crate::stdlib::source(path);
+                // Relative includes inside a remote module are fetched from next to it.
+                let current_url = self
+                    .current_file
+                    .as_ref()
+                    .and_then(|f| f.to_str())
+                    .filter(|f| remote::is_url(f));
+                let remote_spec = match (embedded, current_url) {
+                    (Some(_), _) => None,
+                    _ if remote::is_url(path) => Some(path.clone()),
+                    (None, Some(base)) if !Path::new(path).is_absolute() => {
+                        Some(remote::join(base, path))
+                    }
+                    _ => None,
+                };
+                let remote_file = match remote_spec {
+                    Some(spec) if !self.remote_includes => {
+                        return Err(format!(
+                            "Remote include of {} is not allowed (run with --allow-remote)",
+                            spec
+                        ))
+                    }
+                    Some(spec) => {
+                        let (url, pin) = remote::split_pin(&spec);
+                        Some((url.to_string(), remote::fetch(url, pin)?))
+                    }
+                    None => None,
+                };
+                let resolved_path = match (embedded, &remote_file) {
+                    (Some(_), _) => PathBuf::from(path),
+                    (None, Some((_, cached))) => cached.clone(),
+                    (None, None) => self.resolve_include_path(path),
+                };
+                let canonical = match (embedded, &remote_file) {
+                    (Some(_), _) => resolved_path.clone(),
+                    // Remote modules go by their URL in traces and cycle checks.
+                    (None, Some((url, _))) => PathBuf::from(url),
+                    (None, None) => {
+                        fs::canonicalize(&resolved_path).unwrap_or_else(|_| resolved_path.clone())
+                    }
+                };
 
                 if self.include_in_progress.contains(&canonical) {
                     return Err(format!(
@@ -344,18 +984,48 @@ fn resolve_include_path(&self, path: &str) -> PathBuf {
                 }
 
                 self.include_in_progress.insert(canonical.clone());
+                if !self.included_files.contains(&canonical) {
+                    self.included_files.push(canonical.clone());
+                }
 
-                let result = match fs::read_to_string(&resolved_path) {
-                    Ok(content) => {
-                        let mut parser = crate::parser::Parser::new(&content);
-                        let stmts = parser.parse();
-
-                        let parent_dir = resolved_path.parent().map(|p| p.to_path_buf());
+                let parsed = match self.parse_cache.get(&canonical) {
+                    Some(stmts) => Ok(stmts.clone()),
+                    None => {
+                        let content = match (embedded, &remote_file) {
+                            (Some(source), _) => Ok(source.to_string()),
+                            // A remote module is read from its cached copy, at resolved_path.
+                            (None, _) => self.runtime.file_system().read_to_string(&resolved_path),
+                        };
+                        match content {
+                            Ok(content) => match Parser::new(&content).parse_checked() {
+                                Ok(stmts) => {
+                                    let stmts = Rc::new(stmts);
+                                    self.parse_cache.insert(canonical.clone(), stmts.clone());
+                                    Ok(stmts)
+                                }
+                                Err(errors) => {
+                                    self.error_location = Some((Some(canonical.clone()), errors[0].span));
+                                    self.error_trace = self.frames.clone();
+                                    Err(syntax_error_message(&errors))
+                                }
+                            },
+                            Err(e) => Err(format!("Failed to include file: {}", e)),
+                        }
+                    }
+                };
+                let result = match parsed {
+                    Ok(stmts) => {
+                        let parent_dir = match (embedded, &remote_file) {
+                            (None, None) => resolved_path.parent().map(|p| p.to_path_buf()),
+                            _ => None,
+                        };
                         if let Some(dir) = parent_dir.clone() {
                             self.push_base_dir(dir);
                         }
 
-                        let exec_result = self.execute(stmts);
+                        self.enter_frame(None, Some(canonical.clone()));
+                        let exec_result = self.execute_block(&stmts);
+                        self.leave_frame();
 
                         if parent_dir.is_some() {
                             self.pop_base_dir();
@@ -364,68 +1034,40 @@ fn resolve_include_path(&self, path: &str) -> PathBuf {
                         exec_result?;
                         Ok(None)
                     }
-                    Err(e) => Err(format!("Failed to include file: {}", e)),
+                    Err(e) => Err(e),
                 };
 
                 self.include_in_progress.remove(&canonical);
                 result
             }
 Statement::FunctionDef { name, params, body } => {
-                self.runtime
-                    .define_function(name.clone(), params.clone(), body.clone());
+                self.define_function(name, params, body);
                 Ok(None)
             }
             Statement::FunctionCall { name, args } => {
-                // Built-ins bypass user-defined lookup, so handle them early.
-                if name == "sleep" {
-                    if let Some(arg) = args.first() {
-                        let val = self.eval_expr(arg)?;
-                        let seconds = val.to_int() as u64;
-                        std::thread::sleep(std::time::Duration::from_secs(seconds));
-                    }
-                    return Ok(None);
-                }
-
-                if let Some((params, body)) = self.runtime.get_function(name) {
-                    // Evaluate arguments
+                // Built-ins bypass user-defined lookup, as they do in expressions.
+                if BUILTIN_FUNCTIONS.contains(&name.as_str())
+                    || self.host_functions.contains_key(name)
+                    || self.function_variable(name).is_some()
+                {
+                    self.eval_expr(&Expr::FunctionCall {
+                        name: name.clone(),
+                        args: args.clone(),
+                        span: stmt.span,
+                    })?;
+                    Ok(None)
+                } else if let Some(function) = self.runtime.get_function(name) {
                     let mut arg_vals: Vec<Value> = Vec::new();
                     for arg in args {
                         arg_vals.push(self.eval_expr(arg)?);
                     }
-
-                    // Bind params (save old values for restoration)
-                    let mut saved: Vec<(String, Option<Value>)> = Vec::new();
-                    for (i, p) in params.iter().enumerate() {
-                        let old = match self.runtime.get_var(p) {
-                            Value::Nil => None,
-                            v => Some(v),
-                        };
-                        saved.push((p.clone(), old));
-                        let v = arg_vals.get(i).cloned().unwrap_or(Value::Nil);
-                        self.runtime.set_var(p.clone(), v);
-                    }
-
-                    // Execute body
-                    let mut ret: Option<Value> = None;
-                    for stmt in &body {
-                        if let Ok(Some(val)) = self.execute_statement(stmt) {
-                            ret = Some(val);
-                            break;
-                        }
-                    }
-
-                    // Restore params
-                    for (p, old) in saved.into_iter() {
-                        if let Some(v) = old {
-                            self.runtime.set_var(p, v);
-                        } else {
-                            self.runtime.remove_var(&p);
-                        }
-                    }
-
-                    Ok(ret)
+                    // A call used as a statement discards its return value.
+                    self.invoke(name, &function, arg_vals)?;
+                    Ok(None)
+                } else if self.strict {
+                    Err(format!("Function '{}' not defined", name))
                 } else {
-                    eprintln!("Warning: function '{}' not defined", name);
+                    self.warn(&format!("function '{}' not defined", name));
                     Ok(None)
                 }
             }
@@ -442,29 +1084,127 @@ Statement::FunctionDef { name, params, body } => {
         }
     }
 
+    fn define_function(&mut self, name: &str, params: &[String], body: &[Stmt]) {
+        self.runtime.define_function(
+            name.to_string(),
+            Function {
+                params: params.to_vec(),
+                body: Rc::new(body.to_vec()),
+                file: self.current_file.clone(),
+                captured: HashMap::new(),
+            },
+        );
+    }
+
+    /// Write text to the script's stdout right away (no newline added).
+    fn write_output(&mut self, text: &str) -> Result<(), String> {
+        let stdout = self.runtime.stdout();
+        stdout
+            .write_all(text.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|e| format!("Failed to write output: {}", e))
+    }
+
+    /// Print a warning to the script's stderr; a failed write is not worth
+    /// stopping the script for.
+    fn warn(&mut self, message: &str) {
+        let stderr = self.runtime.stderr();
+        writeln!(stderr, "Warning: {}", message).and_then(|_| stderr.flush()).ok();
+    }
+
+    /// Read one line of input without its line ending ("" at end of input).
+    fn read_input_line(&mut self) -> Result<String, String> {
+        let mut input = String::new();
+        self.runtime
+            .stdin()
+            .read_line(&mut input)
+            .map_err(|e| format!("Failed to read input: {}", e))?;
+        Ok(input.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    /// Call a user-defined function by name, as the test runner does for `test_*` functions.
+    pub fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        match self.runtime.get_function(name) {
+            Some(function) => self.invoke(name, &function, args),
+            None => Err(format!("Function '{}' not defined", name)),
+        }
+    }
+
+    /// Call a function value. `name` is how the call site referred to it.
+    pub fn call_value(&mut self, name: &str, callable: &Callable, args: Vec<Value>) -> Result<Value, String> {
+        match callable {
+            Callable::Named(function) => match self.host_functions.get_mut(function) {
+                Some(host) => host(&args),
+                None => self.call_function(function, args),
+            },
+            Callable::Lambda(function) => self.invoke(name, function, args),
+        }
+    }
+
+    /// Function value held by the variable `name`, for calls like `$f(1)`
+    fn function_variable(&self, name: &str) -> Option<Callable> {
+        match self.runtime.var_ref(name) {
+            Some(Value::Function(callable)) => Some(callable.clone()),
+            _ => None,
+        }
+    }
+
+    /// Run a function body in a new local scope with its parameters bound to `args`.
+    fn invoke(&mut self, name: &str, function: &Function, args: Vec<Value>) -> Result<Value, String> {
+        if self.call_depth >= self.max_call_depth {
+            return Err(format!(
+                "Maximum call depth of {} exceeded calling '{}'",
+                self.max_call_depth, name
+            ));
+        }
+
+        self.runtime.push_scope();
+        for (var, value) in &function.captured {
+            self.runtime.set_var(var.clone(), value.clone());
+        }
+        for (i, p) in function.params.iter().enumerate() {
+            let v = args.get(i).cloned().unwrap_or(Value::Nil);
+            self.runtime.set_var(p.clone(), v);
+        }
+
+        let mut ret: Result<Value, String> = Ok(Value::Nil);
+        self.call_depth += 1;
+        self.enter_frame(Some(name), function.file.clone());
+        for stmt in function.body.iter() {
+            match self.execute_statement(stmt) {
+                Ok(None) => {}
+                Ok(Some(val)) => {
+                    ret = Ok(val);
+                    break;
+                }
+                Err(e) => {
+                    ret = Err(e);
+                    break;
+                }
+            }
+        }
+        self.leave_frame();
+        self.call_depth -= 1;
+        self.runtime.pop_scope();
+
+        ret
+    }
+
     fn eval_expr(&mut self, expr: &Expr) -> Result<Value, String> {
         match expr {
             Expr::Int(n) => Ok(Value::Int(*n)),
+            Expr::Float(f) => Ok(Value::Float(*f)),
+            Expr::Bool(b) => Ok(Value::Bool(*b)),
             Expr::String(s) => Ok(Value::String(s.clone())),
-            Expr::Regex(pat) => Ok(Value::Regex(pat.clone())),
+            Expr::Regex { pat, flags } => Ok(Value::Regex(inline_flags(pat, flags))),
 
 Expr::SubstCall { pat, repl, flags, input } => {
     let src_val = self.eval_expr(input)?;
     let src = src_val.to_string();
 
-    let mut mode = String::new();
-    if flags.contains('i') { mode.push('i'); }
-    if flags.contains('m') { mode.push('m'); }
-    if flags.contains('s') { mode.push('s'); }
+    let pat2 = inline_flags(pat, flags);
 
-    let pat2 = if mode.is_empty() {
-        pat.clone()
-    } else {
-        format!("(?{}){}", mode, pat)
-    };
-
-    let re = Regex::new(&pat2)
-        .map_err(|e| format!("Invalid regex /{}/: {}", pat, e))?;
+    let re = self.regex(&pat2)?;
 
     let out = if flags.contains('g') {
         re.replace_all(&src, repl.as_str()).to_string()
@@ -474,55 +1214,84 @@ Expr::SubstCall { pat, repl, flags, input } => {
 
     Ok(Value::String(out))
 }
-            Expr::Variable(name) => Ok(self.runtime.get_var(name)),
-            Expr::Binary { left, op, right } => {
-                let left_val = self.eval_expr(left)?;
-                let right_val = self.eval_expr(right)?;
-
+            Expr::Variable(name) => match self.runtime.var_ref(name) {
+                Some(value) => Ok(value.clone()),
+                // An unset name that matches a function refers to the function itself.
+                None if self.runtime.has_function(name) || self.host_functions.contains_key(name) => {
+                    Ok(Value::Function(Callable::Named(name.clone())))
+                }
+                None if self.strict => Err(format!("Undefined variable '${}'", name)),
+                None => Ok(Value::Nil),
+            },
+            // Lambdas capture the enclosing call's locals by value; globals stay shared.
+            Expr::Lambda { params, body } => Ok(Value::Function(Callable::Lambda(Rc::new(Function {
+                params: params.clone(),
+                body: Rc::clone(body),
+                file: self.current_file.clone(),
+                captured: self.runtime.local_variables(),
+            })))),
+            Expr::Binary {
+                left,
+                op: BinOp::Coalesce,
+                right,
+            } => match self.eval_optional(left)? {
+                // The default is only evaluated when it is needed.
+                Value::Nil => self.eval_expr(right),
+                value => Ok(value),
+            },
+            Expr::Binary {
+                left,
+                op: op @ (BinOp::RangeInclusive | BinOp::RangeExclusive),
+                right,
+            } => {
+                let start = range_bound(self.eval_expr(left)?)?;
+                let end = range_bound(self.eval_expr(right)?)?;
+                let end = if matches!(op, BinOp::RangeInclusive) { end.saturating_add(1) } else { end };
+                let len = end.saturating_sub(start).max(0);
+                if len > MAX_ARRAY_LEN {
+                    return Err(format!(
+                        "Range {}..{} is too large ({} items, at most {})",
+                        start, end, len, MAX_ARRAY_LEN
+                    ));
+                }
+                Ok(Value::array((start..end).map(Value::Int).collect()))
+            }
+            Expr::Binary { left, op, right } => {
+                let left_val = self.eval_expr(left)?;
+                let right_val = self.eval_expr(right)?;
+
                 Ok(match op {
-                    BinOp::Add => left_val.add(&right_val),
-                    BinOp::Subtract => left_val.subtract(&right_val),
-                    BinOp::Multiply => left_val.multiply(&right_val),
-                    BinOp::Divide => left_val.divide(&right_val),
-                    BinOp::Modulo => left_val.modulo(&right_val),
-                    BinOp::Equal => Value::Int(if left_val.equals(&right_val) { 1 } else { 0 }),
-                    BinOp::NotEqual => Value::Int(if !left_val.equals(&right_val) { 1 } else { 0 }),
-                    BinOp::Less => {
-                        if let Some(std::cmp::Ordering::Less) = left_val.compare(&right_val) {
-                            Value::Int(1)
-                        } else {
-                            Value::Int(0)
-                        }
-                    }
-                    BinOp::LessEqual => match left_val.compare(&right_val) {
-                        Some(std::cmp::Ordering::Less) | Some(std::cmp::Ordering::Equal) => {
-                            Value::Int(1)
-                        }
-                        _ => Value::Int(0),
+                    BinOp::Add
+                    | BinOp::Subtract
+                    | BinOp::Multiply
+                    | BinOp::Divide
+                    | BinOp::Modulo => self.arithmetic(op, &left_val, &right_val)?,
+                    BinOp::Power => self.power(&left_val, &right_val)?,
+                    BinOp::RangeInclusive | BinOp::RangeExclusive => unreachable!(),
+                    BinOp::Coalesce => match left_val {
+                        Value::Nil => right_val,
+                        value => value,
                     },
-                    BinOp::Greater => {
-                        if let Some(std::cmp::Ordering::Greater) = left_val.compare(&right_val) {
-                            Value::Int(1)
-                        } else {
-                            Value::Int(0)
-                        }
-                    }
-                    BinOp::GreaterEqual => match left_val.compare(&right_val) {
-                        Some(std::cmp::Ordering::Greater) | Some(std::cmp::Ordering::Equal) => {
-                            Value::Int(1)
-                        }
-                        _ => Value::Int(0),
-                    },
-                    BinOp::And => Value::Int(if left_val.is_truthy() && right_val.is_truthy() {
-                        1
-                    } else {
-                        0
-                    }),
-                    BinOp::Or => Value::Int(if left_val.is_truthy() || right_val.is_truthy() {
-                        1
-                    } else {
-                        0
-                    }),
+                    BinOp::Equal => Value::Bool(left_val.equals(&right_val)),
+                    BinOp::NotEqual => Value::Bool(!left_val.equals(&right_val)),
+                    BinOp::Less => Value::Bool(matches!(
+                        left_val.compare(&right_val),
+                        Some(std::cmp::Ordering::Less)
+                    )),
+                    BinOp::LessEqual => Value::Bool(matches!(
+                        left_val.compare(&right_val),
+                        Some(std::cmp::Ordering::Less) | Some(std::cmp::Ordering::Equal)
+                    )),
+                    BinOp::Greater => Value::Bool(matches!(
+                        left_val.compare(&right_val),
+                        Some(std::cmp::Ordering::Greater)
+                    )),
+                    BinOp::GreaterEqual => Value::Bool(matches!(
+                        left_val.compare(&right_val),
+                        Some(std::cmp::Ordering::Greater) | Some(std::cmp::Ordering::Equal)
+                    )),
+                    BinOp::And => Value::Bool(left_val.is_truthy() && right_val.is_truthy()),
+                    BinOp::Or => Value::Bool(left_val.is_truthy() || right_val.is_truthy()),
 BinOp::Match => {
     let text = left_val.to_string();
     let pat = match right_val {
@@ -530,17 +1299,33 @@ BinOp::Match => {
         Value::String(s) => s,
         other => other.to_string(),
     };
-    let re = Regex::new(&pat)
-        .map_err(|e| format!("Invalid regex /{}/: {}", pat, e))?;
-    Value::Int(if re.is_match(&text) { 1 } else { 0 })
+    let re = self.regex(&pat)?;
+    match re.captures(&text) {
+        Some(caps) => {
+            // $0 is the whole match and $1..$n its groups.
+            for (i, group) in caps.iter().enumerate() {
+                let value = group.map_or(Value::Nil, |m| Value::String(m.as_str().to_string()));
+                self.runtime.set_var(i.to_string(), value);
+            }
+            Value::Bool(true)
+        }
+        None => Value::Bool(false),
+    }
 }
                 })
             }
             Expr::Unary { op, expr } => {
                 let val = self.eval_expr(expr)?;
                 Ok(match op {
-                    UnaryOp::Not => Value::Int(if val.is_truthy() { 0 } else { 1 }),
-                    UnaryOp::Negate => Value::Int(-val.to_int()),
+                    UnaryOp::Not => Value::Bool(!val.is_truthy()),
+                    UnaryOp::Negate => match val {
+                        Value::Float(f) => Value::Float(-f),
+                        Value::BigInt(n) => Value::big(-n),
+                        _ => {
+                            let n = Value::Int(val.to_int());
+                            self.arithmetic(&BinOp::Subtract, &Value::Int(0), &n)?
+                        }
+                    },
                 })
             }
             Expr::Array(elements) => {
@@ -548,158 +1333,1736 @@ BinOp::Match => {
                 for elem in elements {
                     values.push(self.eval_expr(elem)?);
                 }
-                Ok(Value::Array(values))
+                Ok(Value::array(values))
             }
-            Expr::Index { expr, index } => {
-                let arr = self.eval_expr(expr)?;
-                let idx = self.eval_expr(index)?.to_int() as usize;
-
-                match arr {
-                    Value::Array(elements) => Ok(elements.get(idx).cloned().unwrap_or(Value::Nil)),
+            Expr::Map(entries) => {
+                let mut map = std::collections::BTreeMap::new();
+                for (key, value) in entries {
+                    let key = self.eval_expr(key)?.to_string();
+                    map.insert(key, self.eval_expr(value)?);
+                }
+                Ok(Value::map(map))
+            }
+            Expr::Index { expr, index, optional } => {
+                let arr = if *optional {
+                    match self.eval_optional(expr)? {
+                        arr @ (Value::Array(_) | Value::Map(_) | Value::String(_)) => arr,
+                        _ => return Ok(Value::Nil),
+                    }
+                } else {
+                    self.eval_expr(expr)?
+                };
+                let key = self.eval_expr(index)?;
+                if let Value::Map(map) = arr {
+                    return Ok(map.borrow().get(&key.to_string()).cloned().unwrap_or(Value::Nil));
+                }
+                // An array of indices, such as a range, picks several items at once.
+                if let (Value::Array(keys), Value::Array(_) | Value::String(_)) = (&key, &arr) {
+                    let keys = keys.borrow();
+                    let mut items = Vec::with_capacity(keys.len());
+                    for key in keys.iter() {
+                        items.push(self.index_sequence(&arr, key, *optional)?);
+                    }
+                    return Ok(match arr {
+                        Value::String(_) => {
+                            Value::String(items.iter().map(|item| item.to_string()).collect())
+                        }
+                        _ => Value::array(items),
+                    });
+                }
+                self.index_sequence(&arr, &key, *optional)
+            }
+            Expr::Slice {
+                expr,
+                start,
+                end,
+                optional,
+            } => {
+                let value = if *optional { self.eval_optional(expr)? } else { self.eval_expr(expr)? };
+                let mut bounds = [None, None];
+                for (bound, expr) in bounds.iter_mut().zip([start, end]) {
+                    if let Some(expr) = expr {
+                        *bound = Some(self.eval_expr(expr)?.to_int());
+                    }
+                }
+                Ok(match value {
+                    Value::Array(elements) => {
+                        let elements = elements.borrow();
+                        let range = slice_range(bounds, elements.len());
+                        Value::array(elements[range].to_vec())
+                    }
                     Value::String(s) => {
                         let chars: Vec<char> = s.chars().collect();
-                        if idx < chars.len() {
-                            Ok(Value::String(chars[idx].to_string()))
-                        } else {
-                            Ok(Value::Nil)
+                        let range = slice_range(bounds, chars.len());
+                        Value::String(chars[range].iter().collect())
+                    }
+                    _ => Value::Nil,
+                })
+            }
+            Expr::FunctionCall { name, args, span } => {
+                let result = self.eval_call(name, args);
+                // Point at the call itself unless the error happened deeper down.
+                if result.is_err() && self.error_location.is_none() {
+                    self.error_location = Some((self.current_file.clone(), *span));
+                    self.error_trace = self.frames.clone();
+                }
+                result
+            }
+        }
+    }
+
+    /// Store `val` at the end of the index chain `keys` inside `container`.
+    /// A string key on nil starts a new map, so `$m["a"]["b"] = 1` builds
+    /// the maps it needs; other missing levels leave `container` as it was.
+    fn assign_index(&self, container: &mut Value, keys: &[Value], val: Value) -> Result<(), String> {
+        let Some((key, rest)) = keys.split_first() else {
+            *container = val;
+            return Ok(());
+        };
+        // Each item is taken out while the rest of the chain is assigned, so
+        // an array that contains itself is never borrowed twice.
+        match container {
+            Value::Array(arr) => {
+                let len = arr.borrow().len();
+                let idx = match sequence_index(key.to_int(), len) {
+                    Some(idx) => idx,
+                    None if self.strict_index => {
+                        return Err(format!(
+                            "Index {} out of range for array of length {}",
+                            key, len
+                        ))
+                    }
+                    None if key.to_int() < 0 => return Ok(()),
+                    // Past the end, the array grows with nil up to the index.
+                    None => grow_index(key.to_int())?,
+                };
+                let mut item = arr.borrow().get(idx).cloned().unwrap_or(Value::Nil);
+                self.assign_index(&mut item, rest, val)?;
+                if idx < len || rest.is_empty() || !matches!(item, Value::Nil) {
+                    let mut items = arr.borrow_mut();
+                    if idx >= items.len() {
+                        items.resize(idx + 1, Value::Nil);
+                    }
+                    items[idx] = item;
+                }
+            }
+            Value::Map(map) => {
+                let key = key.to_string();
+                let existing = map.borrow().get(&key).cloned();
+                let found = existing.is_some();
+                let mut item = existing.unwrap_or(Value::Nil);
+                self.assign_index(&mut item, rest, val)?;
+                if found || rest.is_empty() || !matches!(item, Value::Nil) {
+                    map.borrow_mut().insert(key, item);
+                }
+            }
+            // An integer key on nil starts a new array, padded like one that grows.
+            Value::Nil if matches!(key, Value::Int(n) if *n >= 0) => {
+                if self.strict_index && key.to_int() > 0 {
+                    return Err(format!("Index {} out of range for array of length 0", key));
+                }
+                let idx = grow_index(key.to_int())?;
+                let mut item = Value::Nil;
+                self.assign_index(&mut item, rest, val)?;
+                let mut arr = vec![Value::Nil; idx];
+                arr.push(item);
+                *container = Value::array(arr);
+            }
+            Value::Nil if matches!(key, Value::String(_)) => {
+                let mut item = Value::Nil;
+                self.assign_index(&mut item, rest, val)?;
+                if rest.is_empty() || !matches!(item, Value::Nil) {
+                    *container = Value::map([(key.to_string(), item)].into_iter().collect());
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Item `key` of an array or string, or nil (an error under
+    /// `--strict-index`) when there is none
+    fn index_sequence(&self, arr: &Value, key: &Value, optional: bool) -> Result<Value, String> {
+        let (found, len, kind) = match arr {
+            Value::Array(elements) => {
+                let elements = elements.borrow();
+                let found = sequence_index(key.to_int(), elements.len())
+                    .map(|i| elements[i].clone());
+                (found, elements.len(), "array")
+            }
+            // Strings index by character; only a negative index needs the count.
+            Value::String(s) => match key.to_int() {
+                i if i >= 0 => match s.chars().nth(i as usize) {
+                    Some(c) => return Ok(Value::String(c.to_string())),
+                    None => (None, s.chars().count(), "string"),
+                },
+                i => {
+                    let len = s.chars().count();
+                    let found = sequence_index(i, len)
+                        .and_then(|i| s.chars().nth(i))
+                        .map(|c| Value::String(c.to_string()));
+                    (found, len, "string")
+                }
+            },
+            _ => return Ok(Value::Nil),
+        };
+        match found {
+            Some(value) => Ok(value),
+            None if self.strict_index && !optional => Err(format!(
+                "Index {} out of range for {} of length {}",
+                key, kind, len
+            )),
+            None => Ok(Value::Nil),
+        }
+    }
+
+    /// Evaluate the left side of `??` or `?[]`, where an undefined variable
+    /// is nil even in strict mode.
+    fn eval_optional(&mut self, expr: &Expr) -> Result<Value, String> {
+        match expr {
+            Expr::Variable(name)
+                if self.runtime.var_ref(name).is_none()
+                    && !self.runtime.has_function(name)
+                    && !self.host_functions.contains_key(name) =>
+            {
+                Ok(Value::Nil)
+            }
+            _ => self.eval_expr(expr),
+        }
+    }
+
+    /// `left op right` for + - * / %, with integer overflow handled as the
+    /// `overflow` setting says
+    fn arithmetic(&self, op: &BinOp, left: &Value, right: &Value) -> Result<Value, String> {
+        let int = |value: &Value| match value {
+            Value::Int(n) => Some(*n),
+            Value::Bool(b) => Some(*b as i64),
+            _ => None,
+        };
+        if let (Some(a), Some(b)) = (int(left), int(right)) {
+            let checked = match op {
+                BinOp::Add => a.checked_add(b),
+                BinOp::Subtract => a.checked_sub(b),
+                BinOp::Multiply => a.checked_mul(b),
+                // Division by zero gives nil below; only MIN / -1 overflows.
+                BinOp::Divide if b != 0 => a.checked_div(b),
+                BinOp::Modulo if b != 0 => a.checked_rem(b),
+                _ => Some(0),
+            };
+            if checked.is_none() {
+                let symbol = match op {
+                    BinOp::Add => "+",
+                    BinOp::Subtract => "-",
+                    BinOp::Multiply => "*",
+                    BinOp::Divide => "/",
+                    _ => "%",
+                };
+                return match self.overflow {
+                    Overflow::Error => Err(format!(
+                        "Integer overflow: {} {} {} does not fit in 64 bits",
+                        a, symbol, b
+                    )),
+                    Overflow::Saturate => Ok(Value::Int(match op {
+                        BinOp::Add => a.saturating_add(b),
+                        BinOp::Subtract => a.saturating_sub(b),
+                        BinOp::Multiply => a.saturating_mul(b),
+                        BinOp::Divide => a.saturating_div(b),
+                        _ => 0,
+                    })),
+                    // The Value methods wrap.
+                    Overflow::Wrap => Ok(wrapping_arithmetic(op, left, right)),
+                    // With a BigInt operand they are exact.
+                    Overflow::Promote => {
+                        Ok(wrapping_arithmetic(op, &Value::BigInt(a.into()), right))
+                    }
+                };
+            }
+        }
+        Ok(wrapping_arithmetic(op, left, right))
+    }
+
+    /// The compiled regex for `pat`, from the cache when it was used before
+    fn regex(&mut self, pat: &str) -> Result<Regex, String> {
+        if let Some(re) = self.regex_cache.get(pat) {
+            return Ok(re.clone());
+        }
+        let re = Regex::new(pat).map_err(|e| format!("Invalid regex /{}/: {}", pat, e))?;
+        // Patterns built at run time could be endless, so do not grow forever.
+        if self.regex_cache.len() >= MAX_CACHED_REGEXES {
+            self.regex_cache.clear();
+        }
+        self.regex_cache.insert(pat.to_string(), re.clone());
+        Ok(re)
+    }
+
+    /// `left ** right`. Integer powers that do not fit in 64 bits are exact
+    /// for a BigInt base or with `overflow promote`, and floats otherwise.
+    fn power(&self, left: &Value, right: &Value) -> Result<Value, String> {
+        let exact = matches!(left, Value::BigInt(_)) || self.overflow == Overflow::Promote;
+        match (left.to_bigint(), right) {
+            (Some(base), Value::Int(exponent)) if exact && *exponent >= 0 => {
+                if let Value::Int(n) = left.power(right) {
+                    return Ok(Value::Int(n));
+                }
+                // Powers of 0, 1 and -1 stay small however large the exponent.
+                let bits = base.bits();
+                if bits > 1 && bits.saturating_mul(*exponent as u64) > MAX_POWER_BITS {
+                    return Err(format!(
+                        "Integer power {} ** {} is too large (over {} bits)",
+                        left, exponent, MAX_POWER_BITS
+                    ));
+                }
+                Ok(Value::big(Pow::pow(base, *exponent as u64)))
+            }
+            _ => Ok(left.power(right)),
+        }
+    }
+
+    /// Values for min(), max(), sum() and avg(): the elements of a single array
+    /// argument, or else the arguments themselves, leaving out nil.
+    fn aggregate_args(&mut self, args: &[Expr]) -> Result<Vec<Value>, String> {
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(self.eval_expr(arg)?);
+        }
+        if let [Value::Array(array)] = values.as_slice() {
+            let elements = array.borrow().clone();
+            values = elements;
+        }
+        values.retain(|value| !matches!(value, Value::Nil));
+        Ok(values)
+    }
+
+    /// Evaluate a call to a builtin, host, user-defined or stored function.
+    fn eval_call(&mut self, name: &str, args: &[Expr]) -> Result<Value, String> {
+        if let Some(callable) = self.function_variable(name) {
+            let mut arg_vals: Vec<Value> = Vec::new();
+            for arg in args {
+                arg_vals.push(self.eval_expr(arg)?);
+            }
+            return self.call_value(name, &callable, arg_vals);
+        }
+        match name {
+            "len" | "strlen" => {
+                if let Some(arg) = args.first() {
+                    let val = self.eval_expr(arg)?;
+                    match val {
+                        Value::String(s) => Ok(Value::Int(s.chars().count() as i64)),
+                        Value::Array(arr) => Ok(Value::Int(arr.borrow().len() as i64)),
+                        Value::Map(map) => Ok(Value::Int(map.borrow().len() as i64)),
+                        _ => Ok(Value::Int(0)),
+                    }
+                } else {
+                    Ok(Value::Int(0))
+                }
+            }
+            "shell" | "shell_result" | "run" => {
+                let mut arg_vals = Vec::new();
+                for arg in args {
+                    arg_vals.push(self.eval_expr(arg)?);
+                }
+                let mut arg_vals = arg_vals.into_iter();
+                let command = arg_vals.next().unwrap_or(Value::Nil);
+                // Then the text for its stdin and the timeout in seconds, nil
+                // for either to go without.
+                let input = match arg_vals.next().unwrap_or(Value::Nil) {
+                    Value::Nil => None,
+                    value => Some(value.to_string().into_bytes()),
+                };
+                let timeout = match arg_vals.next().unwrap_or(Value::Nil) {
+                    Value::Nil => None,
+                    value @ (Value::Int(_) | Value::BigInt(_) | Value::Float(_)) => Some(value.to_int())
+                        .filter(|&secs| secs > 0)
+                        .map(|secs| std::time::Duration::from_secs(secs as u64)),
+                    other => {
+                        return Err(format!(
+                            "{}(): the timeout must be a number of seconds or nil, got '{}'",
+                            name, other
+                        ));
+                    }
+                };
+
+                // An array is the program and its arguments, run without a shell.
+                let (described, result) = match command {
+                    Value::Array(items) => {
+                        let argv: Vec<String> = items.borrow().iter().map(Value::to_string).collect();
+                        (argv.join(" "), process::run_program(&argv, input, timeout))
+                    }
+                    Value::Nil if name != "shell" => {
+                        return Err(format!("{}(): expected a command", name));
+                    }
+                    other if name == "run" => {
+                        return Err(format!(
+                            "run(): expected an array with the program and its arguments, got '{}'",
+                            other
+                        ));
+                    }
+                    other => {
+                        let cmd_str = other.to_string();
+                        let result = process::run_shell(&cmd_str, input, timeout);
+                        (cmd_str, result)
+                    }
+                };
+                match result {
+                    Ok(result) if name == "shell_result" => {
+                        let mut map = BTreeMap::new();
+                        map.insert("stdout".to_string(), Value::String(command_output(&result.stdout)));
+                        map.insert("stderr".to_string(), Value::String(command_output(&result.stderr)));
+                        let code = result.code.map_or(Value::Nil, |code| Value::Int(code as i64));
+                        map.insert("code".to_string(), code);
+                        map.insert("timed_out".to_string(), Value::Bool(result.timed_out));
+                        Ok(Value::map(map))
+                    }
+                    // A killed command gives nil, which is_nil() tells apart
+                    // from a command that printed nothing.
+                    Ok(result) if result.timed_out => Ok(Value::Nil),
+                    Ok(result) => Ok(Value::String(command_output(&result.stdout))),
+                    Err(_) if name == "shell" => Ok(Value::String(String::new())),
+                    Err(e) => Err(format!("{}(): cannot run '{}': {}", name, described, e)),
+                }
+            }
+            "spawn" => {
+                let command = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?,
+                    None => return Err("spawn(): expected a command".to_string()),
+                };
+                // An array is the program and its arguments, as for run().
+                let (described, started) = match command {
+                    Value::Array(items) => {
+                        let argv: Vec<String> = items.borrow().iter().map(Value::to_string).collect();
+                        (argv.join(" "), process::Process::spawn_program(&argv))
+                    }
+                    other => {
+                        let cmd_str = other.to_string();
+                        let started = process::Process::spawn_shell(&cmd_str);
+                        (cmd_str, started)
+                    }
+                };
+                match started {
+                    Ok(child) => Ok(Value::Int(self.runtime.add_process(child))),
+                    Err(e) => Err(format!("spawn(): cannot run '{}': {}", described, e)),
+                }
+            }
+            "proc_readline" | "proc_write" | "proc_close" | "proc_wait" | "proc_kill" => {
+                let handle = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?.to_int(),
+                    None => return Err(format!("{}(): expected a process from spawn()", name)),
+                };
+                let data = match args.get(1) {
+                    Some(arg) => self.eval_expr(arg)?.to_string(),
+                    None => String::new(),
+                };
+                let no_process = || format!("{}(): no process {}; it was never spawned or has ended", name, handle);
+                match name {
+                    "proc_readline" => {
+                        let child = self.runtime.get_process(handle).ok_or_else(no_process)?;
+                        match child.read_line() {
+                            Ok(Some(line)) => Ok(Value::String(line)),
+                            // Nil at the end of the output, as when reading a closed stream.
+                            Ok(None) => Ok(Value::Nil),
+                            Err(e) => Err(format!("proc_readline(): {}", e)),
                         }
                     }
-                    _ => Ok(Value::Nil),
+                    "proc_write" => {
+                        let child = self.runtime.get_process(handle).ok_or_else(no_process)?;
+                        child
+                            .write(data.as_bytes())
+                            .map(|_| Value::Nil)
+                            .map_err(|e| format!("proc_write(): process {} is not reading: {}", handle, e))
+                    }
+                    "proc_close" => {
+                        self.runtime.get_process(handle).ok_or_else(no_process)?.close_input();
+                        Ok(Value::Nil)
+                    }
+                    "proc_wait" => {
+                        let child = self.runtime.remove_process(handle).ok_or_else(no_process)?;
+                        match child.wait() {
+                            Ok(code) => Ok(code.map_or(Value::Nil, |code| Value::Int(code as i64))),
+                            Err(e) => Err(format!("proc_wait(): {}", e)),
+                        }
+                    }
+                    _ => {
+                        let child = self.runtime.remove_process(handle).ok_or_else(no_process)?;
+                        child.kill().map(|_| Value::Nil).map_err(|e| format!("proc_kill(): {}", e))
+                    }
                 }
             }
-            Expr::FunctionCall { name, args } => {
-                match name.as_str() {
-                    "len" | "strlen" => {
-                        if let Some(arg) = args.first() {
-                            let val = self.eval_expr(arg)?;
-                            match val {
-                                Value::String(s) => Ok(Value::Int(s.len() as i64)),
-                                Value::Array(arr) => Ok(Value::Int(arr.len() as i64)),
-                                _ => Ok(Value::Int(0)),
-                            }
-                        } else {
+            "read_file" | "write_file" | "append_file" | "file_exists" => {
+                let path = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?.to_string(),
+                    None => return Err(format!("{}(): expected a file path", name)),
+                };
+                let path = self.resolve_data_path(&path);
+                match name {
+                    "file_exists" => Ok(Value::Bool(self.runtime.file_system().exists(&path))),
+                    "read_file" => match self.runtime.file_system().read_to_string(&path) {
+                        Ok(content) => Ok(Value::String(content)),
+                        Err(e) => Err(format!("read_file(): cannot read {}: {}", path.display(), e)),
+                    },
+                    _ => {
+                        let data = match args.get(1) {
+                            Some(arg) => self.eval_expr(arg)?.to_string(),
+                            None => String::new(),
+                        };
+                        let append = name == "append_file";
+                        match self.runtime.file_system().write(&path, data.as_bytes(), append) {
+                            Ok(()) => Ok(Value::Nil),
+                            Err(e) => Err(format!("{}(): cannot write {}: {}", name, path.display(), e)),
+                        }
+                    }
+                }
+            }
+            "env" | "setenv" => {
+                let key = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?.to_string(),
+                    None => return Err(format!("{}(): expected a variable name", name)),
+                };
+                // std::env panics on names the OS cannot store.
+                if key.is_empty() || key.contains(['=', '\0']) {
+                    return Err(format!("{}(): '{}' is not a valid variable name", name, key));
+                }
+                if name == "env" {
+                    return Ok(env::var_os(&key)
+                        .map(|value| Value::String(value.to_string_lossy().into_owned()))
+                        .unwrap_or(Value::Nil));
+                }
+                match args.get(1).map(|arg| self.eval_expr(arg)).transpose()? {
+                    None | Some(Value::Nil) => env::remove_var(&key),
+                    Some(value) => {
+                        let value = value.to_string();
+                        if value.contains('\0') {
+                            return Err(format!("setenv(): the value of '{}' contains a NUL byte", key));
+                        }
+                        env::set_var(&key, value);
+                    }
+                }
+                Ok(Value::Nil)
+            }
+            "environ" => Ok(Value::map(
+                env::vars_os()
+                    .map(|(key, value)| {
+                        let value = Value::String(value.to_string_lossy().into_owned());
+                        (key.to_string_lossy().into_owned(), value)
+                    })
+                    .collect(),
+            )),
+            "number" => {
+                if let Some(arg) = args.first() {
+                    let val = self.eval_expr(arg)?;
+                    match val {
+                        Value::Int(n) => Ok(Value::Int(n)),
+                        Value::BigInt(n) => Ok(Value::BigInt(n)),
+                        Value::Float(f) => Ok(Value::Float(f)),
+                        Value::Bool(b) => Ok(Value::Int(b as i64)),
+                        // Text that is not a number gives 0, or nil in strict mode.
+                        Value::String(s) => match Value::parse_number(s.trim()) {
+                            Some(n) => Ok(n),
+                            None if self.strict => Ok(Value::Nil),
+                            None => Ok(Value::Int(0)),
+                        },
+                        Value::Array(_)
+                        | Value::Map(_)
+                        | Value::Nil
+                        | Value::Regex(_)
+                        | Value::Function(_) => {
                             Ok(Value::Int(0))
                         }
                     }
-                    "shell" => {
-                        if let Some(arg) = args.first() {
-                            let val = self.eval_expr(arg)?;
-                            let cmd_str = val.to_string();
-
-                            let output = if cfg!(target_os = "windows") {
-                                Command::new("cmd").args(["/C", &cmd_str]).output()
-                            } else {
-                                Command::new("sh").arg("-c").arg(&cmd_str).output()
-                            };
-
-                            match output {
-                                Ok(result) => {
-                                    let mut stdout =
-                                        String::from_utf8_lossy(&result.stdout).to_string();
-                                    // Trim trailing newline so pipelines behave predictably.
-                                    if stdout.ends_with('\n') {
-                                        stdout.pop();
-                                        if stdout.ends_with('\r') {
-                                            stdout.pop();
-                                        }
-                                    }
-                                    Ok(Value::String(stdout))
-                                }
-                                Err(_) => Ok(Value::String(String::new())),
+                } else {
+                    Ok(Value::Int(0))
+                }
+            }
+            "bigint" => {
+                let value = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?,
+                    None => return Err("bigint() requires a number".to_string()),
+                };
+                match value {
+                    Value::Int(_) | Value::BigInt(_) => Ok(value),
+                    Value::Bool(b) => Ok(Value::Int(b as i64)),
+                    Value::Float(x) => BigInt::from_f64(x.trunc())
+                        .map(Value::big)
+                        .ok_or_else(|| format!("bigint(): '{}' is not a finite number", value)),
+                    Value::String(ref s) => Value::parse_integer(s.trim())
+                        .ok_or_else(|| format!("bigint(): '{}' is not an integer", s)),
+                    other => Err(format!("bigint(): '{}' is not a number", other)),
+                }
+            }
+            "casefold" | "nfc" | "nfd" => {
+                let text = match args.first() {
+                    Some(arg) => match self.eval_expr(arg)? {
+                        Value::Nil => String::new(),
+                        value => value.to_string(),
+                    },
+                    None => String::new(),
+                };
+                Ok(Value::String(match name {
+                    "casefold" => casefold(&text),
+                    "nfc" => text.nfc().collect(),
+                    _ => text.nfd().collect(),
+                }))
+            }
+            "bytelen" | "grapheme_len" | "graphemes" => {
+                let text = match args.first() {
+                    Some(arg) => match self.eval_expr(arg)? {
+                        Value::Nil => String::new(),
+                        value => value.to_string(),
+                    },
+                    None => String::new(),
+                };
+                Ok(match name {
+                    "bytelen" => Value::Int(text.len() as i64),
+                    "grapheme_len" => Value::Int(text.graphemes(true).count() as i64),
+                    _ => Value::array(
+                        text.graphemes(true).map(|g| Value::String(g.to_string())).collect(),
+                    ),
+                })
+            }
+            "lower" => {
+                if let Some(arg) = args.first() {
+                    let val = self.eval_expr(arg)?;
+                    Ok(Value::String(val.to_string().to_lowercase()))
+                } else {
+                    Ok(Value::String(String::new()))
+                }
+            }
+            "upper" => {
+                if let Some(arg) = args.first() {
+                    let val = self.eval_expr(arg)?;
+                    Ok(Value::String(val.to_string().to_uppercase()))
+                } else {
+                    Ok(Value::String(String::new()))
+                }
+            }
+            "substr" | "trim" | "ltrim" | "rtrim" | "replace" | "starts_with" | "ends_with"
+            | "repeat" | "pad_left" | "pad_right" => {
+                let mut arg_vals: Vec<Value> = Vec::new();
+                for arg in args {
+                    arg_vals.push(self.eval_expr(arg)?);
+                }
+                // Missing arguments and nil read as "".
+                let text = |i: usize| match arg_vals.get(i) {
+                    None | Some(Value::Nil) => String::new(),
+                    Some(value) => value.to_string(),
+                };
+                let subject = text(0);
+                Ok(match name {
+                    "substr" => {
+                        // Positions count characters; a negative start counts from the end.
+                        let chars: Vec<char> = subject.chars().collect();
+                        let start = arg_vals.get(1).map_or(0, Value::to_int);
+                        let end = match arg_vals.get(2) {
+                            Some(Value::Nil) | None => None,
+                            Some(len) => {
+                                let from = if start < 0 { start + chars.len() as i64 } else { start };
+                                Some(from.max(0).saturating_add(len.to_int().max(0)))
                             }
+                        };
+                        let range = slice_range([Some(start), end], chars.len());
+                        Value::String(chars[range].iter().collect())
+                    }
+                    "trim" | "ltrim" | "rtrim" => {
+                        // An optional second argument lists the characters to strip.
+                        let set: Option<Vec<char>> =
+                            arg_vals.get(1).map(|_| text(1).chars().collect());
+                        let strip = |c: char| match &set {
+                            Some(set) => set.contains(&c),
+                            None => c.is_whitespace(),
+                        };
+                        Value::String(match name {
+                            "trim" => subject.trim_matches(strip),
+                            "ltrim" => subject.trim_start_matches(strip),
+                            _ => subject.trim_end_matches(strip),
+                        }.to_string())
+                    }
+                    "replace" => {
+                        let from = text(1);
+                        if from.is_empty() {
+                            Value::String(subject)
                         } else {
-                            Ok(Value::String(String::new()))
+                            Value::String(subject.replace(&from, &text(2)))
                         }
                     }
-                    "number" => {
-                        if let Some(arg) = args.first() {
-                            let val = self.eval_expr(arg)?;
-                            match val {
-                                Value::Int(n) => Ok(Value::Int(n)),
-                                Value::String(s) => {
-                                    let trimmed = s.trim();
-                                    match trimmed.parse::<i64>() {
-                                        Ok(n) => Ok(Value::Int(n)),
-                                        Err(_) => Ok(Value::Int(0)),
-                                    }
-                                }
-                                Value::Array(_) | Value::Nil | Value::Regex(_) => Ok(Value::Int(0)),
-                            }
-                        } else {
-                            Ok(Value::Int(0))
+                    "starts_with" => Value::Bool(subject.starts_with(&text(1))),
+                    "ends_with" => Value::Bool(subject.ends_with(&text(1))),
+                    "repeat" => {
+                        let count = arg_vals.get(1).map_or(0, Value::to_int).max(0) as usize;
+                        if subject.len().saturating_mul(count) > MAX_ARRAY_LEN as usize {
+                            return Err(format!(
+                                "repeat(): result would be too long ({} copies)",
+                                count
+                            ));
                         }
+                        Value::String(subject.repeat(count))
                     }
-                    "lower" => {
-                        if let Some(arg) = args.first() {
-                            let val = self.eval_expr(arg)?;
-                            Ok(Value::String(val.to_string().to_lowercase()))
+                    _ => {
+                        let width = arg_vals.get(1).map_or(0, Value::to_int).max(0) as usize;
+                        let fill = match arg_vals.get(2) {
+                            None | Some(Value::Nil) => " ".to_string(),
+                            Some(_) => text(2),
+                        };
+                        let len = subject.chars().count();
+                        if len >= width || fill.is_empty() {
+                            Value::String(subject)
+                        } else if width > MAX_ARRAY_LEN as usize {
+                            return Err(format!(
+                                "{}(): result would be too long ({} characters)",
+                                name, width
+                            ));
                         } else {
-                            Ok(Value::String(String::new()))
+                            // The fill is repeated and cut to the exact width.
+                            let padding: String = fill.chars().cycle().take(width - len).collect();
+                            Value::String(match name {
+                                "pad_left" => padding + &subject,
+                                _ => subject + &padding,
+                            })
                         }
                     }
-                    "upper" => {
-                        if let Some(arg) = args.first() {
-                            let val = self.eval_expr(arg)?;
-                            Ok(Value::String(val.to_string().to_uppercase()))
-                        } else {
-                            Ok(Value::String(String::new()))
+                })
+            }
+            "sprintf" => {
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.eval_expr(arg)?);
+                }
+                let format = values.first().map(printf::text).unwrap_or_default();
+                printf::format(&format, values.get(1..).unwrap_or_default())
+                    .map(Value::String)
+                    .map_err(|e| format!("sprintf(): {}", e))
+            }
+            "format_number" => {
+                let mut values = Vec::new();
+                for arg in args.iter().take(4) {
+                    values.push(self.eval_expr(arg)?);
+                }
+                // The settings can also come as a map in place of the decimals.
+                if let Some(Value::Map(options)) = values.get(1).cloned() {
+                    const KEYS: [&str; 3] = ["decimals", "thousands_sep", "decimal_sep"];
+                    let options = options.borrow();
+                    if let Some(key) = options.keys().find(|key| !KEYS.contains(&key.as_str())) {
+                        return Err(format!(
+                            "format_number(): unknown option '{}', expected one of {}",
+                            key,
+                            KEYS.join(", ")
+                        ));
+                    }
+                    values.truncate(1);
+                    values.extend(KEYS.iter().map(|key| options.get(*key).cloned().unwrap_or(Value::Nil)));
+                }
+                let number = values.first().cloned().unwrap_or(Value::Int(0));
+                let decimals = values.get(1).map_or(0, Value::to_int).clamp(0, 20) as usize;
+                let setting = |i: usize, default: &str| match values.get(i) {
+                    Some(Value::Nil) | None => default.to_string(),
+                    Some(value) => value.to_string(),
+                };
+                let digits = match number {
+                    Value::Int(n) if decimals == 0 => n.to_string(),
+                    Value::Int(n) => format!("{}.{}", n, "0".repeat(decimals)),
+                    other => match other.to_string().trim().parse::<f64>() {
+                        Ok(f) => format!("{:.*}", decimals, f),
+                        Err(_) => {
+                            return Err(format!("format_number(): '{}' is not a number", other))
                         }
+                    },
+                };
+                Ok(Value::String(group_digits(
+                    &digits,
+                    &setting(2, ","),
+                    &setting(3, "."),
+                )))
+            }
+            "assert_eq" => {
+                let left = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?,
+                    None => Value::Nil,
+                };
+                let right = match args.get(1) {
+                    Some(arg) => self.eval_expr(arg)?,
+                    None => Value::Nil,
+                };
+                if left.equals(&right) {
+                    Ok(Value::Bool(true))
+                } else {
+                    Err(format!("assert_eq failed: '{}' != '{}'", left, right))
+                }
+            }
+            "assert_match" => {
+                let text = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?.to_string(),
+                    None => String::new(),
+                };
+                let pat = match args.get(1) {
+                    Some(arg) => match self.eval_expr(arg)? {
+                        Value::Regex(p) | Value::String(p) => p,
+                        other => other.to_string(),
+                    },
+                    None => String::new(),
+                };
+                let re = self.regex(&pat)?;
+                if re.is_match(&text) {
+                    Ok(Value::Bool(true))
+                } else {
+                    Err(format!("assert_match failed: '{}' does not match /{}/", text, pat))
+                }
+            }
+            "regmatch" | "match_all" => {
+                let text = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?.to_string(),
+                    None => String::new(),
+                };
+                let pat = match args.get(1) {
+                    Some(arg) => match self.eval_expr(arg)? {
+                        Value::Regex(p) | Value::String(p) => p,
+                        other => other.to_string(),
+                    },
+                    None => return Err(format!("{}() requires a string and a pattern", name)),
+                };
+                let re = self.regex(&pat)?;
+                if name == "regmatch" {
+                    return Ok(re.captures(&text).map_or(Value::Nil, |caps| captures_map(&re, &caps)));
+                }
+                // Without groups, each match is just its text.
+                Ok(Value::array(if re.captures_len() == 1 {
+                    re.find_iter(&text)
+                        .map(|m| Value::String(m.as_str().to_string()))
+                        .collect()
+                } else {
+                    re.captures_iter(&text).map(|caps| captures_map(&re, &caps)).collect()
+                }))
+            }
+            "gsub" => {
+                let mut arg_vals = Vec::new();
+                for arg in args.iter().take(3) {
+                    arg_vals.push(self.eval_expr(arg)?);
+                }
+                let [subject, pattern, replacement] = <[Value; 3]>::try_from(arg_vals)
+                    .map_err(|_| "gsub() requires a string, a pattern and a replacement".to_string())?;
+                let text = subject.to_string();
+                let pat = match pattern {
+                    Value::Regex(p) | Value::String(p) => p,
+                    other => other.to_string(),
+                };
+                let re = self.regex(&pat)?;
+                let callback = match replacement {
+                    Value::Function(callable) => callable,
+                    // A string replacement may refer to groups as $1, as in s///g.
+                    other => {
+                        let out = re.replace_all(&text, other.to_string().as_str());
+                        return Ok(Value::String(out.into_owned()));
                     }
-                    "sleep" => {
-                        if let Some(arg) = args.first() {
-                            let val = self.eval_expr(arg)?;
-                            let seconds = val.to_int() as u64;
-                            std::thread::sleep(std::time::Duration::from_secs(seconds));
-                            Ok(Value::Nil)
-                        } else {
-                            Ok(Value::Nil)
+                };
+                let mut out = String::with_capacity(text.len());
+                let mut last = 0;
+                for caps in re.captures_iter(&text) {
+                    let whole = caps.get(0).expect("group 0 is the whole match");
+                    let args = vec![
+                        Value::String(whole.as_str().to_string()),
+                        captures_map(&re, &caps),
+                    ];
+                    out.push_str(&text[last..whole.start()]);
+                    out.push_str(&printf::text(&self.call_value(name, &callback, args)?));
+                    last = whole.end();
+                }
+                out.push_str(&text[last..]);
+                Ok(Value::String(out))
+            }
+            "fail" => {
+                let message = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?.to_string(),
+                    None => "fail() called".to_string(),
+                };
+                Err(message)
+            }
+            "prompt" => {
+                let message = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?.to_string(),
+                    None => String::new(),
+                };
+                let default = match args.get(1) {
+                    Some(arg) => Some(self.eval_expr(arg)?),
+                    None => None,
+                };
+                self.write_output(&message)?;
+
+                let line = self.read_input_line()?;
+                match default {
+                    Some(default) if line.trim().is_empty() => Ok(default),
+                    _ => Ok(Value::String(line)),
+                }
+            }
+            "readpass" => {
+                if let Some(arg) = args.first() {
+                    let message = self.eval_expr(arg)?.to_string();
+                    self.write_output(&message)?;
+                }
+                // Piped or host-provided input has no echo to hide.
+                if self.runtime.stdin().is_terminal() {
+                    let line = crate::term::read_password()
+                        .map_err(|e| format!("readpass(): {}", e))?;
+                    self.write_output("\n")?;
+                    Ok(Value::String(line))
+                } else {
+                    Ok(Value::String(self.read_input_line()?))
+                }
+            }
+            "read_all_stdin" => {
+                let mut text = String::new();
+                loop {
+                    let read = self
+                        .runtime
+                        .stdin()
+                        .read_line(&mut text)
+                        .map_err(|e| format!("Failed to read input: {}", e))?;
+                    if read == 0 {
+                        break;
+                    }
+                }
+                Ok(Value::String(text))
+            }
+            "getch" => {
+                if self.runtime.stdin().is_terminal() {
+                    let key = crate::term::read_key().map_err(|e| format!("getch(): {}", e))?;
+                    Ok(Value::String(key))
+                } else {
+                    let key = self
+                        .runtime
+                        .stdin()
+                        .read_char()
+                        .map_err(|e| format!("Failed to read input: {}", e))?;
+                    Ok(Value::String(key.map(String::from).unwrap_or_default()))
+                }
+            }
+            "is_tty" => {
+                let stream = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?.to_string(),
+                    None => "stdout".to_string(),
+                };
+                let terminal = match stream.as_str() {
+                    "stdin" | "0" => self.runtime.stdin().is_terminal(),
+                    "stdout" | "1" => std::io::stdout().is_terminal(),
+                    "stderr" | "2" => std::io::stderr().is_terminal(),
+                    other => {
+                        return Err(format!(
+                            "is_tty(): unknown stream '{}' (use stdin, stdout or stderr)",
+                            other
+                        ))
+                    }
+                };
+                Ok(Value::Bool(terminal))
+            }
+            "term_size" => {
+                let (columns, rows) = crate::term::size();
+                Ok(Value::array(vec![
+                    Value::Int(i64::from(columns)),
+                    Value::Int(i64::from(rows)),
+                ]))
+            }
+            "is_color" => Ok(Value::Bool(crate::term::color_enabled())),
+            "color" | "style" => {
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.eval_expr(arg)?.to_string());
+                }
+                let text = values.first().cloned().unwrap_or_default();
+                let mut codes = Vec::new();
+                for (i, attribute) in values.iter().enumerate().skip(1) {
+                    let attribute = attribute.to_ascii_lowercase();
+                    let code = if name == "style" {
+                        crate::term::style_code(&attribute)
+                    } else if i == 1 {
+                        crate::term::color_code(&attribute)
+                    } else {
+                        // color(text, fg, bg): background codes are 10 higher.
+                        crate::term::color_code(&attribute).map(|code| code + 10)
+                    };
+                    match code {
+                        Some(code) => codes.push(code),
+                        None => return Err(format!("{}(): unknown {} '{}'", name, name, attribute)),
+                    }
+                }
+                if !crate::term::color_enabled() {
+                    codes.clear();
+                }
+                Ok(Value::String(crate::term::paint(&text, &codes)))
+            }
+            "cursor_to" => {
+                let column = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?.to_int(),
+                    None => 1,
+                };
+                let row = match args.get(1) {
+                    Some(arg) => self.eval_expr(arg)?.to_int(),
+                    None => 1,
+                };
+                if crate::term::is_interactive() {
+                    self.write_output(&crate::term::cursor_to(column, row))?;
+                }
+                Ok(Value::Nil)
+            }
+            "clear_screen" | "hide_cursor" | "show_cursor" => {
+                let sequence = match name {
+                    "clear_screen" => crate::term::CLEAR_SCREEN,
+                    "hide_cursor" => crate::term::HIDE_CURSOR,
+                    _ => crate::term::SHOW_CURSOR,
+                };
+                if crate::term::is_interactive() {
+                    self.write_output(sequence)?;
+                }
+                Ok(Value::Nil)
+            }
+            "redraw" => {
+                let mut text = String::new();
+                for arg in args {
+                    text.push_str(&self.eval_expr(arg)?.to_string());
+                }
+                // Redirected output gets one line per update instead.
+                if crate::term::is_interactive() {
+                    self.write_output(&format!("{}{}", crate::term::CLEAR_LINE, text))?;
+                } else {
+                    self.write_output(&format!("{}\n", text))?;
+                }
+                Ok(Value::Nil)
+            }
+            "log_init" => {
+                let mut values = Vec::new();
+                for arg in args.iter().take(3) {
+                    values.push(self.eval_expr(arg)?);
+                }
+                // The settings can also come by name, as a single map.
+                if let Some(Value::Map(options)) = values.first().cloned() {
+                    const KEYS: [&str; 3] = ["level", "file", "format"];
+                    let options = options.borrow();
+                    if let Some(key) = options.keys().find(|key| !KEYS.contains(&key.as_str())) {
+                        return Err(format!(
+                            "log_init(): unknown option '{}', expected one of {}",
+                            key,
+                            KEYS.join(", ")
+                        ));
+                    }
+                    values = KEYS.iter().map(|key| options.get(*key).cloned().unwrap_or(Value::Nil)).collect();
+                }
+                let settings: Vec<String> = values
+                    .iter()
+                    .map(|value| match value {
+                        Value::Nil => String::new(),
+                        value => value.to_string(),
+                    })
+                    .collect();
+                let setting = |i: usize| settings.get(i).map_or("", String::as_str);
+                let level = if setting(0).is_empty() { "info" } else { setting(0) };
+                self.logger = Logger::new(level, setting(1), setting(2))?;
+                Ok(Value::Nil)
+            }
+            "log_debug" | "log_info" | "log_warn" | "log_error" => {
+                let mut message = String::new();
+                for arg in args {
+                    message.push_str(&self.eval_expr(arg)?.to_string());
+                }
+                let level = Level::parse(&name[4..]).unwrap_or(Level::Info);
+                self.logger.log(level, &message, self.runtime.stderr())?;
+                Ok(Value::Nil)
+            }
+            "pow" => {
+                let mut values = Vec::new();
+                for arg in args.iter().take(2) {
+                    values.push(number_arg(name, self.eval_expr(arg)?)?);
+                }
+                match values.as_slice() {
+                    [base, exponent] => self.power(base, exponent),
+                    _ => Err("pow() requires a base and an exponent".to_string()),
+                }
+            }
+            "sqrt" | "abs" => {
+                let value = match args.first() {
+                    Some(arg) => number_arg(name, self.eval_expr(arg)?)?,
+                    None => return Err(format!("{}() requires a number", name)),
+                };
+                Ok(match (name, value) {
+                    ("sqrt", Value::Int(n)) if n >= 0 => Value::Float((n as f64).sqrt()),
+                    ("sqrt", Value::Float(x)) if x >= 0.0 => Value::Float(x.sqrt()),
+                    ("sqrt", Value::BigInt(n)) if n.sign() != Sign::Minus => {
+                        Value::Float(n.to_f64().unwrap_or(f64::INFINITY).sqrt())
+                    }
+                    // Like division by zero, there is no real answer.
+                    ("sqrt", _) => Value::Nil,
+                    (_, Value::Int(n)) => match n.checked_abs() {
+                        Some(n) => Value::Int(n),
+                        None if self.overflow == Overflow::Promote => {
+                            Value::big(BigInt::from(n).abs())
+                        }
+                        None => Value::Float((n as f64).abs()),
+                    },
+                    (_, Value::BigInt(n)) => Value::big(n.abs()),
+                    (_, Value::Float(x)) => Value::Float(x.abs()),
+                    (_, other) => other,
+                })
+            }
+            "min" | "max" => {
+                let wanted = match name {
+                    "min" => std::cmp::Ordering::Less,
+                    _ => std::cmp::Ordering::Greater,
+                };
+                let mut best: Option<Value> = None;
+                for value in self.aggregate_args(args)? {
+                    let replace = match &best {
+                        None => true,
+                        Some(current) => match value.compare(current) {
+                            Some(order) => order == wanted,
+                            None => {
+                                return Err(format!(
+                                    "{}(): cannot compare '{}' with '{}'",
+                                    name, value, current
+                                ))
+                            }
+                        },
+                    };
+                    if replace {
+                        best = Some(value);
+                    }
+                }
+                Ok(best.unwrap_or(Value::Nil))
+            }
+            "sum" | "avg" => {
+                let values = self.aggregate_args(args)?;
+                let count = values.len();
+                let mut total = Value::Int(0);
+                for value in values {
+                    total = self.arithmetic(&BinOp::Add, &total, &number_arg(name, value)?)?;
+                }
+                Ok(match name {
+                    "sum" => total,
+                    _ if count == 0 => Value::Nil,
+                    _ => total.divide(&Value::Float(count as f64)),
+                })
+            }
+            "is_nil" => {
+                let val = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?,
+                    None => Value::Nil,
+                };
+                Ok(Value::Bool(matches!(val, Value::Nil)))
+            }
+            "copy" => match args.first() {
+                Some(arg) => Ok(self.eval_expr(arg)?.deep_copy()),
+                None => Ok(Value::Nil),
+            },
+            "keys" | "values" | "has_key" => {
+                let map = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?,
+                    None => Value::Nil,
+                };
+                let map = match map {
+                    Value::Map(map) => map.borrow().clone(),
+                    Value::Nil => Default::default(),
+                    other => return Err(format!("{}(): expected a map, got '{}'", name, other)),
+                };
+                match name {
+                    "keys" => Ok(Value::array(map.into_keys().map(Value::String).collect())),
+                    "values" => Ok(Value::array(map.into_values().collect())),
+                    _ => {
+                        let key = match args.get(1) {
+                            Some(arg) => self.eval_expr(arg)?.to_string(),
+                            None => String::new(),
+                        };
+                        Ok(Value::Bool(map.contains_key(&key)))
+                    }
+                }
+            }
+            "map" | "filter" | "reduce" => {
+                let mut arg_vals: Vec<Value> = Vec::new();
+                for arg in args {
+                    arg_vals.push(self.eval_expr(arg)?);
+                }
+                let mut arg_vals = arg_vals.into_iter();
+                let array = match arg_vals.next().unwrap_or(Value::Nil) {
+                    // The callback sees a snapshot, so it may change the array.
+                    Value::Array(array) => array.borrow().clone(),
+                    Value::Nil => Vec::new(),
+                    other => return Err(format!("{}(): expected an array, got '{}'", name, other)),
+                };
+                let callback = match arg_vals.next() {
+                    Some(Value::Function(callable)) => callable,
+                    other => {
+                        return Err(format!(
+                            "{}(): expected a function, got '{}'",
+                            name,
+                            other.unwrap_or(Value::Nil)
+                        ))
+                    }
+                };
+                match name {
+                    "map" => {
+                        let mut mapped = Vec::with_capacity(array.len());
+                        for item in array {
+                            mapped.push(self.call_value(name, &callback, vec![item])?);
                         }
+                        Ok(Value::array(mapped))
+                    }
+                    "filter" => {
+                        let mut kept = Vec::new();
+                        for item in array {
+                            if self.call_value(name, &callback, vec![item.clone()])?.is_truthy() {
+                                kept.push(item);
+                            }
+                        }
+                        Ok(Value::array(kept))
                     }
                     _ => {
-                        if let Some((params, body)) = self.runtime.get_function(name) {
-                            // Evaluate args
-                            let mut arg_vals: Vec<Value> = Vec::new();
-                            for arg in args {
-                                arg_vals.push(self.eval_expr(arg)?);
+                        let mut acc = arg_vals.next().unwrap_or(Value::Nil);
+                        for item in array {
+                            acc = self.call_value(name, &callback, vec![acc, item])?;
+                        }
+                        Ok(acc)
+                    }
+                }
+            }
+            "sort" | "sort_by" => {
+                let mut arg_vals: Vec<Value> = Vec::new();
+                for arg in args {
+                    arg_vals.push(self.eval_expr(arg)?);
+                }
+                let mut arg_vals = arg_vals.into_iter();
+                let array = match arg_vals.next().unwrap_or(Value::Nil) {
+                    Value::Array(array) => array.borrow().clone(),
+                    Value::Nil => Vec::new(),
+                    other => return Err(format!("{}(): expected an array, got '{}'", name, other)),
+                };
+                let callback = match arg_vals.next() {
+                    Some(Value::Function(callable)) => Some(callable),
+                    None if name == "sort" => None,
+                    other => {
+                        return Err(format!(
+                            "{}(): expected a function, got '{}'",
+                            name,
+                            other.unwrap_or(Value::Nil)
+                        ))
+                    }
+                };
+                let unordered = |a: &Value, b: &Value| {
+                    format!("{}(): cannot compare '{}' with '{}'", name, a, b)
+                };
+                let sorted = match (name, callback) {
+                    ("sort", None) => merge_sort(array, &mut |a, b| {
+                        a.compare(b).ok_or_else(|| unordered(a, b))
+                    })?,
+                    // The comparator returns a negative number, zero or a positive number.
+                    ("sort", Some(callback)) => merge_sort(array, &mut |a, b| {
+                        let order = self.call_value(name, &callback, vec![a.clone(), b.clone()])?;
+                        Ok(order.to_int().cmp(&0))
+                    })?,
+                    // Each key is computed once, then the elements are sorted by key.
+                    (_, Some(callback)) => {
+                        let mut keyed = Vec::with_capacity(array.len());
+                        for item in array {
+                            let key = self.call_value(name, &callback, vec![item.clone()])?;
+                            keyed.push((key, item));
+                        }
+                        let keyed = merge_sort(keyed, &mut |(a, _), (b, _)| {
+                            a.compare(b).ok_or_else(|| unordered(a, b))
+                        })?;
+                        keyed.into_iter().map(|(_, item)| item).collect()
+                    }
+                    _ => unreachable!(),
+                };
+                Ok(Value::array(sorted))
+            }
+            "reverse" | "unique" | "contains" | "index_of" | "slice" | "flatten" => {
+                let mut arg_vals: Vec<Value> = Vec::new();
+                for arg in args {
+                    arg_vals.push(self.eval_expr(arg)?);
+                }
+                let arg = |i: usize| arg_vals.get(i).cloned().unwrap_or(Value::Nil);
+                // Strings work like arrays of characters, except in flatten().
+                match (name, arg(0)) {
+                    ("reverse", Value::String(s)) => Ok(Value::String(s.chars().rev().collect())),
+                    ("contains", Value::String(s)) => {
+                        Ok(Value::Bool(s.contains(&arg(1).to_string())))
+                    }
+                    ("index_of", Value::String(s)) => Ok(Value::Int(
+                        s.find(&arg(1).to_string())
+                            .map_or(-1, |at| s[..at].chars().count() as i64),
+                    )),
+                    ("slice", Value::String(s)) => {
+                        let chars: Vec<char> = s.chars().collect();
+                        let range = slice_range(slice_bounds(&arg_vals), chars.len());
+                        Ok(Value::String(chars[range].iter().collect()))
+                    }
+                    (_, Value::Array(array)) => {
+                        let items = array.borrow();
+                        Ok(match name {
+                            "reverse" => Value::array(items.iter().rev().cloned().collect()),
+                            "unique" => {
+                                let mut kept: Vec<Value> = Vec::new();
+                                for item in items.iter() {
+                                    if !kept.iter().any(|k| k.equals(item)) {
+                                        kept.push(item.clone());
+                                    }
+                                }
+                                Value::array(kept)
                             }
-
-                            // Bind params
-                            let mut saved: Vec<(String, Option<Value>)> = Vec::new();
-                            for (i, p) in params.iter().enumerate() {
-                                let old = match self.runtime.get_var(p) {
-                                    Value::Nil => None,
-                                    v => Some(v),
-                                };
-                                saved.push((p.clone(), old));
-                                let v = arg_vals.get(i).cloned().unwrap_or(Value::Nil);
-                                self.runtime.set_var(p.clone(), v);
+                            "contains" => {
+                                Value::Bool(items.iter().any(|item| item.equals(&arg(1))))
                             }
-
-                            // Execute
-                            let mut ret: Value = Value::Nil;
-                            for stmt in &body {
-                                if let Ok(Some(val)) = self.execute_statement(stmt) {
-                                    ret = val;
-                                    break;
-                                }
+                            "index_of" => Value::Int(
+                                items
+                                    .iter()
+                                    .position(|item| item.equals(&arg(1)))
+                                    .map_or(-1, |i| i as i64),
+                            ),
+                            "slice" => {
+                                let range = slice_range(slice_bounds(&arg_vals), items.len());
+                                Value::array(items[range].to_vec())
                             }
-
-                            // Restore
-                            for (p, old) in saved.into_iter() {
-                                if let Some(v) = old {
-                                    self.runtime.set_var(p, v);
-                                } else {
-                                    self.runtime.remove_var(&p);
-                                }
+                            _ => {
+                                let depth = arg_vals.get(1).map(Value::to_int);
+                                let mut flat = Vec::new();
+                                flatten_into(&array, depth, &mut Vec::new(), &mut flat)?;
+                                Value::array(flat)
                             }
-
-                            Ok(ret)
+                        })
+                    }
+                    (_, Value::Nil) => Ok(match name {
+                        "contains" => Value::Bool(false),
+                        "index_of" => Value::Int(-1),
+                        _ => Value::array(Vec::new()),
+                    }),
+                    (_, other) => Err(format!("{}(): expected an array, got '{}'", name, other)),
+                }
+            }
+            "ord" | "chr" | "chars" => {
+                let val = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?,
+                    None => Value::Nil,
+                };
+                match (name, val) {
+                    ("chr", Value::Nil) => Ok(Value::Nil),
+                    ("chr", code) => {
+                        let n = code.to_int();
+                        match u32::try_from(n).ok().and_then(char::from_u32) {
+                            Some(c) => Ok(Value::String(c.to_string())),
+                            None => Err(format!("chr(): {} is not a valid character code", n)),
+                        }
+                    }
+                    (_, Value::Nil) if name == "chars" => Ok(Value::array(Vec::new())),
+                    (_, Value::Nil) => Ok(Value::Nil),
+                    ("ord", text) => Ok(text
+                        .to_string()
+                        .chars()
+                        .next()
+                        .map_or(Value::Nil, |c| Value::Int(c as i64))),
+                    (_, text) => Ok(Value::array(
+                        text.to_string().chars().map(|c| Value::String(c.to_string())).collect(),
+                    )),
+                }
+            }
+            "split" | "resplit" | "join" => {
+                let mut arg_vals: Vec<Value> = Vec::new();
+                for arg in args {
+                    arg_vals.push(self.eval_expr(arg)?);
+                }
+                let mut arg_vals = arg_vals.into_iter();
+                let subject = arg_vals.next().unwrap_or(Value::Nil);
+                let separator = arg_vals.next();
+                let pieces = |parts: Vec<&str>| {
+                    Value::array(parts.into_iter().map(|p| Value::String(p.to_string())).collect())
+                };
+                match (name, subject) {
+                    ("join", Value::Array(array)) => {
+                        let sep = separator.map(|sep| sep.to_string()).unwrap_or_default();
+                        let items: Vec<String> = array.borrow().iter().map(printf::text).collect();
+                        Ok(Value::String(items.join(&sep)))
+                    }
+                    ("join", Value::Nil) => Ok(Value::String(String::new())),
+                    ("join", other) => Err(format!("join(): expected an array, got '{}'", other)),
+                    (_, Value::Nil) => Ok(Value::array(Vec::new())),
+                    ("split", subject) => {
+                        let text = subject.to_string();
+                        Ok(match separator.map(|sep| sep.to_string()) {
+                            // Without a separator, split on runs of whitespace.
+                            None => pieces(text.split_whitespace().collect()),
+                            Some(sep) if sep.is_empty() => Value::array(
+                                text.chars().map(|c| Value::String(c.to_string())).collect(),
+                            ),
+                            Some(sep) => pieces(text.split(sep.as_str()).collect()),
+                        })
+                    }
+                    (_, subject) => {
+                        let pat = match separator {
+                            Some(Value::Regex(p)) | Some(Value::String(p)) => p,
+                            Some(other) => other.to_string(),
+                            None => return Err("resplit(): expected a pattern".to_string()),
+                        };
+                        let re = self.regex(&pat)?;
+                        let text = subject.to_string();
+                        Ok(pieces(re.split(&text).collect()))
+                    }
+                }
+            }
+            "concat" => {
+                let mut items = Vec::new();
+                for arg in args {
+                    match self.eval_expr(arg)? {
+                        Value::Array(array) => items.extend(array.borrow().iter().cloned()),
+                        Value::Nil => {}
+                        other => items.push(other),
+                    }
+                }
+                Ok(Value::array(items))
+            }
+            "pop" | "shift" => {
+                let var = array_variable(name, args)?;
+                let removed = match self.runtime.get_var(var) {
+                    Value::Array(elements) => {
+                        let mut elements = elements.borrow_mut();
+                        if elements.is_empty() {
+                            None
+                        } else if name == "pop" {
+                            elements.pop()
                         } else {
-                            eprintln!("Warning: unknown function '{}'", name);
-                            Ok(Value::Nil)
+                            Some(elements.remove(0))
                         }
-                    }}
+                    }
+                    _ => None,
+                };
+                Ok(removed.unwrap_or(Value::Nil))
+            }
+            "insert_at" | "remove_at" | "swap" => {
+                let var = array_variable(name, args)?;
+                let mut values = Vec::new();
+                for arg in &args[1..] {
+                    values.push(self.eval_expr(arg)?);
+                }
+                let array = match self.runtime.get_var(var) {
+                    Value::Array(array) => array,
+                    _ => return Err(format!("{}(): ${} is not an array", name, var)),
+                };
+                let mut elements = array.borrow_mut();
+                let index = |i: usize| values.get(i).map_or(-1, Value::to_int);
+                let in_range = |i: i64, len: usize| usize::try_from(i).ok().filter(|&i| i < len);
+
+                let result = match name {
+                    "insert_at" => {
+                        // Inserting at the length appends.
+                        let at = in_range(index(0), elements.len() + 1).ok_or_else(|| {
+                            format!("insert_at(): index {} out of range", index(0))
+                        })?;
+                        elements.insert(at, values.get(1).cloned().unwrap_or(Value::Nil));
+                        Value::Nil
+                    }
+                    "remove_at" => match in_range(index(0), elements.len()) {
+                        Some(at) => elements.remove(at),
+                        None => return Ok(Value::Nil),
+                    },
+                    _ => {
+                        let (i, j) = match (
+                            in_range(index(0), elements.len()),
+                            in_range(index(1), elements.len()),
+                        ) {
+                            (Some(i), Some(j)) => (i, j),
+                            _ => {
+                                return Err(format!(
+                                    "swap(): index out of range ({}, {})",
+                                    index(0),
+                                    index(1)
+                                ))
+                            }
+                        };
+                        elements.swap(i, j);
+                        Value::Nil
+                    }
+                };
+                Ok(result)
+            }
+            "exit" => {
+                let status = match args.first() {
+                    Some(arg) => {
+                        let value = self.eval_expr(arg)?;
+                        number_arg("exit", value)?.to_int()
+                    }
+                    None => 0,
+                };
+                if !(0..=255).contains(&status) {
+                    return Err(format!("exit(): status {} is not between 0 and 255", status));
+                }
+                // Unwinds like an error, which take_exit_status() tells apart.
+                self.exit_status = Some(status as i32);
+                Err(format!("exit({})", status))
             }
+            "sleep" => {
+                if let Some(arg) = args.first() {
+                    let val = self.eval_expr(arg)?;
+                    let seconds = val.to_int() as u64;
+                    // wasm hosts cannot block; sleeping is a no-op there.
+                    #[cfg(not(target_family = "wasm"))]
+                    std::thread::sleep(std::time::Duration::from_secs(seconds));
+                    #[cfg(target_family = "wasm")]
+                    let _ = seconds;
+                    Ok(Value::Nil)
+                } else {
+                    Ok(Value::Nil)
+                }
+            }
+            #[cfg(not(target_family = "wasm"))]
+            "loadlib" => {
+                let path = match args.first() {
+                    Some(arg) => self.eval_expr(arg)?.to_string(),
+                    None => return Err("loadlib() requires a library path".to_string()),
+                };
+                let resolved = self.resolve_include_path(&path);
+                let (library, functions) = crate::plugin::load(&resolved)?;
+                for (name, function) in functions {
+                    self.register_function(&name, function);
+                }
+                self.plugins.push(library);
+                Ok(Value::Int(1))
+            }
+            #[cfg(target_family = "wasm")]
+            "loadlib" => Err("loadlib() is not supported on this platform".to_string()),
+            _ => {
+                if self.host_functions.contains_key(name) {
+                    let mut arg_vals: Vec<Value> = Vec::new();
+                    for arg in args {
+                        arg_vals.push(self.eval_expr(arg)?);
+                    }
+                    match self.host_functions.get_mut(name) {
+                        Some(function) => function(&arg_vals),
+                        None => Ok(Value::Nil),
+                    }
+                } else if let Some(function) = self.runtime.get_function(name) {
+                    let mut arg_vals: Vec<Value> = Vec::new();
+                    for arg in args {
+                        arg_vals.push(self.eval_expr(arg)?);
+                    }
+                    self.invoke(name, &function, arg_vals)
+                } else if self.strict {
+                    Err(format!("Function '{}' not defined", name))
+                } else {
+                    self.warn(&format!("unknown function '{}'", name));
+                    Ok(Value::Nil)
+                }
+            }}
+    }
+}
+
+/// `pat` with the i, m and s flags among `flags` written into it, as `(?i)pat`
+fn inline_flags(pat: &str, flags: &str) -> String {
+    let mode: String = "ims".chars().filter(|c| flags.contains(*c)).collect();
+    if mode.is_empty() {
+        pat.to_string()
+    } else {
+        format!("(?{}){}", mode, pat)
+    }
+}
+
+/// The groups of a match by number, `0` being the whole match, and also by
+/// name for named groups. Groups that took no part in the match are nil.
+fn captures_map(re: &Regex, caps: &regex::Captures) -> Value {
+    let text = |group: Option<regex::Match>| {
+        group.map_or(Value::Nil, |m| Value::String(m.as_str().to_string()))
+    };
+    let mut groups: BTreeMap<String, Value> =
+        (0..caps.len()).map(|i| (i.to_string(), text(caps.get(i)))).collect();
+    for name in re.capture_names().flatten() {
+        groups.insert(name.to_string(), text(caps.name(name)));
+    }
+    Value::map(groups)
+}
+
+/// `left op right` for + - * / %, wrapping on integer overflow
+fn wrapping_arithmetic(op: &BinOp, left: &Value, right: &Value) -> Value {
+    match op {
+        BinOp::Add => left.add(right),
+        BinOp::Subtract => left.subtract(right),
+        BinOp::Multiply => left.multiply(right),
+        BinOp::Divide => left.divide(right),
+        _ => left.modulo(right),
+    }
+}
+
+/// Position for assigning index `index` past the end of an array
+fn grow_index(index: i64) -> Result<usize, String> {
+    if index >= MAX_ARRAY_LEN {
+        return Err(format!(
+            "Index {} is too large to grow an array to (at most {} items)",
+            index, MAX_ARRAY_LEN
+        ));
+    }
+    Ok(index as usize)
+}
+
+/// A range bound: integers, or booleans as 1 and 0
+fn range_bound(value: Value) -> Result<i64, String> {
+    match value {
+        Value::Int(n) => Ok(n),
+        Value::Bool(b) => Ok(b as i64),
+        other => Err(format!("Range bound '{}' is not an integer", other)),
+    }
+}
+
+/// Position of `index` in a sequence of `len` items, counting from the end
+/// when negative (`-1` is the last item). None when out of range.
+fn sequence_index(index: i64, len: usize) -> Option<usize> {
+    let position = if index < 0 { index + len as i64 } else { index };
+    if (0..len as i64).contains(&position) {
+        Some(position as usize)
+    } else {
+        None
+    }
+}
+
+/// Items `[start, end)` of a sequence of `len` items select. Missing bounds
+/// mean its ends, negative ones count from the end, and bounds past either
+/// end are clamped, so a slice is never out of range.
+fn slice_range([start, end]: [Option<i64>; 2], len: usize) -> std::ops::Range<usize> {
+    let clamp = |bound: i64| {
+        let position = if bound < 0 { bound + len as i64 } else { bound };
+        position.clamp(0, len as i64) as usize
+    };
+    let start = start.map_or(0, clamp);
+    let end = end.map_or(len, clamp);
+    start..end.max(start)
+}
+
+/// What a command printed, without the final newline so pipelines behave
+/// predictably
+fn command_output(bytes: &[u8]) -> String {
+    let mut text = String::from_utf8_lossy(bytes).to_string();
+    if text.ends_with('\n') {
+        text.pop();
+        if text.ends_with('\r') {
+            text.pop();
         }
     }
+    text
+}
+
+/// A numeric argument of a math builtin; numeric strings are converted.
+fn number_arg(function: &str, value: Value) -> Result<Value, String> {
+    match value {
+        Value::Int(_) | Value::BigInt(_) | Value::Float(_) => Ok(value),
+        Value::Bool(b) => Ok(Value::Int(b as i64)),
+        Value::String(ref s) => Value::parse_number(s.trim())
+            .ok_or_else(|| format!("{}(): '{}' is not a number", function, s)),
+        other => Err(format!("{}(): '{}' is not a number", function, other)),
+    }
+}
+
+/// Fold `text` for caseless comparison. Lowercasing alone leaves pairs such as
+/// "Straße"/"STRASSE" or the two Greek final and medial sigmas apart, so the
+/// full foldings that differ from it are applied as well; the result does not
+/// depend on the locale. Canonically equivalent strings fold alike once
+/// normalized, so the text is brought to NFD first and back to NFC after.
+fn casefold(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.nfd() {
+        match c {
+            'ß' | 'ẞ' => out.push_str("ss"),
+            'ς' => out.push('σ'),
+            'ſ' => out.push('s'),
+            'ﬀ' => out.push_str("ff"),
+            'ﬁ' => out.push_str("fi"),
+            'ﬂ' => out.push_str("fl"),
+            'ﬃ' => out.push_str("ffi"),
+            'ﬄ' => out.push_str("ffl"),
+            'ﬅ' | 'ﬆ' => out.push_str("st"),
+            'ŉ' => out.push_str("ʼn"),
+            c => out.extend(c.to_lowercase()),
+        }
+    }
+    out.nfc().collect()
+}
+
+/// The optional start and end arguments of slice(), after the sequence
+fn slice_bounds(args: &[Value]) -> [Option<i64>; 2] {
+    let bound = |i: usize| args.get(i).filter(|v| !matches!(v, Value::Nil)).map(Value::to_int);
+    [bound(1), bound(2)]
+}
+
+/// Append the elements of `array` to `out`, replacing nested arrays with their
+/// elements down to `depth` levels, or all the way when it is None. `path`
+/// holds the arrays being flattened, to catch one that contains itself.
+fn flatten_into(
+    array: &Rc<std::cell::RefCell<Vec<Value>>>,
+    depth: Option<i64>,
+    path: &mut Vec<*const ()>,
+    out: &mut Vec<Value>,
+) -> Result<(), String> {
+    let ptr = Rc::as_ptr(array) as *const ();
+    if depth.is_none() && path.contains(&ptr) {
+        return Err("flatten(): the array contains itself".to_string());
+    }
+    path.push(ptr);
+    for item in array.borrow().iter() {
+        match item {
+            Value::Array(inner) if depth.is_none_or(|depth| depth > 0) => {
+                flatten_into(inner, depth.map(|depth| depth - 1), path, out)?
+            }
+            other => out.push(other.clone()),
+        }
+    }
+    path.pop();
+    Ok(())
+}
+
+/// Stable merge sort with a comparison that can fail, such as a user function.
+/// Unlike `sort_by`, it copes with a comparison that is not a total order.
+fn merge_sort<T>(
+    items: Vec<T>,
+    compare: &mut dyn FnMut(&T, &T) -> Result<std::cmp::Ordering, String>,
+) -> Result<Vec<T>, String> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let mut left = items;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(left, compare)?;
+    let right = merge_sort(right, compare)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Ties take from the left, which keeps equal elements in order.
+        if compare(a, b)? == std::cmp::Ordering::Greater {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// Variable named by the first argument of a builtin that modifies an array in place
+fn array_variable<'a>(name: &str, args: &'a [Expr]) -> Result<&'a String, String> {
+    match args.first() {
+        Some(Expr::Variable(var)) => Ok(var),
+        _ => Err(format!("{}() requires an array variable", name)),
+    }
+}
+
+/// Insert separators into a plain number: `-1234567.89` becomes `-1,234,567.89`
+fn group_digits(digits: &str, thousands_sep: &str, decimal_sep: &str) -> String {
+    let (sign, unsigned) = match digits.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", digits),
+    };
+    let (whole, fraction) = match unsigned.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (unsigned, None),
+    };
+
+    let mut out = String::from(sign);
+    for (i, ch) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            out.push_str(thousands_sep);
+        }
+        out.push(ch);
+    }
+    if let Some(fraction) = fraction {
+        out.push_str(decimal_sep);
+        out.push_str(fraction);
+    }
+    out
 }
@@ -0,0 +1,538 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! `minilux kernel`: a Jupyter kernel. Speaks the Jupyter messaging protocol
+//! over a minimal ZeroMQ (ZMTP 3.0, NULL security) implementation, so no
+//! libzmq is needed. One interpreter is shared by all cells of a notebook.
+
+use crate::diagnostics;
+use crate::host::OutputBuffer;
+use crate::interpreter::{Interpreter, BUILTIN_FUNCTIONS};
+use crate::log::timestamp;
+use crate::runtime::VERSION;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value as Json};
+use sha2::Sha256;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+const PROTOCOL_VERSION: &str = "5.3";
+/// Larger frames are refused rather than allocated
+const MAX_FRAME_SIZE: usize = 8 << 20;
+
+/// Serve the notebook described by a Jupyter connection file.
+pub fn run(connection_file: &Path, modules_spec: Option<&str>) -> Result<(), String> {
+    let text = fs::read_to_string(connection_file)
+        .map_err(|e| format!("Failed to read {}: {}", connection_file.display(), e))?;
+    let info: Json =
+        serde_json::from_str(&text).map_err(|e| format!("Invalid connection file: {}", e))?;
+    if info["transport"].as_str().unwrap_or("tcp") != "tcp" {
+        return Err("only the tcp transport is supported".to_string());
+    }
+    let ip = info["ip"].as_str().unwrap_or("127.0.0.1");
+    let bind = |name: &str| -> Result<TcpListener, String> {
+        let port = info[name].as_u64().unwrap_or(0);
+        TcpListener::bind((ip, port as u16))
+            .map_err(|e| format!("Failed to bind {} on {}:{}: {}", name, ip, port, e))
+    };
+
+    let (sender, requests) = mpsc::channel();
+    serve_router(bind("shell_port")?, sender.clone());
+    serve_router(bind("control_port")?, sender.clone());
+    serve_router(bind("stdin_port")?, sender);
+    serve_heartbeat(bind("hb_port")?);
+    let iopub = serve_publisher(bind("iopub_port")?);
+
+    let output = OutputBuffer::new();
+    let errors = OutputBuffer::new();
+    let mut interpreter = Interpreter::new();
+    if let Some(spec) = modules_spec {
+        interpreter.set_modules_path(spec);
+    }
+    interpreter.set_stdout(Box::new(output.clone()));
+    interpreter.set_stderr(Box::new(errors.clone()));
+    interpreter.set_stdin(Box::new(io::empty()));
+
+    let mut kernel = Kernel {
+        key: info["key"].as_str().unwrap_or("").as_bytes().to_vec(),
+        session: new_id(),
+        iopub,
+        interpreter,
+        output,
+        errors,
+        execution_count: 0,
+    };
+    kernel.publish("status", &Json::Null, json!({ "execution_state": "starting" }));
+
+    for (mut stream, frames) in requests {
+        if let Some(request) = kernel.parse(frames) {
+            if !kernel.handle(&mut stream, &request) {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Register the kernel with Jupyter for the current user.
+pub fn install() -> Result<PathBuf, String> {
+    let exe = env::current_exe().map_err(|e| format!("Cannot locate minilux: {}", e))?;
+    let dir = kernels_dir()
+        .ok_or("Cannot locate the Jupyter data directory")?
+        .join("minilux");
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let spec = json!({
+        "argv": [exe.display().to_string(), "kernel", "{connection_file}"],
+        "display_name": "Minilux",
+        "language": "minilux",
+    });
+    let path = dir.join("kernel.json");
+    fs::write(&path, format!("{:#}\n", spec))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+fn kernels_dir() -> Option<PathBuf> {
+    if let Some(data) = env::var_os("JUPYTER_DATA_DIR") {
+        return Some(PathBuf::from(data).join("kernels"));
+    }
+    if cfg!(windows) {
+        return env::var_os("APPDATA").map(|d| PathBuf::from(d).join("jupyter").join("kernels"));
+    }
+    let home = PathBuf::from(env::var_os("HOME")?);
+    if cfg!(target_os = "macos") {
+        Some(home.join("Library").join("Jupyter").join("kernels"))
+    } else {
+        Some(home.join(".local").join("share").join("jupyter").join("kernels"))
+    }
+}
+
+/// A request received on the shell, control or stdin channel
+struct Request {
+    identities: Vec<Vec<u8>>,
+    header: Json,
+    content: Json,
+}
+
+struct Kernel {
+    key: Vec<u8>,
+    session: String,
+    iopub: Arc<Mutex<Vec<TcpStream>>>,
+    interpreter: Interpreter,
+    output: OutputBuffer,
+    /// Warnings and log records, sent as a `stderr` stream
+    errors: OutputBuffer,
+    execution_count: u64,
+}
+
+impl Kernel {
+    fn parse(&self, frames: Vec<Vec<u8>>) -> Option<Request> {
+        let at = frames.iter().position(|f| f == DELIMITER)?;
+        let parts = frames.get(at + 1..at + 6)?;
+        if !self.key.is_empty() && !self.verify(&parts[0], &parts[1..]) {
+            return None;
+        }
+        Some(Request {
+            identities: frames[..at].to_vec(),
+            header: serde_json::from_slice(&parts[1]).ok()?,
+            content: serde_json::from_slice(&parts[4]).ok()?,
+        })
+    }
+
+    /// Handle one request; returns false once the kernel should exit.
+    fn handle(&mut self, stream: &mut TcpStream, request: &Request) -> bool {
+        let msg_type = request.header["msg_type"].as_str().unwrap_or("");
+        let reply_type = msg_type.replace("_request", "_reply");
+        let parent = &request.header;
+        self.publish("status", parent, json!({ "execution_state": "busy" }));
+
+        let (content, keep_running) = match msg_type {
+            "kernel_info_request" => (kernel_info(), true),
+            "execute_request" => (self.execute(parent, &request.content), true),
+            "is_complete_request" => (json!({ "status": "complete" }), true),
+            "complete_request" => (complete(&request.content), true),
+            "inspect_request" => (
+                json!({ "status": "ok", "found": false, "data": {}, "metadata": {} }),
+                true,
+            ),
+            "history_request" => (json!({ "status": "ok", "history": [] }), true),
+            "comm_info_request" => (json!({ "status": "ok", "comms": {} }), true),
+            "interrupt_request" => (json!({ "status": "ok" }), true),
+            "shutdown_request" => {
+                let restart = request.content["restart"].as_bool().unwrap_or(false);
+                (json!({ "status": "ok", "restart": restart }), false)
+            }
+            _ => (json!({ "status": "error", "ename": "UnknownRequest", "evalue": msg_type, "traceback": [] }), true),
+        };
+
+        let frames = self.message(&request.identities, &reply_type, parent, content);
+        write_message(stream, &frames).ok();
+        self.publish("status", parent, json!({ "execution_state": "idle" }));
+        keep_running
+    }
+
+    fn execute(&mut self, parent: &Json, content: &Json) -> Json {
+        let code = content["code"].as_str().unwrap_or("");
+        let silent = content["silent"].as_bool().unwrap_or(false);
+        if !silent {
+            self.execution_count += 1;
+        }
+        let count = self.execution_count;
+        self.publish(
+            "execute_input",
+            parent,
+            json!({ "code": code, "execution_count": count }),
+        );
+
+        let result = self.interpreter.execute_source(code);
+
+        let warnings = self.errors.take_string();
+        if !warnings.is_empty() && !silent {
+            self.publish("stream", parent, json!({ "name": "stderr", "text": warnings }));
+        }
+        let text = self.output.take_string();
+        if !text.is_empty() && !silent {
+            if is_html(&text) {
+                self.publish(
+                    "display_data",
+                    parent,
+                    json!({ "data": { "text/html": text, "text/plain": text }, "metadata": {} }),
+                );
+            } else {
+                self.publish("stream", parent, json!({ "name": "stdout", "text": text }));
+            }
+        }
+
+        match result {
+            Ok(()) => json!({
+                "status": "ok",
+                "execution_count": count,
+                "user_expressions": {},
+                "payload": [],
+            }),
+            Err(message) => {
+                let trace = self.interpreter.take_error_trace();
+                let location = self
+                    .interpreter
+                    .take_error_location()
+                    .filter(|(file, _)| file.is_none())
+                    .map(|(_, span)| diagnostics::Location {
+                        name: "<cell>",
+                        source: code,
+                        span,
+                    });
+                let traceback = diagnostics::render_error(&message, location, true)
+                    + &diagnostics::render_trace(&trace, "<cell>", true);
+                let error = json!({
+                    "ename": "Error",
+                    "evalue": message,
+                    "traceback": traceback.lines().collect::<Vec<_>>(),
+                });
+                self.publish("error", parent, error.clone());
+                let mut reply = error;
+                reply["status"] = json!("error");
+                reply["execution_count"] = json!(count);
+                reply
+            }
+        }
+    }
+
+    fn publish(&self, msg_type: &str, parent: &Json, content: Json) {
+        let topic = format!("kernel.{}.{}", self.session, msg_type).into_bytes();
+        let frames = self.message(&[topic], msg_type, parent, content);
+        if let Ok(mut subscribers) = self.iopub.lock() {
+            subscribers.retain_mut(|s| write_message(s, &frames).is_ok());
+        }
+    }
+
+    fn message(&self, identities: &[Vec<u8>], msg_type: &str, parent: &Json, content: Json) -> Vec<Vec<u8>> {
+        let header = json!({
+            "msg_id": new_id(),
+            "session": self.session,
+            "username": "minilux",
+            "date": timestamp(),
+            "msg_type": msg_type,
+            "version": PROTOCOL_VERSION,
+        });
+        let parts: Vec<Vec<u8>> = [&header, parent, &json!({}), &content]
+            .iter()
+            .map(|part| part.to_string().into_bytes())
+            .collect();
+
+        let mut frames = identities.to_vec();
+        frames.push(DELIMITER.to_vec());
+        frames.push(self.sign(&parts).into_bytes());
+        frames.extend(parts);
+        frames
+    }
+
+    /// HMAC-SHA256 of the header, parent header, metadata and content frames
+    fn sign(&self, parts: &[Vec<u8>]) -> String {
+        if self.key.is_empty() {
+            return String::new();
+        }
+        let Some(mac) = self.mac(parts) else {
+            return String::new();
+        };
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Check a hex `signature` of `parts` in constant time
+    fn verify(&self, signature: &[u8], parts: &[Vec<u8>]) -> bool {
+        match (hex_decode(signature), self.mac(parts)) {
+            (Some(signature), Some(mac)) => mac.verify_slice(&signature).is_ok(),
+            _ => false,
+        }
+    }
+
+    /// The HMAC of the header, parent header, metadata and content frames
+    fn mac(&self, parts: &[Vec<u8>]) -> Option<Hmac<Sha256>> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).ok()?;
+        for part in parts.iter().take(4) {
+            mac.update(part);
+        }
+        Some(mac)
+    }
+}
+
+fn kernel_info() -> Json {
+    json!({
+        "status": "ok",
+        "protocol_version": PROTOCOL_VERSION,
+        "implementation": "minilux",
+        "implementation_version": VERSION,
+        "language_info": {
+            "name": "minilux",
+            "version": VERSION,
+            "mimetype": "text/x-minilux",
+            "file_extension": ".mi",
+        },
+        "banner": format!("Minilux {}", VERSION),
+        "help_links": [],
+    })
+}
+
+/// Output that is a single HTML fragment is displayed as rich HTML.
+fn is_html(text: &str) -> bool {
+    let text = text.trim();
+    text.starts_with('<') && text.ends_with('>') && text.contains("</")
+}
+
+/// Complete builtin names from the word before the cursor.
+fn complete(content: &Json) -> Json {
+    let code = content["code"].as_str().unwrap_or("");
+    let cursor = (content["cursor_pos"].as_u64().unwrap_or(0) as usize).min(code.chars().count());
+    let before: Vec<char> = code.chars().take(cursor).collect();
+    let start = before
+        .iter()
+        .rposition(|c| !(c.is_alphanumeric() || *c == '_'))
+        .map_or(0, |i| i + 1);
+    let word: String = before[start..].iter().collect();
+    let matches: Vec<&str> = BUILTIN_FUNCTIONS
+        .iter()
+        .copied()
+        .filter(|name| !word.is_empty() && name.starts_with(word.as_str()))
+        .collect();
+    json!({
+        "status": "ok",
+        "matches": matches,
+        "cursor_start": start,
+        "cursor_end": cursor,
+        "metadata": {},
+    })
+}
+
+fn new_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!(
+        "{:016x}-{:x}-{:x}",
+        nanos,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+// --- ZMTP 3.0 -------------------------------------------------------------
+
+/// Accept ROUTER peers; their messages are forwarded with a handle to reply on.
+fn serve_router(listener: TcpListener, requests: Sender<(TcpStream, Vec<Vec<u8>>)>) {
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let requests = requests.clone();
+            thread::spawn(move || {
+                if handshake(&mut stream, "ROUTER").is_err() {
+                    return;
+                }
+                while let Ok(frames) = read_message(&mut stream) {
+                    let Ok(reply) = stream.try_clone() else { break };
+                    if requests.send((reply, frames)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// Heartbeat (REP): echo every message back unchanged.
+fn serve_heartbeat(listener: TcpListener) {
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                if handshake(&mut stream, "REP").is_err() {
+                    return;
+                }
+                while let Ok(frames) = read_message(&mut stream) {
+                    if write_message(&mut stream, &frames).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// IOPub (PUB): every subscriber gets every message; topics are not filtered.
+fn serve_publisher(listener: TcpListener) -> Arc<Mutex<Vec<TcpStream>>> {
+    let subscribers = Arc::new(Mutex::new(Vec::new()));
+    let shared = Arc::clone(&subscribers);
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                if handshake(&mut stream, "PUB").is_err() {
+                    return;
+                }
+                let Ok(writer) = stream.try_clone() else { return };
+                if let Ok(mut subscribers) = shared.lock() {
+                    subscribers.push(writer);
+                }
+                // Drain subscription messages until the peer goes away.
+                while read_message(&mut stream).is_ok() {}
+            });
+        }
+    });
+    subscribers
+}
+
+/// Exchange greetings and READY commands using the NULL mechanism.
+fn handshake(stream: &mut TcpStream, socket_type: &str) -> io::Result<()> {
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    stream.write_all(&greeting)?;
+
+    let mut peer = [0u8; 64];
+    stream.read_exact(&mut peer)?;
+    if peer[0] != 0xff || peer[9] != 0x7f || peer[10] < 3 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a ZMTP 3 peer"));
+    }
+
+    let mut ready = vec![5u8];
+    ready.extend_from_slice(b"READY");
+    ready.push(11);
+    ready.extend_from_slice(b"Socket-Type");
+    ready.extend_from_slice(&(socket_type.len() as u32).to_be_bytes());
+    ready.extend_from_slice(socket_type.as_bytes());
+    write_frame(stream, 0x04, &ready)?;
+
+    // The peer's READY; its properties are not needed.
+    read_frame(stream).map(|_| ())
+}
+
+/// Read the frames of one message, skipping commands.
+fn read_message(stream: &mut TcpStream) -> io::Result<Vec<Vec<u8>>> {
+    let mut frames = Vec::new();
+    loop {
+        let (flags, body) = read_frame(stream)?;
+        if flags & 0x04 != 0 {
+            continue;
+        }
+        frames.push(body);
+        if flags & 0x01 == 0 {
+            return Ok(frames);
+        }
+    }
+}
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0u8; 1];
+    stream.read_exact(&mut flags)?;
+    let size = if flags[0] & 0x02 != 0 {
+        let mut size = [0u8; 8];
+        stream.read_exact(&mut size)?;
+        u64::from_be_bytes(size) as usize
+    } else {
+        let mut size = [0u8; 1];
+        stream.read_exact(&mut size)?;
+        size[0] as usize
+    };
+    // The size comes from the peer before any signature is checked.
+    if size > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is over the {} byte limit", size, MAX_FRAME_SIZE),
+        ));
+    }
+    let mut body = vec![0u8; size];
+    stream.read_exact(&mut body)?;
+    Ok((flags[0], body))
+}
+
+fn hex_decode(text: &[u8]) -> Option<Vec<u8>> {
+    let digit = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    text.chunks(2)
+        .map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?))
+        .collect()
+}
+
+fn write_message(stream: &mut TcpStream, frames: &[Vec<u8>]) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    for (i, frame) in frames.iter().enumerate() {
+        let more = if i + 1 < frames.len() { 0x01 } else { 0 };
+        encode_frame(&mut buffer, more, frame);
+    }
+    stream.write_all(&buffer)
+}
+
+fn write_frame(stream: &mut TcpStream, flags: u8, body: &[u8]) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    encode_frame(&mut buffer, flags, body);
+    stream.write_all(&buffer)
+}
+
+fn encode_frame(buffer: &mut Vec<u8>, flags: u8, body: &[u8]) {
+    if body.len() > 255 {
+        buffer.push(flags | 0x02);
+        buffer.extend_from_slice(&(body.len() as u64).to_be_bytes());
+    } else {
+        buffer.push(flags);
+        buffer.push(body.len() as u8);
+    }
+    buffer.extend_from_slice(body);
+}
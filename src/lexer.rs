@@ -4,6 +4,7 @@
 // License: MPL 2.0
 // SPDX-License-Identifier: MPL-2.0
 // - Control
+use crate::value::Value;
 use std::iter::Peekable;
 use std::str::Chars;
 
@@ -11,16 +12,25 @@ use std::str::Chars;
 pub enum Token {
     // Literals
     Int(i64),
+    Float(f64),
+    True,
+    False,
     String(String),
-    Regex(String),
+    Regex { pat: String, flags: String },
     Subst { pat: String, repl: String, flags: String },
     Variable(String),
+    /// A `#` or `/* */` comment, as written (only from `Lexer::with_comments`)
+    Comment(String),
+    /// Input the lexer cannot make sense of, such as an unterminated comment
+    Error(String),
 
     // Keywords
     If,
     Elseif,
     Else,
     While,
+    For,
+    In,
     Printf,
     Shell,
     Len,
@@ -37,6 +47,10 @@ pub enum Token {
     Sockwrite,
     Sockread,
     Sockstatus,
+    Serialopen,
+    Serialclose,
+    Serialwrite,
+    Serialread,
     Read,
     Lower,
     Upper,
@@ -44,6 +58,13 @@ pub enum Token {
     Include,
     Function,
     Return,
+    Global,
+    Strict,
+    Overflow,
+    Try,
+    Catch,
+    Finally,
+    Throw,
     And,
     Or,
     Not,
@@ -55,6 +76,11 @@ pub enum Token {
     MinusEquals,
     Star,
     StarEquals,
+    StarStar,
+    QuestionQuestion,
+    QuestionBracket,
+    DotDot,
+    DotDotLess,
     Slash,
     SlashEquals,
     Percent,
@@ -79,6 +105,7 @@ pub enum Token {
     RightBracket,
     Semicolon,
     Comma,
+    Colon,
     Dot,
 
     // Special
@@ -86,32 +113,167 @@ pub enum Token {
     Eof,
 }
 
+/// Source position of a token (1-based lines and columns, end is exclusive)
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+// Written as in diagnostics, so dumped syntax trees stay readable.
+impl std::fmt::Debug for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}:{}",
+            self.line, self.column, self.end_line, self.end_column
+        )
+    }
+}
+
+impl std::fmt::Display for Token {
+    /// The token as it would appear in source, for error messages
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let text = match self {
+            Token::Int(n) => return write!(f, "{}", n),
+            Token::Float(x) => return write!(f, "{}", x),
+            Token::String(s) => return write!(f, "\"{}\"", s),
+            Token::Regex { pat, flags } => return write!(f, "/{}/{}", pat, flags),
+            Token::Subst { pat, repl, flags } => return write!(f, "s/{}/{}/{}", pat, repl, flags),
+            Token::Variable(name) => return write!(f, "{}", name),
+            Token::Comment(text) | Token::Error(text) => return write!(f, "{}", text),
+            Token::And => "AND",
+            Token::Or => "OR",
+            Token::Not => "!",
+            Token::Plus => "+",
+            Token::PlusEquals => "+=",
+            Token::Minus => "-",
+            Token::MinusEquals => "-=",
+            Token::Star => "*",
+            Token::StarEquals => "*=",
+            Token::StarStar => "**",
+            Token::QuestionQuestion => "??",
+            Token::QuestionBracket => "?[",
+            Token::DotDot => "..",
+            Token::DotDotLess => "..<",
+            Token::Slash => "/",
+            Token::SlashEquals => "/=",
+            Token::Percent => "%",
+            Token::Equals => "=",
+            Token::EqualEqual => "==",
+            Token::NotEqual => "!=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::Ampersand => "&",
+            Token::Pipe => "|",
+            Token::At => "@",
+            Token::Match => "=~",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::Semicolon => ";",
+            Token::Comma => ",",
+            Token::Colon => ":",
+            Token::Dot => ".",
+            Token::Newline => "end of line",
+            Token::Eof => "end of file",
+            // Keywords are written as their lowercase names.
+            keyword => return write!(f, "{}", format!("{:?}", keyword).to_lowercase()),
+        };
+        write!(f, "{}", text)
+    }
+}
+
 pub struct Lexer<'a> {
     input: Peekable<Chars<'a>>,
     current: Option<char>,
     last_can_end_expr: bool,
+    keep_comments: bool,
+    line: usize,
+    column: usize,
+    token_start: (usize, usize),
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
+        let mut lexer = Self::build(input, false);
+        lexer.skip_shebang();
+        lexer
+    }
+
+    /// Lexer that returns comments (and a shebang line) as `Token::Comment`
+    /// instead of skipping them
+    pub fn with_comments(input: &'a str) -> Self {
+        Self::build(input, true)
+    }
+
+    fn build(input: &'a str, keep_comments: bool) -> Self {
         let mut lexer = Lexer {
             input: input.chars().peekable(),
             current: None,
             last_can_end_expr: false,
+            keep_comments,
+            line: 1,
+            column: 1,
+            token_start: (1, 1),
         };
         lexer.advance();
         lexer
     }
 
+    /// Skip a leading `#!...` line so scripts can be made directly executable.
+    fn skip_shebang(&mut self) {
+        if self.current == Some('#') && self.peek() == Some('!') {
+            while self.current.is_some() && self.current != Some('\n') {
+                self.advance();
+            }
+        }
+    }
+
     fn advance(&mut self) {
+        if let Some(ch) = self.current {
+            if ch == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
         self.current = self.input.next();
     }
 
-    #[allow(dead_code)]
+    /// Span of the most recently returned token
+    fn last_span(&self) -> Span {
+        Span {
+            line: self.token_start.0,
+            column: self.token_start.1,
+            end_line: self.line,
+            end_column: self.column,
+        }
+    }
+
     fn peek(&mut self) -> Option<char> {
         self.input.peek().copied()
     }
 
+    /// The character after the one `peek` returns
+    fn peek_second(&self) -> Option<char> {
+        self.input.clone().nth(1)
+    }
+
     fn skip_whitespace(&mut self) {
         while let Some(ch) = self.current {
             if ch == ' ' || ch == '\t' || ch == '\r' {
@@ -130,6 +292,42 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Read a `/* ... */` comment, which may span lines and nest. None if
+    /// the input ends before it is closed.
+    fn read_block_comment(&mut self) -> Option<String> {
+        let mut text = String::new();
+        let mut depth = 0;
+        while let Some(ch) = self.current {
+            text.push(ch);
+            self.advance();
+            if ch == '/' && self.current == Some('*') {
+                text.push('*');
+                self.advance();
+                depth += 1;
+            } else if ch == '*' && self.current == Some('/') {
+                text.push('/');
+                self.advance();
+                depth -= 1;
+                if depth == 0 {
+                    return Some(text);
+                }
+            }
+        }
+        None
+    }
+
+    fn read_comment(&mut self) -> String {
+        let mut text = String::new();
+        while let Some(ch) = self.current {
+            if ch == '\n' {
+                break;
+            }
+            text.push(ch);
+            self.advance();
+        }
+        text.trim_end().to_string()
+    }
+
     fn read_string(&mut self, quote: char) -> String {
         let mut result = String::new();
         self.advance();
@@ -140,7 +338,7 @@ impl<'a> Lexer<'a> {
                 break;
             } else if ch == '\\' {
                 self.advance();
-                let _tok = match self.current {
+                match self.current {
                     Some('n') => result.push('\n'),
                     Some('t') => result.push('\t'),
                     Some('r') => result.push('\r'),
@@ -172,7 +370,7 @@ fn read_regex(&mut self) -> String {
         } else if ch == '\\' {
             // Keep escapes so the regex engine sees them.
             self.advance();
-            let _tok = match self.current {
+            match self.current {
                 Some('/') => {
                     result.push('/');
                     self.advance();
@@ -252,17 +450,54 @@ fn read_subst_replacement(&mut self) -> String {
 }
 
 
-    fn read_number(&mut self) -> i64 {
+    /// A number literal: `42`, `1_000_000`, `2.5`, `0x1F`, `0o17` or `0b1010`
+    fn read_number(&mut self) -> Token {
         let mut num_str = String::new();
+        let mut is_float = false;
+        let mut radix = 10;
+        if self.current == Some('0') {
+            if let Some(prefix @ ('x' | 'X' | 'o' | 'O' | 'b' | 'B')) = self.peek() {
+                let prefix_radix = match prefix.to_ascii_lowercase() {
+                    'x' => 16,
+                    'o' => 8,
+                    _ => 2,
+                };
+                // `0b` only starts a literal when a digit follows.
+                if self.peek_second().is_some_and(|c| c.is_digit(prefix_radix)) {
+                    radix = prefix_radix;
+                    num_str.push('0');
+                    num_str.push(prefix);
+                    self.advance();
+                    self.advance();
+                }
+            }
+        }
         while let Some(ch) = self.current {
-            if ch.is_ascii_digit() {
+            // An underscore between digits groups them.
+            if ch.is_digit(radix) || (ch == '_' && self.peek().is_some_and(|c| c.is_digit(radix))) {
+                num_str.push(ch);
+                self.advance();
+            } else if ch == '.'
+                && radix == 10
+                && !is_float
+                && self.peek().is_some_and(|c| c.is_ascii_digit())
+            {
+                is_float = true;
                 num_str.push(ch);
                 self.advance();
             } else {
                 break;
             }
         }
-        num_str.parse().unwrap_or(0)
+        match Value::parse_number(&num_str) {
+            Some(Value::Int(n)) => Token::Int(n),
+            Some(Value::Float(x)) => Token::Float(x),
+            // Only hex, octal and binary literals can fail; long decimal ones are floats.
+            _ => Token::Error(format!(
+                "integer literal {} is out of range; bigint(\"{}\") holds any size",
+                num_str, num_str
+            )),
+        }
     }
 
     fn read_identifier(&mut self) -> String {
@@ -282,15 +517,32 @@ fn read_subst_replacement(&mut self) -> String {
         loop {
             self.skip_whitespace();
 
-            if self.current == Some('#') {
+            if self.current == Some('#') && !self.keep_comments {
                 self.skip_comment();
                 continue;
             }
+            // A block comment is whitespace, wherever it appears.
+            if self.current == Some('/') && self.peek() == Some('*') && !self.keep_comments {
+                self.token_start = (self.line, self.column);
+                if self.read_block_comment().is_none() {
+                    return Token::Error("unterminated block comment".to_string());
+                }
+                continue;
+            }
             break;
         }
 
+        self.token_start = (self.line, self.column);
+        let next = self.peek();
+        let block_comment = self.current == Some('/') && next == Some('*');
+
         let tok = match self.current {
             None => Token::Eof,
+            Some('#') => Token::Comment(self.read_comment()),
+            Some('/') if block_comment => match self.read_block_comment() {
+                Some(text) => Token::Comment(text),
+                None => Token::Error("unterminated block comment".to_string()),
+            },
             Some('\n') => {
                 self.advance();
                 Token::Newline
@@ -318,6 +570,9 @@ fn read_subst_replacement(&mut self) -> String {
                 if self.current == Some('=') {
                     self.advance();
                     Token::StarEquals
+                } else if self.current == Some('*') {
+                    self.advance();
+                    Token::StarStar
                 } else {
                     Token::Star
                 }
@@ -332,7 +587,17 @@ fn read_subst_replacement(&mut self) -> String {
                           Token::Slash
                       }
                   } else {
-                      Token::Regex(self.read_regex())
+                      let pat = self.read_regex();
+                      let mut flags = String::new();
+                      while let Some(ch) = self.current {
+                          if matches!(ch, 'i' | 'm' | 's') {
+                              flags.push(ch);
+                              self.advance();
+                          } else {
+                              break;
+                          }
+                      }
+                      Token::Regex { pat, flags }
                   }
               }
             Some('%') => {
@@ -351,6 +616,16 @@ fn read_subst_replacement(&mut self) -> String {
                     Token::Equals
                 }
             }
+            Some('?') if matches!(next, Some('?') | Some('[')) => {
+                self.advance();
+                let token = if self.current == Some('?') {
+                    Token::QuestionQuestion
+                } else {
+                    Token::QuestionBracket
+                };
+                self.advance();
+                token
+            }
             Some('!') => {
                 self.advance();
                 if self.current == Some('=') {
@@ -439,11 +714,25 @@ fn read_subst_replacement(&mut self) -> String {
             }
             Some('.') => {
                 self.advance();
-                Token::Dot
+                if self.current == Some('.') {
+                    self.advance();
+                    if self.current == Some('<') {
+                        self.advance();
+                        Token::DotDotLess
+                    } else {
+                        Token::DotDot
+                    }
+                } else {
+                    Token::Dot
+                }
+            }
+            Some(':') => {
+                self.advance();
+                Token::Colon
             }
             Some('"') => Token::String(self.read_string('"')),
             Some('\'') => Token::String(self.read_string('\'')),
-            Some(ch) if ch.is_ascii_digit() => Token::Int(self.read_number()),
+            Some(ch) if ch.is_ascii_digit() => self.read_number(),
 
 Some('s') => {
     // Substitution literal: s/pat/repl/flags  (used as s/.../.../g(<expr>))
@@ -486,6 +775,10 @@ Some('s') => {
             "sockwrite" => Token::Sockwrite,
             "sockread" => Token::Sockread,
             "sockstatus" => Token::Sockstatus,
+            "serialopen" => Token::Serialopen,
+            "serialclose" => Token::Serialclose,
+            "serialwrite" => Token::Serialwrite,
+            "serialread" => Token::Serialread,
             "read" => Token::Read,
             "lower" => Token::Lower,
             "upper" => Token::Upper,
@@ -493,6 +786,7 @@ Some('s') => {
             "include" => Token::Include,
             "function" | "func" => Token::Function,
             "return" => Token::Return,
+            "strict" => Token::Strict,
             "AND" => Token::And,
             "OR" => Token::Or,
             _ => Token::Variable(ident),
@@ -507,6 +801,8 @@ Some('s') => {
                     "elseif" => Token::Elseif,
                     "else" => Token::Else,
                     "while" => Token::While,
+                    "for" => Token::For,
+                    "in" => Token::In,
                     "printf" | "print" => Token::Printf,
                     "shell" => Token::Shell,
                     "len" => Token::Len,
@@ -523,6 +819,10 @@ Some('s') => {
                     "sockwrite" => Token::Sockwrite,
                     "sockread" => Token::Sockread,
                     "sockstatus" => Token::Sockstatus,
+                    "serialopen" => Token::Serialopen,
+                    "serialclose" => Token::Serialclose,
+                    "serialwrite" => Token::Serialwrite,
+                    "serialread" => Token::Serialread,
                     "read" => Token::Read,
                     "lower" => Token::Lower,
                     "upper" => Token::Upper,
@@ -530,6 +830,15 @@ Some('s') => {
                     "include" => Token::Include,
                     "function" | "func" => Token::Function,
                     "return" => Token::Return,
+                    "global" => Token::Global,
+                    "strict" => Token::Strict,
+                    "overflow" => Token::Overflow,
+                    "try" => Token::Try,
+                    "catch" => Token::Catch,
+                    "finally" => Token::Finally,
+                    "throw" => Token::Throw,
+                    "true" => Token::True,
+                    "false" => Token::False,
                     "AND" => Token::And,
                     "OR" => Token::Or,
                     _ => Token::Variable(ident),
@@ -540,11 +849,18 @@ Some('s') => {
                 self.next_token()
             }    };
 
+    // Comments leave the previous token deciding what '/' means next.
+    if matches!(tok, Token::Comment(_)) {
+        return tok;
+    }
     self.last_can_end_expr = matches!(
         tok,
         Token::Int(_)
+            | Token::Float(_)
+            | Token::True
+            | Token::False
             | Token::String(_)
-            | Token::Regex(_)
+            | Token::Regex { .. }
             | Token::Subst { .. }
             | Token::Variable(_)
             | Token::RightParen
@@ -554,6 +870,21 @@ Some('s') => {
     tok
 }
 
+    /// Tokenize the whole input, keeping the source span of every token
+    pub fn tokenize_with_spans(&mut self) -> Vec<(Token, Span)> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_token();
+            let span = self.last_span();
+            let done = token == Token::Eof;
+            tokens.push((token, span));
+            if done {
+                break;
+            }
+        }
+        tokens
+    }
+
     pub fn tokenize(&mut self) -> Vec<Token> {
         let mut tokens = Vec::new();
         loop {
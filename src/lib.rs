@@ -0,0 +1,23 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! The Minilux language core, usable from Rust and, through [`capi`], from C.
+
+pub mod capi;
+pub mod host;
+pub mod interpreter;
+pub mod lexer;
+pub mod log;
+pub mod parser;
+pub mod pkg;
+pub mod plugin;
+pub mod printf;
+pub mod process;
+pub mod remote;
+pub mod runtime;
+pub mod stdlib;
+pub mod term;
+pub mod value;
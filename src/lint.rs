@@ -0,0 +1,519 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::interpreter::{Interpreter, BUILTIN_FUNCTIONS};
+use crate::lexer::{Lexer, Span, Token};
+use crate::parser::{Expr, Parser, Statement, Stmt};
+use crate::stdlib;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A single lint finding
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Lint a script. `resolver` is used to locate included files so that the
+/// functions and variables they provide are known.
+pub fn lint_source(source: &str, resolver: &mut Interpreter) -> Vec<Diagnostic> {
+    let mut parser = Parser::new(source);
+    let statements = parser.parse();
+    let mut linter = Linter {
+        diagnostics: parser
+            .errors()
+            .iter()
+            .map(|e| Diagnostic {
+                span: e.span,
+                message: format!("syntax error: {}", e.message),
+            })
+            .collect(),
+        functions: HashSet::new(),
+        reads: HashSet::new(),
+        writes: HashMap::new(),
+        included_writes: HashSet::new(),
+        include_depth: 0,
+        params: HashSet::new(),
+        visited: HashSet::new(),
+        loads_plugins: false,
+    };
+
+    linter.check_condition_assignments(source);
+    linter.collect(&statements, resolver);
+    linter.check_unreachable(&statements);
+    linter.check_calls(&statements);
+    linter.check_unused();
+    linter.check_shadowed_params(&statements);
+
+    linter
+        .diagnostics
+        .sort_by_key(|d| (d.span.line, d.span.column));
+    linter.diagnostics
+}
+
+struct Linter {
+    diagnostics: Vec<Diagnostic>,
+    functions: HashSet<String>,
+    reads: HashSet<String>,
+    /// Variables assigned in the linted file, and where first
+    writes: HashMap<String, Span>,
+    /// Variables assigned in included files, which are reported there
+    included_writes: HashSet<String>,
+    /// How many includes deep `collect` is
+    include_depth: usize,
+    /// Parameters of functions and lambdas, which may be passed functions
+    params: HashSet<String>,
+    visited: HashSet<PathBuf>,
+    /// Plugins register functions at runtime, so calls cannot be checked
+    loads_plugins: bool,
+}
+
+impl Linter {
+    fn report(&mut self, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic { span, message });
+    }
+
+    /// `if ($x = 1)` does not parse as a comparison; catch it on the token stream.
+    fn check_condition_assignments(&mut self, source: &str) {
+        let tokens = Lexer::new(source).tokenize_with_spans();
+        let mut i = 0;
+        while i < tokens.len() {
+            let is_condition = matches!(tokens[i].0, Token::If | Token::Elseif | Token::While)
+                && tokens.get(i + 1).map(|t| &t.0) == Some(&Token::LeftParen);
+            if !is_condition {
+                i += 1;
+                continue;
+            }
+
+            let mut depth = 0;
+            let mut j = i + 1;
+            while j < tokens.len() {
+                match tokens[j].0 {
+                    Token::LeftParen => depth += 1,
+                    Token::RightParen => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    Token::Equals => {
+                        self.report(
+                            tokens[j].1,
+                            "assignment in condition (did you mean '=='?)".to_string(),
+                        );
+                    }
+                    Token::Eof => break,
+                    _ => {}
+                }
+                j += 1;
+            }
+            i = j;
+        }
+    }
+
+    /// Gather function definitions, variable reads and writes, following includes.
+    fn collect(&mut self, statements: &[Stmt], resolver: &mut Interpreter) {
+        walk(statements, &mut |stmt| {
+            match &stmt.kind {
+                Statement::FunctionDef { name, params, .. } => {
+                    self.functions.insert(name.clone());
+                    self.params.extend(params.iter().cloned());
+                }
+                Statement::Assignment { var, .. }
+                | Statement::ForIn { var, .. }
+                | Statement::Read { var }
+                | Statement::Sockread { var, .. }
+                | Statement::Serialread { var, .. } => {
+                    if self.include_depth > 0 {
+                        self.included_writes.insert(var.clone());
+                    } else {
+                        self.writes.entry(var.clone()).or_insert(stmt.span);
+                    }
+                }
+                Statement::ArrayAssignment { var, .. }
+                | Statement::Inc { var, .. }
+                | Statement::Dec { var, .. } => {
+                    self.reads.insert(var.clone());
+                }
+                Statement::Push { array, .. }
+                | Statement::Pop { array }
+                | Statement::Shift { array }
+                | Statement::Unshift { array, .. } => {
+                    self.reads.insert(array.clone());
+                }
+                Statement::Include { path } => {
+                    self.collect_include(path, stmt.span, resolver);
+                }
+                Statement::FunctionCall { name, .. } => {
+                    // Calling `$f(...)` uses the function stored in $f.
+                    self.reads.insert(name.clone());
+                    self.loads_plugins |= name == "loadlib";
+                }
+                _ => {}
+            }
+            for_each_expr(stmt, &mut |expr| match expr {
+                Expr::Variable(name) => {
+                    self.reads.insert(name.clone());
+                }
+                Expr::Lambda { params, .. } => {
+                    self.params.extend(params.iter().cloned());
+                }
+                Expr::FunctionCall { name, .. } => {
+                    self.reads.insert(name.clone());
+                    self.loads_plugins |= name == "loadlib";
+                }
+                _ => {}
+            });
+        });
+    }
+
+    fn collect_include(&mut self, path: &str, span: Span, resolver: &mut Interpreter) {
+        if let Some(source) = stdlib::source(path) {
+            if self.visited.insert(PathBuf::from(path)) {
+                let saved = std::mem::take(&mut self.diagnostics);
+                self.include_depth += 1;
+                self.collect(&Parser::new(source).parse(), resolver);
+                self.include_depth -= 1;
+                self.diagnostics = saved;
+            }
+            return;
+        }
+
+        let resolved = resolver.resolve_include_path(path);
+        let canonical = fs::canonicalize(&resolved).unwrap_or(resolved.clone());
+        if !self.visited.insert(canonical) {
+            return;
+        }
+
+        let content = match fs::read_to_string(&resolved) {
+            Ok(content) => content,
+            Err(_) => {
+                self.report(span, format!("included file '{}' not found", path));
+                return;
+            }
+        };
+
+        let statements = Parser::new(&content).parse();
+        let parent = resolved.parent().map(Path::to_path_buf);
+        if let Some(dir) = parent.clone() {
+            resolver.push_base_dir(dir);
+        }
+
+        // Diagnostics belong to the included file; only harvest its symbols.
+        let saved = std::mem::take(&mut self.diagnostics);
+        self.include_depth += 1;
+        self.collect(&statements, resolver);
+        self.include_depth -= 1;
+        self.diagnostics = saved;
+
+        if parent.is_some() {
+            resolver.pop_base_dir();
+        }
+    }
+
+    fn check_unreachable(&mut self, statements: &[Stmt]) {
+        if let Some(pos) = statements
+            .iter()
+            .position(|s| matches!(s.kind, Statement::Return { .. }))
+        {
+            if let Some(next) = statements.get(pos + 1) {
+                self.report(next.span, "unreachable code after 'return'".to_string());
+            }
+        }
+        for stmt in statements {
+            for block in child_blocks(stmt) {
+                self.check_unreachable(block);
+            }
+        }
+    }
+
+    fn check_calls(&mut self, statements: &[Stmt]) {
+        if self.loads_plugins {
+            return;
+        }
+        let mut undefined: Vec<(Span, String)> = Vec::new();
+        walk(statements, &mut |stmt| {
+            if let Statement::FunctionCall { name, .. } = &stmt.kind {
+                if !self.is_callable(name) {
+                    undefined.push((stmt.span, name.clone()));
+                }
+            }
+            for_each_expr(stmt, &mut |expr| {
+                if let Expr::FunctionCall { name, span, .. } = expr {
+                    if !self.is_callable(name) {
+                        undefined.push((*span, name.clone()));
+                    }
+                }
+            });
+        });
+        for (span, name) in undefined {
+            self.report(span, format!("call to undefined function '{}'", name));
+        }
+    }
+
+    /// A function, or a variable that may hold a function value
+    fn is_callable(&self, name: &str) -> bool {
+        self.functions.contains(name)
+            || BUILTIN_FUNCTIONS.contains(&name)
+            || self.writes.contains_key(name)
+            || self.included_writes.contains(name)
+            || self.params.contains(name)
+    }
+
+    fn check_unused(&mut self) {
+        let mut unused: Vec<(Span, String)> = self
+            .writes
+            .iter()
+            .filter(|(name, _)| !self.reads.contains(*name))
+            .map(|(name, span)| (*span, name.clone()))
+            .collect();
+        unused.sort_by_key(|(span, _)| (span.line, span.column));
+        for (span, name) in unused {
+            self.report(span, format!("variable '${}' is assigned but never used", name));
+        }
+    }
+
+    fn check_shadowed_params(&mut self, statements: &[Stmt]) {
+        let globals: HashSet<&String> = statements
+            .iter()
+            .filter_map(|s| match &s.kind {
+                Statement::Assignment { var, .. } | Statement::Read { var } => Some(var),
+                _ => None,
+            })
+            .collect();
+
+        let mut found: Vec<(Span, String)> = Vec::new();
+        walk(statements, &mut |stmt| {
+            if let Statement::FunctionDef { name, params, .. } = &stmt.kind {
+                let mut seen = HashSet::new();
+                for param in params {
+                    if !seen.insert(param) {
+                        found.push((
+                            stmt.span,
+                            format!("parameter '${}' of '{}' is declared twice", param, name),
+                        ));
+                    } else if globals.contains(param) {
+                        found.push((
+                            stmt.span,
+                            format!(
+                                "parameter '${}' of '{}' shadows a global variable",
+                                param, name
+                            ),
+                        ));
+                    }
+                }
+            }
+        });
+        for (span, message) in found {
+            self.report(span, message);
+        }
+    }
+}
+
+/// Visit every statement, including those nested in blocks and function bodies.
+pub fn walk(statements: &[Stmt], visit: &mut dyn FnMut(&Stmt)) {
+    for stmt in statements {
+        visit(stmt);
+        for block in child_blocks(stmt) {
+            walk(block, visit);
+        }
+    }
+}
+
+/// Blocks of statements directly nested in `stmt`, including lambda bodies.
+pub fn child_blocks(stmt: &Stmt) -> Vec<&[Stmt]> {
+    let mut blocks = statement_blocks(stmt);
+    for expr in expr_roots(stmt) {
+        lambda_bodies(expr, &mut blocks);
+    }
+    blocks
+}
+
+fn statement_blocks(stmt: &Stmt) -> Vec<&[Stmt]> {
+    match &stmt.kind {
+        Statement::If {
+            then_body,
+            elseif_parts,
+            else_body,
+            ..
+        } => {
+            let mut blocks: Vec<&[Stmt]> = vec![then_body];
+            blocks.extend(elseif_parts.iter().map(|(_, body)| body.as_slice()));
+            if let Some(body) = else_body {
+                blocks.push(body);
+            }
+            blocks
+        }
+        Statement::While { body, .. }
+        | Statement::ForIn { body, .. }
+        | Statement::FunctionDef { body, .. } => vec![body],
+        Statement::Try {
+            body,
+            catch_body,
+            finally_body,
+            ..
+        } => {
+            let mut blocks: Vec<&[Stmt]> = vec![body];
+            blocks.extend(catch_body.as_deref());
+            blocks.extend(finally_body.as_deref());
+            blocks
+        }
+        Statement::For {
+            init, step, body, ..
+        } => {
+            // The init and step statements are visited like one-statement blocks.
+            let mut blocks: Vec<&[Stmt]> = Vec::new();
+            blocks.extend(init.as_deref().map(std::slice::from_ref));
+            blocks.extend(step.as_deref().map(std::slice::from_ref));
+            blocks.push(body);
+            blocks
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Visit every expression that belongs to `stmt` itself (not to nested blocks).
+pub fn for_each_expr(stmt: &Stmt, visit: &mut dyn FnMut(&Expr)) {
+    for expr in expr_roots(stmt) {
+        visit_expr(expr, visit);
+    }
+}
+
+/// Top-level expressions of `stmt`.
+fn expr_roots(stmt: &Stmt) -> Vec<&Expr> {
+    let mut roots: Vec<&Expr> = Vec::new();
+    match &stmt.kind {
+        Statement::Assignment { value, .. } => roots.push(value),
+        Statement::ArrayAssignment { indices, value, .. } => {
+            roots.extend(indices.iter());
+            roots.push(value);
+        }
+        Statement::If {
+            condition,
+            elseif_parts,
+            ..
+        } => {
+            roots.push(condition);
+            roots.extend(elseif_parts.iter().map(|(cond, _)| cond));
+        }
+        Statement::While { condition, .. } => roots.push(condition),
+        Statement::ForIn { iterable, .. } => roots.push(iterable),
+        Statement::For { condition, .. } => roots.extend(condition.as_ref()),
+        Statement::Printf { args, .. } | Statement::FunctionCall { args, .. } => {
+            roots.extend(args.iter())
+        }
+        Statement::Inc { value, .. }
+        | Statement::Dec { value, .. }
+        | Statement::Push { value, .. }
+        | Statement::Unshift { value, .. } => roots.push(value),
+        Statement::Sockopen { host, port, .. } => {
+            roots.push(host);
+            roots.push(port);
+        }
+        Statement::Serialopen { device, baud, .. } => {
+            roots.push(device);
+            roots.push(baud);
+        }
+        Statement::Sockwrite { data, .. } | Statement::Serialwrite { data, .. } => roots.push(data),
+        Statement::Return { value: Some(value) } | Statement::Throw { value } => roots.push(value),
+        Statement::Read { .. }
+        | Statement::Pop { .. }
+        | Statement::Shift { .. }
+        | Statement::Sockclose { .. }
+        | Statement::Sockread { .. }
+        | Statement::Serialclose { .. }
+        | Statement::Serialread { .. }
+        | Statement::Include { .. }
+        | Statement::FunctionDef { .. }
+        | Statement::Global { .. }
+        | Statement::Strict
+        | Statement::Overflow(_)
+        | Statement::Try { .. }
+        | Statement::Return { value: None } => {}
+    }
+    roots
+}
+
+/// Bodies of the lambdas in `expr`, not counting lambdas nested in those bodies.
+fn lambda_bodies<'a>(expr: &'a Expr, out: &mut Vec<&'a [Stmt]>) {
+    match expr {
+        Expr::Lambda { body, .. } => out.push(body),
+        Expr::Binary { left, right, .. } => {
+            lambda_bodies(left, out);
+            lambda_bodies(right, out);
+        }
+        Expr::Unary { expr, .. } | Expr::SubstCall { input: expr, .. } => lambda_bodies(expr, out),
+        Expr::Index { expr, index, .. } => {
+            lambda_bodies(expr, out);
+            lambda_bodies(index, out);
+        }
+        Expr::Slice { expr, start, end, .. } => {
+            lambda_bodies(expr, out);
+            for bound in [start, end].into_iter().flatten() {
+                lambda_bodies(bound, out);
+            }
+        }
+        Expr::Array(items) | Expr::FunctionCall { args: items, .. } => {
+            for item in items {
+                lambda_bodies(item, out);
+            }
+        }
+        Expr::Map(entries) => {
+            for (key, value) in entries {
+                lambda_bodies(key, out);
+                lambda_bodies(value, out);
+            }
+        }
+        Expr::Int(_)
+        | Expr::Float(_)
+        | Expr::Bool(_)
+        | Expr::String(_)
+        | Expr::Regex { .. }
+        | Expr::Variable(_) => {}
+    }
+}
+
+fn visit_expr(expr: &Expr, visit: &mut dyn FnMut(&Expr)) {
+    visit(expr);
+    match expr {
+        Expr::Binary { left, right, .. } => {
+            visit_expr(left, visit);
+            visit_expr(right, visit);
+        }
+        Expr::Unary { expr, .. } | Expr::SubstCall { input: expr, .. } => visit_expr(expr, visit),
+        Expr::Index { expr, index, .. } => {
+            visit_expr(expr, visit);
+            visit_expr(index, visit);
+        }
+        Expr::Slice { expr, start, end, .. } => {
+            visit_expr(expr, visit);
+            for bound in [start, end].into_iter().flatten() {
+                visit_expr(bound, visit);
+            }
+        }
+        Expr::Array(items) | Expr::FunctionCall { args: items, .. } => {
+            for item in items {
+                visit_expr(item, visit);
+            }
+        }
+        Expr::Map(entries) => {
+            for (key, value) in entries {
+                visit_expr(key, visit);
+                visit_expr(value, visit);
+            }
+        }
+        // Lambda bodies are statements; walk() reaches them through child_blocks().
+        Expr::Int(_)
+        | Expr::Float(_)
+        | Expr::Bool(_)
+        | Expr::String(_)
+        | Expr::Regex { .. }
+        | Expr::Variable(_)
+        | Expr::Lambda { .. } => {}
+    }
+}
@@ -0,0 +1,159 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! Leveled logging behind the `log_init()` and `log_debug/info/warn/error()`
+//! builtins.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+#[cfg(not(target_family = "wasm"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Severity of a log message, lowest first
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn parse(name: &str) -> Option<Level> {
+        match name.to_ascii_lowercase().as_str() {
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" | "warning" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+/// How each log record is written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// `2026-01-02T03:04:05.000000Z INFO  message`
+    Text,
+    /// One JSON object per line with `time`, `level` and `message`
+    Json,
+}
+
+/// Where and how log records go. By default, records of level info and
+/// above are written to stderr as text.
+pub struct Logger {
+    level: Level,
+    format: Format,
+    /// Appended to, None for stderr
+    file: Option<PathBuf>,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Logger {
+            level: Level::Info,
+            format: Format::Text,
+            file: None,
+        }
+    }
+}
+
+impl Logger {
+    /// Build a logger from `log_init()` arguments. An empty `file` means
+    /// stderr; `format` is "text" or "json".
+    pub fn new(level: &str, file: &str, format: &str) -> Result<Logger, String> {
+        let level = Level::parse(level).ok_or_else(|| {
+            format!("log_init(): unknown level '{}' (use debug, info, warn or error)", level)
+        })?;
+        let format = match format.to_ascii_lowercase().as_str() {
+            "" | "text" => Format::Text,
+            "json" => Format::Json,
+            other => return Err(format!("log_init(): unknown format '{}' (use text or json)", other)),
+        };
+        let file = Some(PathBuf::from(file)).filter(|f| !f.as_os_str().is_empty());
+        Ok(Logger { level, format, file })
+    }
+
+    /// Write `message` if `level` is enabled; `stderr` is used when no log
+    /// file is set.
+    pub fn log(&self, level: Level, message: &str, stderr: &mut dyn Write) -> Result<(), String> {
+        if level < self.level {
+            return Ok(());
+        }
+        let record = match self.format {
+            Format::Text => format!(
+                "{} {:<5} {}\n",
+                timestamp(),
+                level.name().to_ascii_uppercase(),
+                message
+            ),
+            Format::Json => format!(
+                "{{\"time\":\"{}\",\"level\":\"{}\",\"message\":{}}}\n",
+                timestamp(),
+                level.name(),
+                serde_json::Value::from(message)
+            ),
+        };
+
+        match &self.file {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(record.as_bytes()))
+                .map_err(|e| format!("Failed to write log file {}: {}", path.display(), e)),
+            None => stderr
+                .write_all(record.as_bytes())
+                .and_then(|_| stderr.flush())
+                .map_err(|e| format!("Failed to write log record: {}", e)),
+        }
+    }
+}
+
+/// Current UTC time in ISO 8601 with microseconds
+pub fn timestamp() -> String {
+    // wasm32-unknown-unknown has no clock; SystemTime::now() would panic.
+    #[cfg(not(target_family = "wasm"))]
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    #[cfg(target_family = "wasm")]
+    let now = std::time::Duration::ZERO;
+
+    let secs = now.as_secs();
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        now.subsec_micros()
+    )
+}
@@ -0,0 +1,449 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::interpreter::{Interpreter, BUILTIN_FUNCTIONS};
+use crate::lexer::Span;
+use crate::lint::{self, walk};
+use crate::parser::{Parser, Statement, Stmt};
+use serde_json::{json, Value as Json};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Statement keywords offered by completion next to the builtin functions,
+/// here and in the REPL
+pub(crate) const KEYWORDS: &[&str] = &[
+    "if", "elseif", "else", "while", "for", "in", "function", "return", "global", "strict", "overflow", "try",
+    "catch", "finally", "throw", "include", "printf", "print", "read", "inc", "dec", "push",
+    "unshift", "sockopen", "sockclose", "sockwrite", "sockread", "serialopen", "serialclose",
+    "serialwrite", "serialread", "true", "false", "AND", "OR",
+];
+
+/// Hover text for builtins and statement keywords
+fn builtin_doc(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "printf" | "print" => "printf(args...)\n\nPrint all arguments concatenated, followed by a newline. A literal first argument may hold `%s`, `%d`, `%x`, `%05d`, `%-10s`, `%.2f` style directives filled by the following arguments.",
+        "sprintf" => "sprintf(format, args...)\n\nReturn `format` with its `%` directives filled in by `args`, as printf does.",
+        "read" => "read($var)\n\nRead one line from stdin into `$var`.",
+        "prompt" => "prompt(message, default)\n\nPrint `message` without a newline and return the line typed, or `default` when it is empty.",
+        "readpass" => "readpass(message)\n\nPrint `message` and read a line without echoing it, for passwords.",
+        "read_all_stdin" => "read_all_stdin()\n\nRead standard input up to end of file and return it as one string.",
+        "getch" => "getch()\n\nWait for a single keypress and return it: the character typed, or a key name such as \"enter\", \"up\" or \"f1\".",
+        "is_tty" => "is_tty(stream)\n\ntrue when `stream` (\"stdin\", \"stdout\" or \"stderr\"; default stdout) is a terminal, else false.",
+        "term_size" => "term_size()\n\nTerminal size as `[columns, rows]`.",
+        "is_color" => "is_color()\n\ntrue when output goes to a color terminal and NO_COLOR is not set, else false.",
+        "color" => "color(text, fg, bg)\n\n`text` in the given colors (red, bright_blue, ...) when color is enabled; `bg` is optional.",
+        "style" => "style(text, styles...)\n\n`text` with styles such as bold, dim, italic or underline when color is enabled.",
+        "cursor_to" => "cursor_to(x, y)\n\nMove the cursor to column `x`, row `y` (1-based).",
+        "clear_screen" => "clear_screen()\n\nClear the terminal and move the cursor to the top left.",
+        "hide_cursor" => "hide_cursor()\n\nHide the terminal cursor (restore it with show_cursor()).",
+        "show_cursor" => "show_cursor()\n\nShow the terminal cursor again.",
+        "redraw" => "redraw(args...)\n\nReplace the current terminal line with the arguments, without a newline.",
+        "len" | "strlen" => "len(value)\n\nLength of a string (in characters), an array or a map.",
+        "casefold" => "casefold(text)\n\n`text` folded for caseless comparison: like lower(), but also matching ß with ss and the Greek sigmas, the same in every locale.",
+        "nfc" => "nfc(text)\n\n`text` in Unicode normalization form C (composed characters).",
+        "nfd" => "nfd(text)\n\n`text` in Unicode normalization form D (decomposed characters).",
+        "bytelen" => "bytelen(text)\n\nLength of `text` in bytes of UTF-8.",
+        "grapheme_len" => "grapheme_len(text)\n\nNumber of user-perceived characters in `text`, counting an accented letter or an emoji with modifiers as one.",
+        "graphemes" => "graphemes(text)\n\nArray of the user-perceived characters of `text`.",
+        "shell" => "shell(cmd, input, timeout)\n\nRun `cmd` with the system shell and return its stdout. `input` is written to its stdin as a string (nil for none). With `timeout` seconds, a command still running is killed and nil is returned.",
+        "run" => "run([program, args...], input, timeout)\n\nRun `program` with the arguments as they are, without a shell, and return its stdout. `input` and `timeout` are as for shell().",
+        "spawn" => "spawn(cmd)\n\nStart `cmd` (a command line, or an array of program and arguments) running alongside the script and return a handle for the proc_ functions.",
+        "proc_readline" => "proc_readline(p)\n\nNext line of the process's output, waiting for it; nil once the output has ended.",
+        "proc_write" => "proc_write(p, data)\n\nWrite `data` to the process's stdin.",
+        "proc_close" => "proc_close(p)\n\nClose the process's stdin so it sees the end of its input; its output can still be read.",
+        "proc_wait" => "proc_wait(p)\n\nClose the process's stdin, wait for it to exit and return its exit code (nil if a signal ended it).",
+        "proc_kill" => "proc_kill(p)\n\nStop the process.",
+        "shell_result" => "shell_result(cmd, input, timeout)\n\nRun `cmd` like shell() (or like run() when it is an array) and return a map with its `stdout`, `stderr`, exit `code` (nil if it was killed) and whether it `timed_out`.",
+        "is_nil" => "is_nil(value)\n\ntrue if `value` is nil (an unset variable, a missing element, a timed-out shell()), otherwise false.",
+        "pow" => "pow(base, exponent)\n\n`base` raised to `exponent`, the same as `base ** exponent`.",
+        "sqrt" => "sqrt(n)\n\nSquare root of `n` as a float (nil for a negative number).",
+        "abs" => "abs(n)\n\nAbsolute value of `n`.",
+        "min" => "min(a, b, ...) or min(array)\n\nThe smallest of the arguments or elements, leaving out nil.",
+        "max" => "max(a, b, ...) or max(array)\n\nThe largest of the arguments or elements, leaving out nil.",
+        "sum" => "sum(array) or sum(a, b, ...)\n\nTotal of the elements or arguments, leaving out nil (0 when there are none).",
+        "avg" => "avg(array) or avg(a, b, ...)\n\nMean of the elements or arguments as a float, leaving out nil (nil when there are none).",
+        "bigint" => "bigint(value)\n\nAn exact integer of any size from a number or a string such as \"123456789012345678901234567890\" or \"0xFFFF_FFFF_FFFF_FFFF_FF\". Arithmetic on it stays exact.",
+        "number" => "number(value)\n\nConvert a string such as \"42\", \"-2.5\", \"0x1F\" or \"1_000\" to a number (0 when it is not a number, nil in strict mode).",
+        "lower" => "lower(str)\n\nLowercase copy of `str`.",
+        "upper" => "upper(str)\n\nUppercase copy of `str`.",
+        "format_number" => "format_number(n, decimals, thousands_sep, decimal_sep)\n\n`n` with `decimals` digits after the point and grouped thousands, e.g. \"1,234,567.89\". Separators default to \",\" and \".\". The settings can also be given as a map: `{\"decimals\": 2, \"thousands_sep\": \" \", \"decimal_sep\": \",\"}`.",
+        "read_file" => "read_file(path)\n\nContents of the file at `path` (relative to the script's directory).",
+        "write_file" => "write_file(path, data)\n\nReplace the contents of the file at `path` with `data`, creating it if needed.",
+        "append_file" => "append_file(path, data)\n\nAdd `data` to the end of the file at `path`, creating it if needed.",
+        "file_exists" => "file_exists(path)\n\nWhether a file or directory exists at `path`.",
+        "env" => "env(name)\n\nValue of the environment variable `name`, or nil when it is not set.",
+        "setenv" => "setenv(name, value)\n\nSet the environment variable `name` for this process and the commands it runs; a nil value unsets it.",
+        "environ" => "environ()\n\nMap of all environment variables.",
+        "sleep" => "sleep(seconds)\n\nPause execution.",
+        "exit" => "exit(status)\n\nEnd the script with exit status `status` (0 to 255, default 0). `try` does not catch it; `finally` blocks still run.",
+        "assert_eq" => "assert_eq(actual, expected)\n\nFail the current test unless both values are equal.",
+        "assert_match" => "assert_match(str, /re/)\n\nFail the current test unless `str` matches the regex.",
+        "fail" => "fail(message)\n\nFail the current test with `message`.",
+        "loadlib" => "loadlib(\"libfoo.so\")\n\nLoad a native plugin and make the functions it registers callable.",
+        "log_init" => "log_init(level, file, format)\n\nConfigure logging: minimum level (debug, info, warn, error), log file (\"\" for stderr) and format (text or json). They can also be given as a map: `{\"level\": \"debug\", \"format\": \"json\"}`.",
+        "log_debug" | "log_info" | "log_warn" | "log_error" => "log_info(args...)\n\nLog the arguments concatenated, with a timestamp and level.",
+        "inc" => "inc $var + n\n\nIncrement `$var` by `n`.",
+        "dec" => "dec $var - n\n\nDecrement `$var` by `n`.",
+        "push" => "push $array, value\n\nAppend `value` to `$array`.",
+        "copy" => "copy(value)\n\nA copy of an array or map that shares nothing with it, nested arrays and maps included.",
+        "keys" => "keys(map)\n\nArray of the keys of `map`, in sorted order.",
+        "values" => "values(map)\n\nArray of the values of `map`, in key order.",
+        "has_key" => "has_key(map, key)\n\ntrue if `map` has an entry for `key`.",
+        "map" => "map(array, fn)\n\nNew array of `fn(element)` for every element.",
+        "filter" => "filter(array, fn)\n\nNew array of the elements for which `fn(element)` is truthy.",
+        "reduce" => "reduce(array, fn, init)\n\nFold the array: `fn(acc, element)` for each element, starting with `acc` = `init`.",
+        "sort" => "sort(array[, fn])\n\nNew array with the elements in ascending order, or in the order of `fn(a, b)`: negative when `a` comes first, positive when `b` does, 0 to keep them as they are.",
+        "sort_by" => "sort_by(array, fn)\n\nNew array with the elements ordered by the key `fn(element)`.",
+        "reverse" => "reverse(array)\n\nNew array with the elements in reverse order (or the string reversed).",
+        "unique" => "unique(array)\n\nNew array without repeated elements, keeping the first of each.",
+        "contains" => "contains(array, value)\n\ntrue if an element equals `value` (or the string contains `value`).",
+        "index_of" => "index_of(array, value)\n\nPosition of the first element equal to `value` (or of `value` in the string), -1 if there is none.",
+        "slice" => "slice(array, start[, end])\n\nNew array of the elements from `start` up to, but not including, `end`, like `$array[start:end]`.",
+        "concat" => "concat(a, b, ...)\n\nNew array with the elements of all the arrays, in order; other values are added as elements.",
+        "flatten" => "flatten(array[, depth])\n\nNew array with nested arrays replaced by their elements, all the way down or `depth` levels.",
+        "substr" => "substr(text, start[, length])\n\nThe characters of `text` from `start` (negative counts from the end), up to `length` of them.",
+        "trim" => "trim(text[, chars])\n\n`text` without leading and trailing whitespace, or the characters in `chars`.",
+        "ltrim" => "ltrim(text[, chars])\n\n`text` without leading whitespace, or the characters in `chars`.",
+        "rtrim" => "rtrim(text[, chars])\n\n`text` without trailing whitespace, or the characters in `chars`.",
+        "replace" => "replace(text, from, to)\n\n`text` with every occurrence of `from` replaced by `to` (no regex).",
+        "starts_with" => "starts_with(text, prefix)\n\ntrue if `text` begins with `prefix`.",
+        "ends_with" => "ends_with(text, suffix)\n\ntrue if `text` ends with `suffix`.",
+        "repeat" => "repeat(text, n)\n\n`text` repeated `n` times.",
+        "pad_left" => "pad_left(text, width[, fill])\n\n`text` padded on the left to `width` characters with `fill` (a space by default).",
+        "pad_right" => "pad_right(text, width[, fill])\n\n`text` padded on the right to `width` characters with `fill` (a space by default).",
+        "ord" => "ord(text)\n\nUnicode code point of the first character of `text` (nil when empty).",
+        "chr" => "chr(code)\n\nOne-character string for the Unicode code point `code`.",
+        "chars" => "chars(text)\n\nArray of the characters of `text`, each a one-character string.",
+        "split" => "split(text[, sep])\n\nArray of the pieces of `text` between occurrences of `sep`; without `sep`, the words separated by whitespace, and with \"\", the characters.",
+        "regmatch" => "regmatch(text, /pattern/)\n\nMap of the groups of the first match, keyed by number (`0` is the whole match) and by name for named groups; nil when there is no match.",
+        "match_all" => "match_all(text, /pattern/)\n\nArray of every match in `text`: the matched strings, or for a pattern with groups, maps of the groups as regmatch() gives them.",
+        "gsub" => "gsub(text, /pattern/, replacement)\n\n`text` with every match replaced. `replacement` is a string, where `$1` refers to a group, or a function called with the matched text and a map of its groups that returns the replacement.",
+        "resplit" => "resplit(text, /pattern/)\n\nArray of the pieces of `text` between matches of the regex.",
+        "join" => "join(array[, sep])\n\nThe elements of `array` as one string, with `sep` between them.",
+        "pop" => "pop($array)\n\nRemove the last element of `$array` and return it (nil when empty).",
+        "shift" => "shift($array)\n\nRemove the first element of `$array` and return it (nil when empty).",
+        "insert_at" => "insert_at($array, index, value)\n\nInsert `value` before position `index` (the length appends).",
+        "remove_at" => "remove_at($array, index)\n\nRemove the element at `index` and return it (nil when out of range).",
+        "swap" => "swap($array, i, j)\n\nExchange the elements at positions `i` and `j`.",
+        "unshift" => "unshift $array, value\n\nInsert `value` at the front of `$array`.",
+        "sockopen" => "sockopen(\"name\", host, port)\n\nOpen a TCP connection called `name`.",
+        "sockwrite" => "sockwrite(\"name\", data)\n\nWrite `data` to the socket.",
+        "sockread" => "sockread(\"name\", $var)\n\nRead up to 1024 bytes from the socket into `$var`.",
+        "sockclose" => "sockclose(\"name\")\n\nClose the socket.",
+        "serialopen" => "serialopen(\"name\", device, baud)\n\nOpen the serial port `device` (such as /dev/ttyUSB0 or COM3) at `baud` bits per second.",
+        "serialwrite" => "serialwrite(\"name\", data)\n\nWrite `data` to the serial port.",
+        "serialread" => "serialread(\"name\", $var)\n\nRead up to 1024 bytes from the serial port into `$var` (\"\" after a second without data).",
+        "serialclose" => "serialclose(\"name\")\n\nClose the serial port.",
+        "include" => "include \"file.mi\"\n\nRun another script in the current interpreter.",
+        "global" => "global $name, ...\n\nInside a function, read and assign the global variables of these names instead of locals.",
+        "overflow" => "overflow wrap|saturate|error|promote\n\nFrom here on, what integer arithmetic does when a result does not fit in 64 bits: wrap around, stop at the largest or smallest integer, raise an error (the default), or carry on with an exact integer of any size. Same as `--overflow`.",
+        "strict" => "strict\n\nFrom here on, reading an undefined variable or calling an undefined function is an error instead of a warning (same as `--strict`).",
+        _ => return None,
+    })
+}
+
+struct Server {
+    documents: HashMap<String, String>,
+    modules_spec: Option<String>,
+}
+
+/// Serve the Language Server Protocol over stdin/stdout until `exit`.
+pub fn run(modules_spec: Option<&str>) -> Result<(), String> {
+    let mut server = Server {
+        documents: HashMap::new(),
+        modules_spec: modules_spec.map(String::from),
+    };
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or("");
+        let id = message.get("id").cloned();
+        if method == "exit" {
+            break;
+        }
+
+        let result = server.handle(method, &message["params"])?;
+        if let Some(id) = id {
+            write_message(&json!({ "jsonrpc": "2.0", "id": id, "result": result }))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Json>, String> {
+    let mut length: Option<usize> = None;
+    loop {
+        let mut header = String::new();
+        let read = input
+            .read_line(&mut header)
+            .map_err(|e| format!("Failed to read message: {}", e))?;
+        if read == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+
+    let length = length.ok_or("Missing Content-Length header")?;
+    let mut body = vec![0; length];
+    input
+        .read_exact(&mut body)
+        .map_err(|e| format!("Failed to read message: {}", e))?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| format!("Invalid JSON message: {}", e))
+}
+
+pub fn write_message(message: &Json) -> Result<(), String> {
+    let body = message.to_string();
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| format!("Failed to write message: {}", e))
+}
+
+impl Server {
+    fn handle(&mut self, method: &str, params: &Json) -> Result<Json, String> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "completionProvider": { "triggerCharacters": ["$"] }
+                },
+                "serverInfo": { "name": "minilux", "version": env!("CARGO_PKG_VERSION") }
+            })),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+                self.publish_diagnostics(&uri)?;
+                Ok(Json::Null)
+            }
+            "textDocument/didChange" => {
+                if let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                self.publish_diagnostics(&uri)?;
+                Ok(Json::Null)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                Ok(Json::Null)
+            }
+            "textDocument/hover" => Ok(self.hover(&uri, &params["position"])),
+            "textDocument/definition" => Ok(self.definition(&uri, &params["position"])),
+            "textDocument/completion" => Ok(self.completion(&uri)),
+            _ => Ok(Json::Null),
+        }
+    }
+
+    fn resolver(&self, uri: &str) -> Interpreter {
+        let mut resolver = Interpreter::new();
+        if let Some(spec) = &self.modules_spec {
+            resolver.set_modules_path(spec);
+        }
+        if let Some(dir) = uri_to_path(uri).and_then(|p| p.parent().map(Path::to_path_buf)) {
+            resolver.push_base_dir(dir);
+        }
+        resolver
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Result<(), String> {
+        let text = self.documents.get(uri).map(String::as_str).unwrap_or("");
+        let diagnostics: Vec<Json> = lint::lint_source(text, &mut self.resolver(uri))
+            .into_iter()
+            .map(|d| {
+                json!({
+                    "range": span_to_range(d.span),
+                    "severity": 2,
+                    "source": "minilux",
+                    "message": d.message,
+                })
+            })
+            .collect();
+        write_message(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics }
+        }))
+    }
+
+    fn word_at(&self, uri: &str, position: &Json) -> Option<String> {
+        let text = self.documents.get(uri)?;
+        let line = text.lines().nth(position["line"].as_u64()? as usize)?;
+        let chars: Vec<char> = line.chars().collect();
+        let at = (position["character"].as_u64()? as usize).min(chars.len());
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+
+        let start = chars[..at]
+            .iter()
+            .rposition(|c| !is_word(c))
+            .map_or(0, |p| p + 1);
+        let end = chars[at..]
+            .iter()
+            .position(|c| !is_word(c))
+            .map_or(chars.len(), |p| at + p);
+        if start >= end {
+            return None;
+        }
+        Some(chars[start..end].iter().collect())
+    }
+
+    fn hover(&self, uri: &str, position: &Json) -> Json {
+        let doc = match self.word_at(uri, position).as_deref().and_then(builtin_doc) {
+            Some(doc) => doc,
+            None => return Json::Null,
+        };
+        let (signature, description) = doc.split_once("\n\n").unwrap_or((doc, ""));
+        json!({
+            "contents": {
+                "kind": "markdown",
+                "value": format!("```minilux\n{}\n```\n\n{}", signature, description)
+            }
+        })
+    }
+
+    fn definition(&self, uri: &str, position: &Json) -> Json {
+        let name = match self.word_at(uri, position) {
+            Some(name) => name,
+            None => return Json::Null,
+        };
+        let text = self.documents.get(uri).cloned().unwrap_or_default();
+        let mut resolver = self.resolver(uri);
+        let mut seen = Vec::new();
+        match find_function(&name, uri, &text, &mut resolver, &mut seen) {
+            Some((target, span)) => json!({ "uri": target, "range": span_to_range(span) }),
+            None => Json::Null,
+        }
+    }
+
+    fn completion(&self, uri: &str) -> Json {
+        let mut items: Vec<Json> = Vec::new();
+        for name in BUILTIN_FUNCTIONS {
+            items.push(json!({ "label": name, "kind": 3, "detail": "builtin" }));
+        }
+        for name in KEYWORDS {
+            items.push(json!({ "label": name, "kind": 14 }));
+        }
+
+        let text = self.documents.get(uri).map(String::as_str).unwrap_or("");
+        let statements = Parser::new(text).parse();
+        let mut functions: Vec<String> = Vec::new();
+        let mut variables: Vec<String> = Vec::new();
+        walk(&statements, &mut |stmt| match &stmt.kind {
+            Statement::FunctionDef { name, params, .. } => {
+                functions.push(name.clone());
+                variables.extend(params.iter().cloned());
+            }
+            Statement::Assignment { var, .. }
+            | Statement::ForIn { var, .. }
+            | Statement::Read { var } => variables.push(var.clone()),
+            _ => {}
+        });
+        functions.sort();
+        functions.dedup();
+        variables.sort();
+        variables.dedup();
+
+        for name in functions {
+            items.push(json!({ "label": name, "kind": 3 }));
+        }
+        for name in variables {
+            items.push(json!({ "label": format!("${}", name), "insertText": name, "kind": 6 }));
+        }
+        Json::Array(items)
+    }
+}
+
+/// Look for `function name` in a document and, recursively, in what it includes.
+fn find_function(
+    name: &str,
+    uri: &str,
+    text: &str,
+    resolver: &mut Interpreter,
+    seen: &mut Vec<PathBuf>,
+) -> Option<(String, Span)> {
+    let statements: Vec<Stmt> = Parser::new(text).parse();
+    let mut found: Option<Span> = None;
+    let mut includes: Vec<String> = Vec::new();
+    walk(&statements, &mut |stmt| match &stmt.kind {
+        Statement::FunctionDef { name: n, .. } if n == name && found.is_none() => {
+            found = Some(stmt.span)
+        }
+        Statement::Include { path } => includes.push(path.clone()),
+        _ => {}
+    });
+    if let Some(span) = found {
+        return Some((uri.to_string(), span));
+    }
+
+    for include in includes {
+        // Embedded standard library modules have no file to jump to.
+        if crate::stdlib::source(&include).is_some() {
+            continue;
+        }
+        let path = resolver.resolve_include_path(&include);
+        let canonical = fs::canonicalize(&path).unwrap_or(path);
+        if seen.contains(&canonical) {
+            continue;
+        }
+        seen.push(canonical.clone());
+        if let Ok(content) = fs::read_to_string(&canonical) {
+            let target = format!("file://{}", canonical.display());
+            if let Some(dir) = canonical.parent() {
+                resolver.push_base_dir(dir.to_path_buf());
+            }
+            let result = find_function(name, &target, &content, resolver, seen);
+            resolver.pop_base_dir();
+            if result.is_some() {
+                return result;
+            }
+        }
+    }
+    None
+}
+
+fn span_to_range(span: Span) -> Json {
+    json!({
+        "start": { "line": span.line.saturating_sub(1), "character": span.column.saturating_sub(1) },
+        "end": { "line": span.end_line.saturating_sub(1), "character": span.end_column.saturating_sub(1) }
+    })
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut decoded = Vec::new();
+    let bytes = path.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            // Checked byte by byte: what follows a '%' may not be ASCII.
+            let digit = |b: u8| (b as char).to_digit(16);
+            if let (Some(high), Some(low)) = (digit(bytes[i + 1]), digit(bytes[i + 2])) {
+                decoded.push((high * 16 + low) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&decoded).into_owned()))
+}
@@ -4,26 +4,160 @@
 // License: MPL 2.0
 // SPDX-License-Identifier: MPL-2.0
 
-mod interpreter;
-mod lexer;
-mod parser;
-mod runtime;
-mod value;
+mod ast_json;
+mod bundle;
+mod dap;
+mod diagnostics;
+mod doc;
+mod formatter;
+mod kernel;
+mod lint;
+mod lsp;
+mod repl;
+mod transpile;
+
+use minilux::{host, interpreter, lexer, log, parser, pkg, printf, remote, runtime, stdlib, value};
 
 use interpreter::Interpreter;
+use lexer::Lexer;
 use parser::Parser;
+use value::{Overflow, Value};
 use std::env;
 use std::fs;
-use std::io::{self, BufRead, Write};
-use std::path::Path;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 fn main() {
+    // Deep recursion in scripts needs more than the default main thread stack.
+    let runner = thread::Builder::new()
+        .stack_size(interpreter::SCRIPT_STACK_SIZE)
+        .spawn(run);
+    match runner.map(thread::JoinHandle::join) {
+        Ok(Ok(())) => {}
+        Ok(Err(_)) => std::process::exit(101),
+        Err(e) => {
+            eprintln!("Error: failed to start interpreter thread: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run() {
     let args: Vec<String> = env::args().collect();
 
-    let mut modules_spec: Option<String> = None;
-    let mut script: Option<String> = None;
+    if let Some(bundle) = bundle::from_current_exe() {
+        run_bundle(bundle);
+    }
+
+    match args.get(1).map(String::as_str) {
+        Some("build") => build_command(&args[0], &args[2..]),
+        Some("doc") => doc_command(&args[0], &args[2..]),
+        Some("emit-sh") => emit_sh_command(&args[0], &args[2..]),
+        Some("fmt") => fmt_command(&args[0], &args[2..]),
+        Some("run-md") => run_md_command(&args[0], &args[2..]),
+        Some("pkg") => pkg_command(&args[0], &args[2..]),
+        Some("test") => test_command(&args[0], &args[2..]),
+        Some("kernel") => kernel_command(&args[0], &args[2..]),
+        Some("lint") => lint_command(&args[0], &args[2..]),
+        Some("lsp") => server_command(&args[0], &args[2..], lsp::run),
+        Some("dap") => server_command(&args[0], &args[2..], dap::run),
+        _ => {}
+    }
+
+    let mut options = Options::default();
+    if let Ok(defaults) = env::var("MINILUX_OPTIONS") {
+        let env_args: Vec<String> = defaults.split_whitespace().map(String::from).collect();
+        parse_options(&args[0], &env_args, &mut options);
+        if options.script.is_some() {
+            eprintln!("Error: MINILUX_OPTIONS may only contain options, not a script path");
+            std::process::exit(1);
+        }
+    }
+    parse_options(&args[0], &args[1..], &mut options);
+    if !options.eval.is_empty() {
+        // There is no script to name, so every argument is for the code.
+        if let Some(first) = options.script.take() {
+            options.args.insert(0, first);
+        }
+    }
+
+    let color = diagnostics::use_color(options.no_color);
+    if options.dump_tokens || options.dump_ast {
+        match given_source(&options) {
+            Ok((_, content)) if options.dump_tokens => print_tokens(&content),
+            Ok((_, content)) => print_ast(&content, options.ast_json),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else if options.check {
+        if let Err(e) = check_syntax(&options, color) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    } else if !options.eval.is_empty() {
+        if options.watch {
+            eprintln!("Error: --watch needs a script file, not -e/--eval");
+            std::process::exit(1);
+        }
+        handle_interrupts();
+        if let Some(secs) = options.timeout {
+            start_watchdog(secs);
+        }
+        let mut interpreter = script_interpreter(&options);
+        let source = options.eval.join("\n");
+        let result = run_source(&mut interpreter, "<eval>", None, &source, color);
+        finish(&mut interpreter, result);
+    } else if let Some(path) = options.script.as_deref() {
+        handle_interrupts();
+        if options.watch {
+            // The watchdog ends the whole process, not just the current run.
+            if options.timeout.is_some() {
+                eprintln!("Error: --watch cannot be combined with -t/--timeout");
+                std::process::exit(1);
+            }
+            watch_file(path, &options, color);
+        }
+        if let Some(secs) = options.timeout {
+            start_watchdog(secs);
+        }
+        let mut interpreter = script_interpreter(&options);
+        let result = run_file(&mut interpreter, path, color);
+        finish(&mut interpreter, result);
+    } else {
+        repl::run(|| script_interpreter(&options), color);
+    }
+}
 
-    let mut i = 1;
+/// Command-line settings, filled from MINILUX_OPTIONS first and then argv
+#[derive(Default)]
+struct Options {
+    modules_spec: Option<String>,
+    script: Option<String>,
+    /// Code given with -e, one entry per flag
+    eval: Vec<String>,
+    /// Arguments after the script path, passed to it as $ARGV
+    args: Vec<String>,
+    check: bool,
+    dump_ast: bool,
+    /// With --ast=json, the tree is printed as JSON
+    ast_json: bool,
+    dump_tokens: bool,
+    watch: bool,
+    timeout: Option<u64>,
+    no_color: bool,
+    allow_remote: bool,
+    max_depth: Option<usize>,
+    strict: bool,
+    strict_index: bool,
+    overflow: Option<Overflow>,
+}
+
+fn parse_options(prog: &str, args: &[String], options: &mut Options) {
+    let mut i = 0;
     while i < args.len() {
         match args[i].as_str() {
             "-m" | "--modules" => {
@@ -32,57 +166,791 @@ fn main() {
                     eprintln!("Error: -m/--modules requires a path");
                     std::process::exit(1);
                 }
-                modules_spec = Some(args[i].clone());
+                options.modules_spec = Some(args[i].clone());
+            }
+            "-e" | "--eval" => {
+                i += 1;
+                if i >= args.len() {
+                    eprintln!("Error: -e/--eval requires code to run");
+                    std::process::exit(1);
+                }
+                options.eval.push(args[i].clone());
+            }
+            "--check" => {
+                options.check = true;
+            }
+            "--ast" | "--dump-ast" => {
+                options.dump_ast = true;
+            }
+            "--ast=json" | "--dump-ast=json" => {
+                options.dump_ast = true;
+                options.ast_json = true;
+            }
+            "--tokens" | "--dump-tokens" => {
+                options.dump_tokens = true;
+            }
+            "-w" | "--watch" => {
+                options.watch = true;
+            }
+            "--no-color" => {
+                options.no_color = true;
+            }
+            "--allow-remote" => {
+                options.allow_remote = true;
+            }
+            "--strict" => {
+                options.strict = true;
+            }
+            "--strict-index" => {
+                options.strict_index = true;
+            }
+            "--overflow" => {
+                i += 1;
+                match args.get(i).and_then(|v| Overflow::from_name(v)) {
+                    Some(mode) => options.overflow = Some(mode),
+                    None => {
+                        eprintln!("Error: --overflow requires wrap, saturate, error or promote");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "-t" | "--timeout" => {
+                i += 1;
+                match args.get(i).and_then(|v| v.parse::<u64>().ok()) {
+                    Some(secs) => options.timeout = Some(secs),
+                    None => {
+                        eprintln!("Error: -t/--timeout requires a number of seconds");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--max-depth" => {
+                i += 1;
+                match args.get(i).and_then(|v| v.parse::<usize>().ok()) {
+                    Some(depth) => options.max_depth = Some(depth),
+                    None => {
+                        eprintln!("Error: --max-depth requires a number of calls");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "-V" | "--version" => {
+                println!(
+                    "minilux {} (commit {}, {})",
+                    runtime::VERSION,
+                    env!("MINILUX_COMMIT"),
+                    env!("MINILUX_TARGET")
+                );
+                std::process::exit(0);
             }
             "-h" | "--help" => {
-                print_usage_and_exit(&args[0]);
+                print_usage_and_exit(prog);
             }
-            s if s.starts_with('-') => {
+            s if s.starts_with('-') && s != "-" => {
                 eprintln!("Error: unknown option: {}", s);
-                print_usage_and_exit(&args[0]);
+                print_usage_and_exit(prog);
             }
             _ => {
-                // first positional arg is script path
-                if script.is_none() {
-                    script = Some(args[i].clone());
+                // first positional arg is script path ("-" reads the script from stdin);
+                // everything after it, options included, belongs to the script
+                options.script = Some(args[i].clone());
+                options.args = args[i + 1..].to_vec();
+                return;
+            }
+        }
+        i += 1;
+    }
+}
+
+fn read_source(path: &str) -> Result<String, String> {
+    if path == "-" {
+        let mut content = String::new();
+        io::stdin()
+            .read_to_string(&mut content)
+            .map_err(|e| format!("Failed to read stdin: {}", e))?;
+        Ok(content)
+    } else {
+        fs::read_to_string(path).map_err(|e| format!("Failed to read file: {}", e))
+    }
+}
+
+fn doc_command(prog: &str, args: &[String]) -> ! {
+    let mut html = false;
+    let mut paths: Vec<String> = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "--html" => html = true,
+            "-h" | "--help" => print_usage_and_exit(prog),
+            s if s.starts_with('-') => {
+                eprintln!("Error: unknown doc option: {}", s);
+                print_usage_and_exit(prog);
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(".".to_string());
+    }
+
+    let mut files = Vec::new();
+    for path in &paths {
+        if let Err(e) = collect_scripts(Path::new(path), ".mi", &mut files) {
+            eprintln!("Error: {}: {}", path, e);
+            std::process::exit(2);
+        }
+    }
+
+    let mut modules = Vec::new();
+    for file in &files {
+        match fs::read_to_string(file) {
+            Ok(source) => modules.push(doc::document_source(&file.display().to_string(), &source)),
+            Err(e) => {
+                eprintln!("Error: {}: Failed to read file: {}", file.display(), e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if html {
+        print!("{}", doc::render_html(&modules));
+    } else {
+        print!("{}", doc::render_markdown(&modules));
+    }
+    std::process::exit(0);
+}
+
+/// Add `path` if it is a file, or every file below it whose name ends with
+/// `suffix` if it is a directory.
+fn collect_scripts(path: &Path, suffix: &str, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    if !path.is_dir() {
+        if !path.exists() {
+            return Err("No such file or directory".to_string());
+        }
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)
+        .map_err(|e| format!("Failed to read directory: {}", e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            collect_scripts(&entry, suffix, files)?;
+        } else if entry.to_string_lossy().ends_with(suffix) {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+fn build_command(prog: &str, args: &[String]) -> ! {
+    let mut modules_spec: Option<String> = None;
+    let mut output: Option<PathBuf> = None;
+    let mut script: Option<PathBuf> = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-m" | "--modules" | "-o" | "--output" => {
+                let value = match args.get(i + 1) {
+                    Some(value) => value.clone(),
+                    None => {
+                        eprintln!("Error: {} requires a value", args[i]);
+                        std::process::exit(2);
+                    }
+                };
+                if matches!(args[i].as_str(), "-m" | "--modules") {
+                    modules_spec = Some(value);
                 } else {
-                    eprintln!("Error: unexpected extra argument: {}", args[i]);
-                    print_usage_and_exit(&args[0]);
+                    output = Some(PathBuf::from(value));
                 }
+                i += 1;
+            }
+            "-h" | "--help" => print_usage_and_exit(prog),
+            s if s.starts_with('-') => {
+                eprintln!("Error: unknown build option: {}", s);
+                print_usage_and_exit(prog);
             }
+            _ if script.is_none() => script = Some(PathBuf::from(&args[i])),
+            _ => print_usage_and_exit(prog),
         }
         i += 1;
     }
 
-    if let Some(path) = script {
-        if let Err(e) = execute_file(&path, modules_spec.as_deref()) {
+    let script = script.unwrap_or_else(|| print_usage_and_exit(prog));
+    let output = output.unwrap_or_else(|| {
+        let stem = script.file_stem().unwrap_or_default();
+        PathBuf::from(stem).with_extension(env::consts::EXE_EXTENSION)
+    });
+    let mut resolver = new_interpreter(modules_spec.as_deref());
+    match bundle::build(&script, &output, &mut resolver) {
+        Ok(count) => {
+            println!("Built {} ({} script(s))", output.display(), count);
+            std::process::exit(0);
+        }
+        Err(e) => {
             eprintln!("Error: {}", e);
             std::process::exit(1);
         }
-    } else {
-        run_repl(modules_spec.as_deref());
     }
 }
 
-fn execute_file(path: &str, modules_spec: Option<&str>) -> Result<(), String> {
-    let content = fs::read_to_string(path).map_err(|e| format!("Failed to read file: {}", e))?;
+/// Run the script bundled into this executable by `minilux build`.
+fn run_bundle(bundle: bundle::Bundle) -> ! {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_file_system(Box::new(bundle.file_system()));
+    interpreter.add_modules_dir(bundle::Bundle::root());
+    interpreter.push_base_dir(bundle::Bundle::root());
+    // A bundled program has no options of its own: every argument is the script's.
+    let args: Vec<String> = env::args().skip(1).collect();
+    set_script_args(&mut interpreter, &args);
+
+    let source = bundle.main_source();
+    if let Err(e) = interpreter.execute_source(source) {
+        if let Some(status) = interpreter.take_exit_status() {
+            std::process::exit(status);
+        }
+        let color = diagnostics::use_color(false);
+        eprintln!(
+            "{}",
+            render_runtime_error(&mut interpreter, &e, &bundle.main, source, color)
+        );
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+fn run_md_command(prog: &str, args: &[String]) -> ! {
+    let mut modules_spec: Option<&str> = None;
+    let mut no_color = false;
+    let mut path: Option<&str> = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-m" | "--modules" => {
+                i += 1;
+                match args.get(i) {
+                    Some(spec) => modules_spec = Some(spec),
+                    None => {
+                        eprintln!("Error: -m/--modules requires a path");
+                        std::process::exit(2);
+                    }
+                }
+            }
+            "--no-color" => no_color = true,
+            "-h" | "--help" => print_usage_and_exit(prog),
+            s if path.is_none() && !s.starts_with('-') => path = Some(s),
+            s => {
+                eprintln!("Error: unexpected run-md argument: {}", s);
+                print_usage_and_exit(prog);
+            }
+        }
+        i += 1;
+    }
+    let Some(path) = path else {
+        print_usage_and_exit(prog);
+    };
+    let color = diagnostics::use_color(no_color);
+
+    let markdown = match fs::read_to_string(path) {
+        Ok(markdown) => markdown,
+        Err(e) => {
+            eprintln!("Error: Failed to read {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut interpreter = new_interpreter(modules_spec);
+    if let Some(dir) = Path::new(path).parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        interpreter.push_base_dir(dir.to_path_buf());
+    }
+    for block in code_blocks(&markdown) {
+        if let Err(e) = interpreter.execute_source(&block) {
+            if let Some(status) = interpreter.take_exit_status() {
+                std::process::exit(status);
+            }
+            eprintln!("{}", render_runtime_error(&mut interpreter, &e, path, &block, color));
+            std::process::exit(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+/// The ```minilux (or ```mi) fenced blocks of a Markdown document, in order.
+/// Each block is preceded by blank lines so its line numbers match the document.
+fn code_blocks(markdown: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut open: Option<(String, String)> = None;
+
+    for (index, line) in markdown.lines().enumerate() {
+        let trimmed = line.trim_start();
+        match &mut open {
+            Some((fence, code)) => {
+                // A closing fence is at least as long as the opening one.
+                let closing = trimmed.trim_end();
+                if closing.starts_with(fence.as_str()) && closing.chars().all(|c| fence.starts_with(c)) {
+                    blocks.push(std::mem::take(code));
+                    open = None;
+                } else {
+                    code.push_str(line);
+                    code.push('\n');
+                }
+            }
+            None => {
+                let marker = match trimmed.chars().next() {
+                    Some(c @ ('`' | '~')) => c,
+                    _ => continue,
+                };
+                let fence: String = trimmed.chars().take_while(|&c| c == marker).collect();
+                if fence.len() < 3 {
+                    continue;
+                }
+                let language = trimmed[fence.len()..].split_whitespace().next().unwrap_or("");
+                if language == "minilux" || language == "mi" {
+                    open = Some((fence, "\n".repeat(index + 1)));
+                }
+            }
+        }
+    }
+    // An unterminated fence runs to the end of the document.
+    if let Some((_, code)) = open {
+        blocks.push(code);
+    }
+    blocks
+}
+
+fn emit_sh_command(prog: &str, args: &[String]) -> ! {
+    let path = match args {
+        [path] if !path.starts_with('-') => path,
+        _ => print_usage_and_exit(prog),
+    };
+    let source = match read_source(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let statements = match Parser::new(&source).parse_checked() {
+        Ok(statements) => statements,
+        Err(errors) => {
+            let color = diagnostics::use_color(false);
+            eprintln!("{}", diagnostics::render_syntax_errors(&errors, path, &source, color));
+            std::process::exit(1);
+        }
+    };
+    match transpile::emit_sh(&statements, path) {
+        Ok(script) => print!("{}", script),
+        Err(e) => {
+            eprintln!("Error: {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+fn pkg_command(prog: &str, args: &[String]) -> ! {
+    if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+        print_usage_and_exit(prog);
+    }
+    if let Err(e) = pkg::run(args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+fn test_command(prog: &str, args: &[String]) -> ! {
+    let mut modules_spec: Option<String> = None;
+    let mut paths: Vec<String> = Vec::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-m" | "--modules" => {
+                i += 1;
+                match args.get(i) {
+                    Some(spec) => modules_spec = Some(spec.clone()),
+                    None => {
+                        eprintln!("Error: -m/--modules requires a path");
+                        std::process::exit(2);
+                    }
+                }
+            }
+            "-h" | "--help" => print_usage_and_exit(prog),
+            s if s.starts_with('-') => {
+                eprintln!("Error: unknown test option: {}", s);
+                print_usage_and_exit(prog);
+            }
+            _ => paths.push(args[i].clone()),
+        }
+        i += 1;
+    }
+    if paths.is_empty() {
+        paths.push(".".to_string());
+    }
+
+    let mut files = Vec::new();
+    for path in &paths {
+        if let Err(e) = collect_scripts(Path::new(path), "_test.mi", &mut files) {
+            eprintln!("Error: {}: {}", path, e);
+            std::process::exit(2);
+        }
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+    for file in &files {
+        println!("{}", file.display());
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => {
+                println!("  FAIL (Failed to read file: {})", e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let statements = match Parser::new(&source).parse_checked() {
+            Ok(statements) => statements,
+            Err(errors) => {
+                for error in errors {
+                    println!("  FAIL (syntax) {} (line {})", error.message, error.span.line);
+                }
+                failed += 1;
+                continue;
+            }
+        };
+        let tests: Vec<String> = statements
+            .iter()
+            .filter_map(|stmt| match &stmt.kind {
+                parser::Statement::FunctionDef { name, .. } if name.starts_with("test_") => {
+                    Some(name.clone())
+                }
+                _ => None,
+            })
+            .collect();
+
+        // Top-level code runs first, so it can define helpers and fixtures.
+        let mut interpreter = new_interpreter(modules_spec.as_deref());
+        if let Some(dir) = file.parent() {
+            interpreter.push_base_dir(dir.to_path_buf());
+        }
+        if let Err(e) = interpreter.execute(statements) {
+            println!("  FAIL (setup) {}", describe_failure(&mut interpreter, &e, file));
+            failed += 1;
+            continue;
+        }
+
+        for test in tests {
+            match interpreter.call_function(&test, Vec::new()) {
+                Ok(_) => {
+                    println!("  ok   {}", test);
+                    passed += 1;
+                }
+                Err(e) => {
+                    println!("  FAIL {}: {}", test, describe_failure(&mut interpreter, &e, file));
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    println!();
+    println!("{} passed, {} failed", passed, failed);
+    std::process::exit(if failed > 0 { 1 } else { 0 });
+}
+
+/// Error message with the line it was raised on, when known, followed by
+/// the calls that led there.
+fn describe_failure(interpreter: &mut Interpreter, message: &str, test_file: &Path) -> String {
+    // A test calling exit() fails with "exit(n)" rather than ending the run.
+    interpreter.take_exit_status();
+    let trace = interpreter.take_error_trace();
+    let described = match interpreter.take_error_location() {
+        Some((file, span)) => match file {
+            Some(file) => format!("{} ({}:{})", message, file.display(), span.line),
+            None => format!("{} (line {})", message, span.line),
+        },
+        None => message.to_string(),
+    };
+    let main_name = test_file.display().to_string();
+    described + &diagnostics::render_trace(&trace, &main_name, false)
+}
 
-    let mut parser = Parser::new(&content);
+fn fmt_command(prog: &str, args: &[String]) -> ! {
+    let mut check = false;
+    let mut files: Vec<String> = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-h" | "--help" => print_usage_and_exit(prog),
+            s if s.starts_with('-') && s != "-" => {
+                eprintln!("Error: unknown fmt option: {}", s);
+                print_usage_and_exit(prog);
+            }
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    let mut status = 0;
+    for path in &files {
+        let result = read_source(path).and_then(|source| {
+            let formatted = formatter::format_source(&source)?;
+            if check {
+                if formatted != source {
+                    println!("{}", path);
+                    status = 1;
+                }
+            } else if path == "-" {
+                print!("{}", formatted);
+            } else if formatted != source {
+                fs::write(path, formatted).map_err(|e| format!("Failed to write file: {}", e))?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            eprintln!("Error: {}: {}", path, e);
+            status = 2;
+        }
+    }
+    std::process::exit(status);
+}
+
+fn lint_command(prog: &str, args: &[String]) -> ! {
+    let mut modules_spec: Option<String> = None;
+    let mut files: Vec<String> = Vec::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-m" | "--modules" => {
+                i += 1;
+                match args.get(i) {
+                    Some(spec) => modules_spec = Some(spec.clone()),
+                    None => {
+                        eprintln!("Error: -m/--modules requires a path");
+                        std::process::exit(2);
+                    }
+                }
+            }
+            "-h" | "--help" => print_usage_and_exit(prog),
+            s if s.starts_with('-') && s != "-" => {
+                eprintln!("Error: unknown lint option: {}", s);
+                print_usage_and_exit(prog);
+            }
+            _ => files.push(args[i].clone()),
+        }
+        i += 1;
+    }
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    let mut status = 0;
+    for path in &files {
+        let source = match read_source(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Error: {}: {}", path, e);
+                status = 2;
+                continue;
+            }
+        };
+
+        let mut resolver = Interpreter::new();
+        if let Some(spec) = modules_spec.as_deref() {
+            resolver.set_modules_path(spec);
+        }
+        if let Some(dir) = Path::new(path).parent().filter(|_| path != "-") {
+            let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+            resolver.push_base_dir(dir.to_path_buf());
+        }
+
+        for diagnostic in lint::lint_source(&source, &mut resolver) {
+            println!(
+                "{}:{}:{}: warning: {}",
+                path, diagnostic.span.line, diagnostic.span.column, diagnostic.message
+            );
+            if status == 0 {
+                status = 1;
+            }
+        }
+    }
+    std::process::exit(status);
+}
+
+fn kernel_command(prog: &str, args: &[String]) -> ! {
+    let mut modules_spec: Option<&str> = None;
+    let mut connection_file: Option<&str> = None;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--install" => match kernel::install() {
+                Ok(path) => {
+                    println!("Installed kernel spec {}", path.display());
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            },
+            "-m" | "--modules" => {
+                i += 1;
+                match args.get(i) {
+                    Some(spec) => modules_spec = Some(spec),
+                    None => print_usage_and_exit(prog),
+                }
+            }
+            s if connection_file.is_none() && !s.starts_with('-') => connection_file = Some(s),
+            _ => print_usage_and_exit(prog),
+        }
+        i += 1;
+    }
+    let Some(connection_file) = connection_file else {
+        print_usage_and_exit(prog);
+    };
+    if let Err(e) = kernel::run(Path::new(connection_file), modules_spec) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+/// Run a stdio protocol server (`lsp` or `dap`), which only takes `-m`.
+fn server_command(prog: &str, args: &[String], serve: fn(Option<&str>) -> Result<(), String>) -> ! {
+    let modules_spec = match args {
+        [] => None,
+        [flag, spec] if flag == "-m" || flag == "--modules" => Some(spec.as_str()),
+        _ => print_usage_and_exit(prog),
+    };
+    if let Err(e) = serve(modules_spec) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+// Both dumps are often piped into a pager or `head`, so they stop quietly
+// once nobody is reading.
+fn print_tokens(content: &str) {
+    let mut out = io::stdout().lock();
+    let mut lexer = Lexer::new(content);
+    for (token, span) in lexer.tokenize_with_spans() {
+        if writeln!(out, "{:<16} {:?}", span.to_string(), token).is_err() {
+            break;
+        }
+    }
+}
+
+fn print_ast(content: &str, json: bool) {
+    let mut out = io::stdout().lock();
+    let mut parser = Parser::new(content);
     let statements = parser.parse();
+    if json {
+        let tree = ast_json::statements(&statements);
+        let _ = writeln!(out, "{}", serde_json::to_string_pretty(&tree).unwrap_or_default());
+        return;
+    }
+    for stmt in statements {
+        if writeln!(out, "{:#?}", stmt).is_err() {
+            break;
+        }
+    }
+}
 
+fn new_interpreter(modules_spec: Option<&str>) -> Interpreter {
     let mut interpreter = Interpreter::new();
     if let Some(spec) = modules_spec {
         interpreter.set_modules_path(spec);
     }
-    let absolute_path = {
-        let provided = Path::new(path);
-        if provided.is_absolute() {
-            provided.to_path_buf()
-        } else {
-            env::current_dir()
-                .map_err(|e| format!("Failed to determine current directory: {}", e))?
-                .join(provided)
-        }
+    interpreter
+}
+
+/// Interpreter for running the script named on the command line, or the REPL
+fn script_interpreter(options: &Options) -> Interpreter {
+    let mut interpreter = new_interpreter(options.modules_spec.as_deref());
+    interpreter.set_remote_includes(options.allow_remote);
+    if let Some(depth) = options.max_depth {
+        interpreter.set_max_call_depth(depth);
+    }
+    interpreter.set_strict(options.strict);
+    interpreter.set_strict_index(options.strict_index);
+    if let Some(mode) = options.overflow {
+        interpreter.set_overflow(mode);
+    }
+    set_script_args(&mut interpreter, &options.args);
+    interpreter
+}
+
+/// Pass the script its command line arguments as `$ARGV` and `$ARGC`
+fn set_script_args(interpreter: &mut Interpreter, args: &[String]) {
+    let values = args.iter().map(|arg| Value::String(arg.clone())).collect();
+    interpreter.set_var("ARGV", Value::array(values));
+    interpreter.set_var("ARGC", Value::Int(args.len() as i64));
+}
+
+/// Parse the script, or the -e code, without running it; errors come back
+/// already rendered for display
+fn check_syntax(options: &Options, color: bool) -> Result<(), String> {
+    let (name, content) =
+        given_source(options).map_err(|e| diagnostics::render_error(&e, None, color))?;
+    Parser::new(&content)
+        .parse_checked()
+        .map(|_| ())
+        .map_err(|errors| diagnostics::render_syntax_errors(&errors, &name, &content, color))
+}
+
+/// The name and source of the code to inspect rather than run: the -e code,
+/// the script, or stdin when neither was given
+fn given_source(options: &Options) -> Result<(String, String), String> {
+    if !options.eval.is_empty() {
+        return Ok(("<eval>".to_string(), options.eval.join("\n")));
+    }
+    let path = options.script.as_deref().unwrap_or("-");
+    let name = if path == "-" { "<stdin>" } else { path };
+    Ok((name.to_string(), read_source(path)?))
+}
+
+/// Run a script; errors come back already rendered for display
+fn run_file(interpreter: &mut Interpreter, path: &str, color: bool) -> Result<(), String> {
+    let content =
+        read_source(path).map_err(|e| diagnostics::render_error(&e, None, color))?;
+    if path == "-" {
+        run_source(interpreter, "<stdin>", None, &content, color)
+    } else {
+        run_source(interpreter, path, Some(Path::new(path)), &content, color)
+    }
+}
+
+/// Run `content`, read from `file`, or from stdin or the command line when
+/// that is None; errors are reported under `name`
+fn run_source(
+    interpreter: &mut Interpreter,
+    name: &str,
+    file: Option<&Path>,
+    content: &str,
+    color: bool,
+) -> Result<(), String> {
+    // A script with syntax errors does not run at all.
+    let statements = Parser::new(content)
+        .parse_checked()
+        .map_err(|errors| diagnostics::render_syntax_errors(&errors, name, content, color))?;
+
+    let absolute_path = match file {
+        Some(provided) if provided.is_absolute() => provided.to_path_buf(),
+        // Code not read from a file resolves includes relative to the working directory.
+        _ => env::current_dir()
+            .map_err(|e| format!("Failed to determine current directory: {}", e))?
+            .join(file.unwrap_or(Path::new("-"))),
     };
 
     let base_dir = absolute_path.parent().map(|p| p.to_path_buf());
@@ -96,59 +964,186 @@ fn execute_file(path: &str, modules_spec: Option<&str>) -> Result<(), String> {
         interpreter.pop_base_dir();
     }
 
-    result
+    result.map_err(|e| render_runtime_error(interpreter, &e, name, content, color))
 }
 
-fn run_repl(modules_spec: Option<&str>) {
-    let stdin = io::stdin();
-    let mut reader = stdin.lock();
-    let mut input = String::new();
+/// Exit as the script asked with exit(), or report the error it stopped on
+fn finish(interpreter: &mut Interpreter, result: Result<(), String>) {
+    if let Err(e) = result {
+        if let Some(status) = interpreter.take_exit_status() {
+            std::process::exit(status);
+        }
+        eprintln!("{}", e);
+        let code = if interpreter::interrupted() { INTERRUPT_EXIT_CODE } else { 1 };
+        std::process::exit(code);
+    }
+}
 
-    println!("Minilux Interpreter Console (REPL)");
-    println!("Version 0.1.0 on {} -- [Rust]", get_system_info());
-    println!("Type \"exit\" to quit");
-    println!();
+fn render_runtime_error(
+    interpreter: &mut Interpreter,
+    message: &str,
+    path: &str,
+    content: &str,
+    color: bool,
+) -> String {
+    let trace = interpreter.take_error_trace();
+    let (file, span) = match interpreter.take_error_location() {
+        Some(location) => location,
+        None => return diagnostics::render_error(message, None, color),
+    };
 
-    loop {
-        input.clear();
-        print!("> ");
-        std::io::stdout().flush().ok();
+    // Errors inside included files point into that file's source.
+    let included = file.map(|f| {
+        let source = match f.to_str().and_then(stdlib::source) {
+            Some(source) => source.to_string(),
+            // Remote modules are read back from the download cache.
+            None => {
+                let cached = f.to_str().filter(|f| remote::is_url(f)).and_then(remote::cache_path);
+                fs::read_to_string(cached.as_ref().unwrap_or(&f)).unwrap_or_default()
+            }
+        };
+        (f.display().to_string(), source)
+    });
+    let main_name = if path == "-" { "<stdin>" } else { path };
+    let (name, source) = match &included {
+        Some((name, source)) => (name.as_str(), source.as_str()),
+        None => (main_name, content),
+    };
+    let mut out = diagnostics::render_error(
+        message,
+        Some(diagnostics::Location { name, source, span }),
+        color,
+    );
+    out.push_str(&diagnostics::render_trace(&trace, main_name, color));
+    out
+}
 
-        if reader.read_line(&mut input).is_err() {
-            break;
-        }
+/// Exit status used when a script exceeds its --timeout
+const TIMEOUT_EXIT_CODE: i32 = 124;
 
-        let trimmed = input.trim();
-        if trimmed == "exit" {
-            break;
-        }
+/// Exit status used when a script is stopped with Ctrl-C, as shells report it
+const INTERRUPT_EXIT_CODE: i32 = 130;
 
-        if trimmed.is_empty() {
-            continue;
+/// Make Ctrl-C stop the running script before its next statement rather
+/// than kill the process, so the REPL survives it. A second Ctrl-C before
+/// that ends the process at once.
+#[cfg(unix)]
+fn handle_interrupts() {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        if interpreter::interrupt() {
+            // Only async-signal-safe calls here.
+            unsafe { libc::_exit(INTERRUPT_EXIT_CODE) };
         }
+    }
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
 
-        let mut parser = Parser::new(trimmed);
-        let statements = parser.parse();
+#[cfg(not(unix))]
+fn handle_interrupts() {}
 
-        let mut interpreter = Interpreter::new();
-        if let Some(spec) = modules_spec {
-            interpreter.set_modules_path(spec);
-        }
-    if let Some(spec) = modules_spec {
-        interpreter.set_modules_path(spec);
+/// Abort the whole process once `secs` seconds of wall-clock time have passed.
+/// A separate thread is used so that blocking builtins (sleep, shell, sockets)
+/// are interrupted as well.
+fn start_watchdog(secs: u64) {
+    thread::spawn(move || {
+        thread::sleep(Duration::from_secs(secs));
+        io::stdout().flush().ok();
+        eprintln!("Error: execution timed out after {} second(s)", secs);
+        std::process::exit(TIMEOUT_EXIT_CODE);
+    });
+}
+
+/// Run the script, then re-run it every time it or one of its includes changes.
+fn watch_file(path: &str, options: &Options, color: bool) -> ! {
+    if path == "-" {
+        eprintln!("Error: --watch needs a script file, not stdin");
+        std::process::exit(1);
     }
-        if let Err(e) = interpreter.execute(statements) {
-            eprintln!("Error: {}", e);
+
+    loop {
+        let mut interpreter = script_interpreter(options);
+        if let Err(e) = run_file(&mut interpreter, path, color) {
+            match interpreter.take_exit_status() {
+                Some(status) => eprintln!("[watch] exited with status {}", status),
+                None => eprintln!("{}", e),
+            }
         }
+
+        let mut files = vec![PathBuf::from(path)];
+        files.extend(interpreter.included_files().iter().cloned());
+        let stamps = modification_times(&files);
+
+        eprintln!("[watch] waiting for changes ({} file(s))...", files.len());
+        while modification_times(&files) == stamps {
+            if interpreter::interrupted() {
+                std::process::exit(INTERRUPT_EXIT_CODE);
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+        eprintln!("[watch] change detected, re-running {}", path);
     }
 }
 
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
 fn print_usage_and_exit(prog: &str) -> ! {
-    eprintln!("Usage: {} [-m <paths>] [script.mi]", prog);
+    eprintln!(
+        "Usage: {} [-m <paths>] [-w] [-t <secs>] [--allow-remote] [--check | --ast[=json] | --tokens] [script.mi | - | -e <code>] [args...]",
+        prog
+    );
+    eprintln!("       {} build [-m <paths>] [-o <output>] script.mi", prog);
+    eprintln!("       {} run-md [-m <paths>] document.md", prog);
+    eprintln!("       {} emit-sh script.mi", prog);
+    eprintln!("       {} fmt [--check] [files...]", prog);
+    eprintln!("       {} doc [--html] [files or dirs...]", prog);
+    eprintln!("       {} lint [-m <paths>] [files...]", prog);
+    eprintln!("       {} test [-m <paths>] [files or dirs...]", prog);
+    eprintln!("       {} pkg install <git-url | tarball> [--name <name>] [--version <tag>]", prog);
+    eprintln!("       {} pkg list | pkg remove <name>", prog);
+    eprintln!("       {} lsp [-m <paths>]", prog);
+    eprintln!("       {} dap [-m <paths>]", prog);
+    eprintln!("       {} kernel [-m <paths>] <connection-file> | kernel --install", prog);
     eprintln!();
     eprintln!("Options:");
     eprintln!("  -m, --modules <paths>   Module search path list (':' or ';' separated)");
+    eprintln!("  -e, --eval <code>       Run <code> instead of a script; repeat to add more lines");
+    eprintln!("  -w, --watch             Re-run the script whenever it or an included file changes");
+    eprintln!("  -t, --timeout <secs>    Abort with exit status 124 after <secs> seconds");
+    eprintln!("      --no-color          Disable colored diagnostics (also honors NO_COLOR)");
+    eprintln!("      --allow-remote      Allow including modules from http(s) URLs");
+    eprintln!("      --max-depth <n>     Stop scripts nesting more than <n> function calls (default 1000)");
+    eprintln!("      --strict            Make undefined variables and functions errors, not warnings");
+    eprintln!("      --strict-index      Make array and string indices out of range errors");
+    eprintln!("      --overflow <mode>   Integer overflow: error (default), wrap, saturate or promote");
+    eprintln!("      --check             Check the script for syntax errors without running it");
+    eprintln!("      --ast, --dump-ast   Print the parsed syntax tree with source spans instead of running");
+    eprintln!("      --ast=json          Print the syntax tree as JSON instead");
+    eprintln!("      --tokens, --dump-tokens");
+    eprintln!("                          Print the lexer token stream with source spans");
+    eprintln!("  -V, --version           Show the version, commit and build target");
     eprintln!("  -h, --help              Show this help");
+    eprintln!();
+    eprintln!("Use '-' as the script path to read the program from stdin.");
+    eprintln!("Default options can be set in the MINILUX_OPTIONS environment variable.");
+    eprintln!("'build' writes a standalone executable that runs the script and its includes.");
+    eprintln!("'run-md' runs the ```minilux code blocks of a Markdown file in order.");
+    eprintln!("'emit-sh' prints a POSIX shell translation of a script (experimental).");
+    eprintln!("'fmt' rewrites files in canonical style; --check only lists files that differ.");
+    eprintln!("'doc' prints Markdown (or HTML) API docs from '##' comments above functions.");
+    eprintln!("'lint' reports likely mistakes and exits with status 1 when it finds any.");
+    eprintln!("'test' runs the test_* functions of every *_test.mi file and prints a summary.");
+    eprintln!("'pkg' manages modules in ~/.minilux/modules (or $MINILUX_HOME/modules).");
+    eprintln!("'lsp' serves the Language Server Protocol over stdin/stdout for editors.");
+    eprintln!("'dap' serves the Debug Adapter Protocol over stdin/stdout for debuggers.");
+    eprintln!("'kernel' runs as a Jupyter kernel; --install registers it with Jupyter.");
     std::process::exit(1);
 }
 
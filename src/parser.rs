@@ -4,14 +4,18 @@
 // License: MPL 2.0
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::lexer::{Lexer, Token};
+use crate::lexer::{Lexer, Span, Token};
+use crate::value::Overflow;
 use std::collections::VecDeque;
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub enum Expr {
     Int(i64),
+    Float(f64),
+    Bool(bool),
     String(String),
-    Regex(String),
+    Regex { pat: String, flags: String },
     SubstCall { pat: String, repl: String, flags: String, input: Box<Expr> },
     Variable(String),
     Binary {
@@ -24,14 +28,32 @@ pub enum Expr {
         expr: Box<Expr>,
     },
     Array(Vec<Expr>),
+    /// `{ "key": value, ... }`
+    Map(Vec<(Expr, Expr)>),
+    /// `expr[index]`, or `expr?[index]` when `optional`
     Index {
         expr: Box<Expr>,
         index: Box<Expr>,
+        optional: bool,
+    },
+    /// `expr[start:end]`; either bound may be left out
+    Slice {
+        expr: Box<Expr>,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+        optional: bool,
     },
     FunctionCall {
         name: String,
         #[allow(dead_code)]
         args: Vec<Expr>,
+        /// Where the call appears, so errors in it can point at it
+        span: Span,
+    },
+    /// `function ($a, $b) { ... }` used as a value
+    Lambda {
+        params: Vec<String>,
+        body: Rc<Vec<Stmt>>,
     },
 }
 
@@ -42,6 +64,13 @@ pub enum BinOp {
     Multiply,
     Divide,
     Modulo,
+    Power,
+    /// `a ?? b`: `a` unless it is nil
+    Coalesce,
+    /// `a..b`: the integers from `a` through `b`
+    RangeInclusive,
+    /// `a..<b`: the integers from `a` up to, but not including, `b`
+    RangeExclusive,
     Equal,
     NotEqual,
     Match,
@@ -65,20 +94,34 @@ pub enum Statement {
         var: String,
         value: Expr,
     },
+    /// `$var[i][j]... = value`
     ArrayAssignment {
         var: String,
-        index: Expr,
+        indices: Vec<Expr>,
         value: Expr,
     },
     If {
         condition: Expr,
-        then_body: Vec<Statement>,
-        elseif_parts: Vec<(Expr, Vec<Statement>)>,
-        else_body: Option<Vec<Statement>>,
+        then_body: Vec<Stmt>,
+        elseif_parts: Vec<(Expr, Vec<Stmt>)>,
+        else_body: Option<Vec<Stmt>>,
     },
     While {
         condition: Expr,
-        body: Vec<Statement>,
+        body: Vec<Stmt>,
+    },
+    /// `for (init; condition; step) { ... }`; every part may be left empty
+    For {
+        init: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        step: Option<Box<Stmt>>,
+        body: Vec<Stmt>,
+    },
+    /// `for ($var in iterable) { ... }`
+    ForIn {
+        var: String,
+        iterable: Expr,
+        body: Vec<Stmt>,
     },
     Printf {
         format: String,
@@ -125,13 +168,29 @@ pub enum Statement {
         name: String,
         var: String,
     },
+    Serialopen {
+        name: String,
+        device: Expr,
+        baud: Expr,
+    },
+    Serialclose {
+        name: String,
+    },
+    Serialwrite {
+        name: String,
+        data: Expr,
+    },
+    Serialread {
+        name: String,
+        var: String,
+    },
     Include {
         path: String,
     },
     FunctionDef {
         name: String,
         params: Vec<String>,
-        body: Vec<Statement>,
+        body: Vec<Stmt>,
     },
     FunctionCall {
         name: String,
@@ -141,18 +200,178 @@ pub enum Statement {
     Return {
         value: Option<Expr>,
     },
+    /// `global $a, $b`: inside a function, use the global variables of these names
+    Global {
+        vars: Vec<String>,
+    },
+    /// `strict`: from here on, undefined variables and functions are errors
+    Strict,
+    /// `overflow wrap|saturate|error`: from here on, what integer overflow does
+    Overflow(Overflow),
+    /// `try { } catch ($e) { } finally { }`; at least one of catch and finally is present
+    Try {
+        body: Vec<Stmt>,
+        catch_var: Option<String>,
+        catch_body: Option<Vec<Stmt>>,
+        finally_body: Option<Vec<Stmt>>,
+    },
+    Throw {
+        value: Expr,
+    },
+}
+
+/// A statement together with the source span it was parsed from
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: Statement,
+    pub span: Span,
+}
+
+/// A syntax error and where it was found
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+}
+
+/// One-line summary of a failed parse, naming the first error
+pub fn syntax_error_message(errors: &[ParseError]) -> String {
+    let first = &errors[0].message;
+    match errors.len() {
+        1 => format!("Syntax error: {}", first),
+        n => format!("Syntax error: {} (and {} more)", first, n - 1),
+    }
 }
 
 pub struct Parser {
     tokens: VecDeque<Token>,
+    spans: VecDeque<Span>,
+    last_span: Span,
+    errors: Vec<ParseError>,
+    /// Set after an error until the next statement starts
+    recovering: bool,
+    /// How many blocks the current statement is nested in
+    blocks: usize,
 }
 
 impl Parser {
     pub fn new(input: &str) -> Self {
         let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+        let mut errors = Vec::new();
+        let (tokens, spans) = lexer
+            .tokenize_with_spans()
+            .into_iter()
+            .filter_map(|(token, span)| match token {
+                Token::Error(message) => {
+                    // A bad number literal still stands for a value, so the
+                    // expression around it parses without a second error.
+                    let literal = message.starts_with("integer literal");
+                    errors.push(ParseError { span, message });
+                    literal.then_some((Token::Int(0), span))
+                }
+                token => Some((token, span)),
+            })
+            .unzip();
         Parser {
-            tokens: tokens.into_iter().collect(),
+            tokens,
+            spans,
+            last_span: Span::default(),
+            errors,
+            recovering: false,
+            blocks: 0,
+        }
+    }
+
+    /// Syntax errors found so far; statements they affect are left out of the tree
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Parse the whole input, failing with every syntax error found
+    pub fn parse_checked(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
+        let statements = self.parse();
+        if self.errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    /// Parse the whole input as one expression, with the span it covers,
+    /// failing with every syntax error found
+    pub fn parse_expression(&mut self) -> Result<(Expr, Span), Vec<ParseError>> {
+        self.skip_newlines();
+        let start = self.current_span();
+        let expr = self.parse_expr();
+        let span = self.span_from(start);
+        self.skip_statement_end();
+        if self.current() != &Token::Eof {
+            let found = self.describe_current();
+            self.error(format!("Expected the end of the expression, found {}", found));
+        }
+        if self.errors.is_empty() {
+            Ok((expr, span))
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    /// Record an error at the current token. Only the first error in a
+    /// statement is kept, since later ones are usually caused by it.
+    fn error(&mut self, message: String) {
+        if self.recovering {
+            return;
+        }
+        self.recovering = true;
+        let span = self.current_span();
+        self.errors.push(ParseError { span, message });
+    }
+
+    /// The current token quoted for an error message
+    fn describe_current(&self) -> String {
+        match self.current() {
+            Token::Newline | Token::Eof => self.current().to_string(),
+            // Identifiers and variables share a token; the span tells them apart.
+            Token::Variable(name) => {
+                let span = self.current_span();
+                if span.end_column.saturating_sub(span.column) > name.chars().count() {
+                    format!("'${}'", name)
+                } else {
+                    format!("'{}'", name)
+                }
+            }
+            token => format!("'{}'", token),
+        }
+    }
+
+    /// Like `expect`, but a missing token is a syntax error.
+    fn require(&mut self, expected: Token) -> bool {
+        if self.expect(expected.clone()) {
+            return true;
+        }
+        self.error(format!("expected '{}', found {}", expected, self.describe_current()));
+        false
+    }
+
+    /// Skip the rest of a statement that failed to parse: up to the end of
+    /// its line, or past a block it opened.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.current() {
+                Token::Eof => return,
+                Token::Newline | Token::Semicolon if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                // Leave the '}' closing an enclosing block for it to consume.
+                Token::RightBrace if depth == 0 && self.blocks > 0 => return,
+                Token::RightBrace if depth == 0 => {}
+                Token::RightBrace => depth -= 1,
+                Token::LeftBrace => depth += 1,
+                _ => {}
+            }
+            self.advance();
         }
     }
 
@@ -160,8 +379,56 @@ impl Parser {
         self.tokens.front().unwrap_or(&Token::Eof)
     }
 
+    fn current_span(&self) -> Span {
+        self.spans.front().copied().unwrap_or(self.last_span)
+    }
+
     fn advance(&mut self) {
-        self.tokens.pop_front();
+        if let Some(token) = self.tokens.pop_front() {
+            let span = self.spans.pop_front().unwrap_or_default();
+            if !matches!(token, Token::Newline | Token::Semicolon) {
+                self.last_span = span;
+            }
+        }
+    }
+
+    /// Put a token back in front of the stream, reusing the last consumed span
+    fn unread(&mut self, token: Token) {
+        self.tokens.push_front(token);
+        self.spans.push_front(self.last_span);
+    }
+
+    /// Parse one statement and attach the span from `start` to the last consumed token
+    fn parse_spanned_statement(&mut self) -> Option<Stmt> {
+        // An empty statement: a stray ';'
+        if self.expect(Token::Semicolon) {
+            return None;
+        }
+        let start = self.current_span();
+        self.recovering = false;
+        let errors = self.errors.len();
+        let stmt = self.parse_statement().map(|kind| Stmt {
+            kind,
+            span: self.span_from(start),
+        });
+        if stmt.is_none() && self.errors.len() == errors {
+            self.error(format!("unexpected {}", self.describe_current()));
+        }
+        // Skip whatever is left of a statement with errors.
+        if self.errors.len() > errors {
+            self.synchronize();
+        }
+        stmt
+    }
+
+    /// Span from the start of `start` to the end of the last consumed token
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            line: start.line,
+            column: start.column,
+            end_line: self.last_span.end_line,
+            end_column: self.last_span.end_column,
+        }
     }
 
     fn expect(&mut self, expected: Token) -> bool {
@@ -179,7 +446,7 @@ impl Parser {
         }
     }
 
-    pub fn parse(&mut self) -> Vec<Statement> {
+    pub fn parse(&mut self) -> Vec<Stmt> {
         let mut statements = Vec::new();
         self.skip_newlines();
 
@@ -189,7 +456,7 @@ impl Parser {
                 break;
             }
 
-            if let Some(stmt) = self.parse_statement() {
+            if let Some(stmt) = self.parse_spanned_statement() {
                 statements.push(stmt);
             }
             self.skip_newlines();
@@ -204,6 +471,7 @@ impl Parser {
         match self.current() {
             Token::If => self.parse_if(),
             Token::While => self.parse_while(),
+            Token::For => self.parse_for(),
             Token::Printf => self.parse_printf(),
             Token::Read => self.parse_read(),
             Token::Inc => self.parse_inc(),
@@ -216,13 +484,77 @@ impl Parser {
             Token::Sockclose => self.parse_sockclose(),
             Token::Sockwrite => self.parse_sockwrite(),
             Token::Sockread => self.parse_sockread(),
+            // Serial ports take the same arguments as sockets.
+            Token::Serialopen => match self.parse_sockopen()? {
+                Statement::Sockopen { name, host, port } => Some(Statement::Serialopen {
+                    name,
+                    device: host,
+                    baud: port,
+                }),
+                _ => None,
+            },
+            Token::Serialclose => match self.parse_sockclose()? {
+                Statement::Sockclose { name } => Some(Statement::Serialclose { name }),
+                _ => None,
+            },
+            Token::Serialwrite => match self.parse_sockwrite()? {
+                Statement::Sockwrite { name, data } => Some(Statement::Serialwrite { name, data }),
+                _ => None,
+            },
+            Token::Serialread => match self.parse_sockread()? {
+                Statement::Sockread { name, var } => Some(Statement::Serialread { name, var }),
+                _ => None,
+            },
             Token::Include => self.parse_include(),
             Token::Function => self.parse_function_def(),
             Token::Return => self.parse_return(),
+            Token::Global => self.parse_global(),
+            Token::Strict => {
+                self.advance();
+                Some(Statement::Strict)
+            }
+            Token::Overflow => {
+                self.advance();
+                let mode = match self.current() {
+                    Token::Variable(name) => Overflow::from_name(name),
+                    _ => None,
+                };
+                match mode {
+                    Some(mode) => {
+                        self.advance();
+                        self.skip_statement_end();
+                        Some(Statement::Overflow(mode))
+                    }
+                    None => {
+                        let found = self.describe_current();
+                        self.error(format!(
+                            "Expected wrap, saturate, error or promote after 'overflow', found {}",
+                            found
+                        ));
+                        None
+                    }
+                }
+            }
+            Token::Try => self.parse_try(),
+            Token::Throw => {
+                self.advance();
+                let value = self.parse_expr();
+                self.skip_statement_end();
+                Some(Statement::Throw { value })
+            }
             Token::Sleep => self.parse_sleep(),
+            // Built-ins written as keywords, called for their side effects
+            Token::Shell | Token::Len | Token::Number | Token::Lower | Token::Upper => {
+                match self.parse_expr() {
+                    Expr::FunctionCall { name, args, .. } => {
+                        Some(Statement::FunctionCall { name, args })
+                    }
+                    _ => None,
+                }
+            }
             Token::Elseif | Token::Else => {
-                // These should have been consumed by the previous if statement
-                // If we see them here, skip them to avoid treating them as separate statements
+                // These are consumed by the `if` they belong to.
+                self.error(format!("{} without a matching 'if'", self.describe_current()));
                 self.advance();
                 None
             }
@@ -231,11 +563,11 @@ impl Parser {
                 self.advance();
 
                 if self.current() == &Token::LeftBrace {
-                    self.tokens.push_front(Token::LeftBrace);
-                    self.tokens.push_front(Token::Variable(saved_name.clone()));
+                    self.unread(Token::LeftBrace);
+                    self.unread(Token::Variable(saved_name.clone()));
                     self.parse_function_call()
                 } else if self.current() == &Token::LeftParen {
-                    self.tokens.push_front(Token::Variable(saved_name.clone()));
+                    self.unread(Token::Variable(saved_name.clone()));
                     self.parse_function_call()
                 } else if self.current() == &Token::Equals
                     || self.current() == &Token::PlusEquals
@@ -244,7 +576,7 @@ impl Parser {
                     || self.current() == &Token::SlashEquals
                     || self.current() == &Token::LeftBracket
                 {
-                    self.tokens.push_front(Token::Variable(saved_name.clone()));
+                    self.unread(Token::Variable(saved_name.clone()));
                     self.parse_assignment()
                 } else if self.current() == &Token::Semicolon
                     || self.current() == &Token::Newline
@@ -252,11 +584,12 @@ impl Parser {
                 {
                     self.parse_function_call_simple(saved_name)
                 } else {
-                    self.tokens.push_front(Token::Variable(saved_name.clone()));
+                    self.unread(Token::Variable(saved_name.clone()));
                     self.parse_assignment()
                 }
             }
             _ => {
+                self.error(format!("unexpected {}", self.describe_current()));
                 self.advance();
                 None
             }
@@ -266,16 +599,16 @@ impl Parser {
     fn parse_if(&mut self) -> Option<Statement> {
         self.advance();
 
-        if !self.expect(Token::LeftParen) {
+        if !self.require(Token::LeftParen) {
             return None;
         }
 
         let condition = self.parse_expr();
-        if !self.expect(Token::RightParen) {
+        if !self.require(Token::RightParen) {
             return None;
         }
 
-        if !self.expect(Token::LeftBrace) {
+        if !self.require(Token::LeftBrace) {
             return None;
         }
 
@@ -288,16 +621,16 @@ impl Parser {
         while self.current() == &Token::Elseif {
             self.advance();
 
-            if !self.expect(Token::LeftParen) {
+            if !self.require(Token::LeftParen) {
                 break;
             }
 
             let cond = self.parse_expr();
-            if !self.expect(Token::RightParen) {
+            if !self.require(Token::RightParen) {
                 break;
             }
 
-            if !self.expect(Token::LeftBrace) {
+            if !self.require(Token::LeftBrace) {
                 break;
             }
 
@@ -308,7 +641,7 @@ impl Parser {
 
         if self.current() == &Token::Else {
             self.advance();
-            if self.expect(Token::LeftBrace) {
+            if self.require(Token::LeftBrace) {
                 else_body = Some(self.parse_block());
             }
         }
@@ -321,19 +654,68 @@ impl Parser {
         })
     }
 
+    fn parse_try(&mut self) -> Option<Statement> {
+        self.advance();
+
+        if !self.require(Token::LeftBrace) {
+            return None;
+        }
+        let body = self.parse_block();
+
+        let mut catch_var = None;
+        let mut catch_body = None;
+        self.skip_newlines();
+        if self.expect(Token::Catch) {
+            // The error variable is optional: `catch { ... }`
+            if self.expect(Token::LeftParen) {
+                if let Token::Variable(var) = self.current() {
+                    catch_var = Some(var.clone());
+                    self.advance();
+                }
+                if !self.require(Token::RightParen) {
+                    return None;
+                }
+            }
+            if !self.require(Token::LeftBrace) {
+                return None;
+            }
+            catch_body = Some(self.parse_block());
+            self.skip_newlines();
+        }
+
+        let mut finally_body = None;
+        if self.expect(Token::Finally) {
+            if !self.require(Token::LeftBrace) {
+                return None;
+            }
+            finally_body = Some(self.parse_block());
+        }
+
+        if catch_body.is_none() && finally_body.is_none() {
+            return None;
+        }
+
+        Some(Statement::Try {
+            body,
+            catch_var,
+            catch_body,
+            finally_body,
+        })
+    }
+
     fn parse_while(&mut self) -> Option<Statement> {
         self.advance();
 
-        if !self.expect(Token::LeftParen) {
+        if !self.require(Token::LeftParen) {
             return None;
         }
 
         let condition = self.parse_expr();
-        if !self.expect(Token::RightParen) {
+        if !self.require(Token::RightParen) {
             return None;
         }
 
-        if !self.expect(Token::LeftBrace) {
+        if !self.require(Token::LeftBrace) {
             return None;
         }
 
@@ -342,10 +724,73 @@ impl Parser {
         Some(Statement::While { condition, body })
     }
 
-    fn parse_block(&mut self) -> Vec<Statement> {
+    fn parse_for(&mut self) -> Option<Statement> {
+        self.advance();
+
+        if !self.require(Token::LeftParen) {
+            return None;
+        }
+
+        if let (Token::Variable(var), Some(Token::In)) = (self.current(), self.tokens.get(1)) {
+            let var = var.clone();
+            self.advance();
+            self.advance();
+            let iterable = self.parse_expr();
+            if !self.require(Token::RightParen) || !self.require(Token::LeftBrace) {
+                return None;
+            }
+            let body = self.parse_block();
+            return Some(Statement::ForIn {
+                var,
+                iterable,
+                body,
+            });
+        }
+
+        // The init statement consumes its own ';'.
+        let init = if self.expect(Token::Semicolon) {
+            None
+        } else {
+            Some(Box::new(self.parse_spanned_statement()?))
+        };
+
+        let condition = if self.current() == &Token::Semicolon {
+            None
+        } else {
+            Some(self.parse_expr())
+        };
+        if !self.require(Token::Semicolon) {
+            return None;
+        }
+
+        let step = if self.current() == &Token::RightParen {
+            None
+        } else {
+            Some(Box::new(self.parse_spanned_statement()?))
+        };
+        if !self.require(Token::RightParen) {
+            return None;
+        }
+
+        if !self.require(Token::LeftBrace) {
+            return None;
+        }
+
+        let body = self.parse_block();
+
+        Some(Statement::For {
+            init,
+            condition,
+            step,
+            body,
+        })
+    }
+
+    fn parse_block(&mut self) -> Vec<Stmt> {
         let mut statements = Vec::new();
         self.skip_newlines();
 
+        self.blocks += 1;
         while self.current() != &Token::RightBrace && self.current() != &Token::Eof {
             self.skip_newlines();
 
@@ -353,13 +798,14 @@ impl Parser {
                 break;
             }
 
-            if let Some(stmt) = self.parse_statement() {
+            if let Some(stmt) = self.parse_spanned_statement() {
                 statements.push(stmt);
             }
             self.skip_newlines();
         }
+        self.blocks -= 1;
 
-        self.expect(Token::RightBrace);
+        self.require(Token::RightBrace);
         statements
     }
 
@@ -369,13 +815,16 @@ impl Parser {
             self.advance();
 
             if self.current() == &Token::LeftBracket {
-                self.advance();
-                let index = self.parse_expr();
-                if !self.expect(Token::RightBracket) {
-                    return None;
+                let mut indices = Vec::new();
+                while self.current() == &Token::LeftBracket {
+                    self.advance();
+                    indices.push(self.parse_expr());
+                    if !self.require(Token::RightBracket) {
+                        return None;
+                    }
                 }
 
-                if !self.expect(Token::Equals) {
+                if !self.require(Token::Equals) {
                     return None;
                 }
 
@@ -384,7 +833,7 @@ impl Parser {
 
                 return Some(Statement::ArrayAssignment {
                     var: var_name,
-                    index,
+                    indices,
                     value,
                 });
             }
@@ -439,7 +888,7 @@ Some(Statement::Assignment {
     fn parse_printf(&mut self) -> Option<Statement> {
         self.advance();
 
-        if !self.expect(Token::LeftParen) {
+        if !self.require(Token::LeftParen) {
             return None;
         }
 
@@ -459,7 +908,7 @@ Some(Statement::Assignment {
             args.push(self.parse_expr());
         }
 
-        if !self.expect(Token::RightParen) {
+        if !self.require(Token::RightParen) {
             return None;
         }
 
@@ -474,7 +923,7 @@ Some(Statement::Assignment {
     fn parse_read(&mut self) -> Option<Statement> {
         self.advance();
 
-        if !self.expect(Token::LeftParen) {
+        if !self.require(Token::LeftParen) {
             return None;
         }
 
@@ -486,7 +935,7 @@ Some(Statement::Assignment {
             return None;
         };
 
-        if !self.expect(Token::RightParen) {
+        if !self.require(Token::RightParen) {
             return None;
         }
 
@@ -502,7 +951,7 @@ Some(Statement::Assignment {
             let var_name = var.clone();
             self.advance();
 
-            if !self.expect(Token::Plus) {
+            if !self.require(Token::Plus) {
                 return None;
             }
 
@@ -525,7 +974,7 @@ Some(Statement::Assignment {
             let var_name = var.clone();
             self.advance();
 
-            if !self.expect(Token::Minus) {
+            if !self.require(Token::Minus) {
                 return None;
             }
 
@@ -548,7 +997,7 @@ Some(Statement::Assignment {
             let var_name = var.clone();
             self.advance();
 
-            if !self.expect(Token::Comma) {
+            if !self.require(Token::Comma) {
                 return None;
             }
 
@@ -566,10 +1015,14 @@ Some(Statement::Assignment {
 
     fn parse_pop(&mut self) -> Option<Statement> {
         self.advance();
+        let parenthesized = self.expect(Token::LeftParen);
 
         if let Token::Variable(var) = self.current() {
             let var_name = var.clone();
             self.advance();
+            if parenthesized && !self.expect(Token::RightParen) {
+                return None;
+            }
             self.skip_statement_end();
 
             return Some(Statement::Pop { array: var_name });
@@ -580,10 +1033,14 @@ Some(Statement::Assignment {
 
     fn parse_shift(&mut self) -> Option<Statement> {
         self.advance();
+        let parenthesized = self.expect(Token::LeftParen);
 
         if let Token::Variable(var) = self.current() {
             let var_name = var.clone();
             self.advance();
+            if parenthesized && !self.expect(Token::RightParen) {
+                return None;
+            }
             self.skip_statement_end();
 
             return Some(Statement::Shift { array: var_name });
@@ -599,7 +1056,7 @@ Some(Statement::Assignment {
             let var_name = var.clone();
             self.advance();
 
-            if !self.expect(Token::Comma) {
+            if !self.require(Token::Comma) {
                 return None;
             }
 
@@ -618,7 +1075,7 @@ Some(Statement::Assignment {
     fn parse_sockopen(&mut self) -> Option<Statement> {
         self.advance();
 
-        if !self.expect(Token::LeftParen) {
+        if !self.require(Token::LeftParen) {
             return None;
         }
 
@@ -630,19 +1087,19 @@ Some(Statement::Assignment {
             return None;
         };
 
-        if !self.expect(Token::Comma) {
+        if !self.require(Token::Comma) {
             return None;
         }
 
         let host = self.parse_expr();
 
-        if !self.expect(Token::Comma) {
+        if !self.require(Token::Comma) {
             return None;
         }
 
         let port = self.parse_expr();
 
-        if !self.expect(Token::RightParen) {
+        if !self.require(Token::RightParen) {
             return None;
         }
 
@@ -654,7 +1111,7 @@ Some(Statement::Assignment {
     fn parse_sockclose(&mut self) -> Option<Statement> {
         self.advance();
 
-        if !self.expect(Token::LeftParen) {
+        if !self.require(Token::LeftParen) {
             return None;
         }
 
@@ -666,7 +1123,7 @@ Some(Statement::Assignment {
             return None;
         };
 
-        if !self.expect(Token::RightParen) {
+        if !self.require(Token::RightParen) {
             return None;
         }
 
@@ -678,7 +1135,7 @@ Some(Statement::Assignment {
     fn parse_sockwrite(&mut self) -> Option<Statement> {
         self.advance();
 
-        if !self.expect(Token::LeftParen) {
+        if !self.require(Token::LeftParen) {
             return None;
         }
 
@@ -690,13 +1147,13 @@ Some(Statement::Assignment {
             return None;
         };
 
-        if !self.expect(Token::Comma) {
+        if !self.require(Token::Comma) {
             return None;
         }
 
         let data = self.parse_expr();
 
-        if !self.expect(Token::RightParen) {
+        if !self.require(Token::RightParen) {
             return None;
         }
 
@@ -708,7 +1165,7 @@ Some(Statement::Assignment {
     fn parse_sockread(&mut self) -> Option<Statement> {
         self.advance();
 
-        if !self.expect(Token::LeftParen) {
+        if !self.require(Token::LeftParen) {
             return None;
         }
 
@@ -720,7 +1177,7 @@ Some(Statement::Assignment {
             return None;
         };
 
-        if !self.expect(Token::Comma) {
+        if !self.require(Token::Comma) {
             return None;
         }
 
@@ -732,7 +1189,7 @@ Some(Statement::Assignment {
             return None;
         };
 
-        if !self.expect(Token::RightParen) {
+        if !self.require(Token::RightParen) {
             return None;
         }
 
@@ -757,16 +1214,35 @@ Some(Statement::Assignment {
         Some(Statement::Include { path })
     }
 
+    fn parse_global(&mut self) -> Option<Statement> {
+        self.advance();
+
+        let mut vars = Vec::new();
+        while let Token::Variable(var) = self.current() {
+            vars.push(var.clone());
+            self.advance();
+            if !self.expect(Token::Comma) {
+                break;
+            }
+        }
+        if vars.is_empty() {
+            return None;
+        }
+        self.skip_statement_end();
+
+        Some(Statement::Global { vars })
+    }
+
     fn parse_sleep(&mut self) -> Option<Statement> {
         self.advance();
 
-        if !self.expect(Token::LeftParen) {
+        if !self.require(Token::LeftParen) {
             return None;
         }
 
         let seconds = self.parse_expr();
 
-        if !self.expect(Token::RightParen) {
+        if !self.require(Token::RightParen) {
             return None;
         }
 
@@ -789,6 +1265,19 @@ Some(Statement::Assignment {
             return None;
         };
 
+        let params = self.parse_params()?;
+
+        if !self.require(Token::LeftBrace) {
+            return None;
+        }
+
+        let body = self.parse_block();
+
+        Some(Statement::FunctionDef { name, params, body })
+    }
+
+    /// Optional parameter list of a function: `($a, $b, ...)`
+    fn parse_params(&mut self) -> Option<Vec<String>> {
         let mut params: Vec<String> = Vec::new();
 
         if self.current() == &Token::LeftParen {
@@ -817,18 +1306,12 @@ Some(Statement::Assignment {
                 }
             }
 
-            if !self.expect(Token::RightParen) {
+            if !self.require(Token::RightParen) {
                 return None;
             }
         }
 
-        if !self.expect(Token::LeftBrace) {
-            return None;
-        }
-
-        let body = self.parse_block();
-
-        Some(Statement::FunctionDef { name, params, body })
+        Some(params)
     }
 
     fn parse_return(&mut self) -> Option<Statement> {
@@ -856,18 +1339,16 @@ Some(Statement::Assignment {
             let mut args: Vec<Expr> = Vec::new();
 
             // Parse optional argument list: fname(expr, expr, ...)
-            if self.expect(Token::LeftParen) {
-                if !self.expect(Token::RightParen) {
-                    loop {
-                        args.push(self.parse_expr());
-                        if self.expect(Token::Comma) {
-                            continue;
-                        }
-                        if !self.expect(Token::RightParen) {
-                            return None;
-                        }
-                        break;
+            if self.expect(Token::LeftParen) && !self.expect(Token::RightParen) {
+                loop {
+                    args.push(self.parse_expr());
+                    if self.expect(Token::Comma) {
+                        continue;
+                    }
+                    if !self.require(Token::RightParen) {
+                        return None;
                     }
+                    break;
                 }
             }
 
@@ -891,7 +1372,23 @@ Some(Statement::Assignment {
     }
 
     fn parse_expr(&mut self) -> Expr {
-        self.parse_or()
+        self.parse_coalesce()
+    }
+
+    fn parse_coalesce(&mut self) -> Expr {
+        let mut left = self.parse_or();
+
+        while self.current() == &Token::QuestionQuestion {
+            self.advance();
+            let right = self.parse_or();
+            left = Expr::Binary {
+                left: Box::new(left),
+                op: BinOp::Coalesce,
+                right: Box::new(right),
+            };
+        }
+
+        left
     }
 
     fn parse_or(&mut self) -> Expr {
@@ -964,7 +1461,7 @@ Some(Statement::Assignment {
     }
 
     fn parse_comparison(&mut self) -> Expr {
-        let mut left = self.parse_additive();
+        let mut left = self.parse_range();
 
         while let Some(op) = match self.current() {
             Token::Less => Some(BinOp::Less),
@@ -974,7 +1471,7 @@ Some(Statement::Assignment {
             _ => None,
         } {
             self.advance();
-            let right = self.parse_additive();
+            let right = self.parse_range();
             left = Expr::Binary {
                 left: Box::new(left),
                 op,
@@ -985,6 +1482,23 @@ Some(Statement::Assignment {
         left
     }
 
+    /// `a..b` and `a..<b`, below arithmetic so `1..$n - 1` needs no parentheses
+    fn parse_range(&mut self) -> Expr {
+        let left = self.parse_additive();
+        let op = match self.current() {
+            Token::DotDot => BinOp::RangeInclusive,
+            Token::DotDotLess => BinOp::RangeExclusive,
+            _ => return left,
+        };
+        self.advance();
+        let right = self.parse_additive();
+        Expr::Binary {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }
+    }
+
     fn parse_additive(&mut self) -> Expr {
         let mut left = self.parse_multiplicative();
 
@@ -1042,50 +1556,90 @@ Some(Statement::Assignment {
                     expr: Box::new(self.parse_unary()),
                 }
             }
-            _ => self.parse_postfix(),
+            _ => self.parse_power(),
+        }
+    }
+
+    /// `**` binds tighter than a unary minus on its left (`-2 ** 2` is -4)
+    /// and groups to the right (`2 ** 3 ** 2` is `2 ** 9`).
+    fn parse_power(&mut self) -> Expr {
+        let base = self.parse_postfix();
+        if self.current() != &Token::StarStar {
+            return base;
+        }
+        self.advance();
+        let exponent = self.parse_unary();
+        Expr::Binary {
+            left: Box::new(base),
+            op: BinOp::Power,
+            right: Box::new(exponent),
         }
     }
 
     fn parse_postfix(&mut self) -> Expr {
         let mut expr = self.parse_primary();
 
-        loop {
-            match self.current() {
-                Token::LeftBracket => {
-                    self.advance();
-                    let index = self.parse_expr();
-                    self.expect(Token::RightBracket);
-                    expr = Expr::Index {
-                        expr: Box::new(expr),
-                        index: Box::new(index),
-                    };
-                }
-                _ => break,
+        while matches!(self.current(), Token::LeftBracket | Token::QuestionBracket) {
+            let optional = self.current() == &Token::QuestionBracket;
+            self.advance();
+            let start = match self.current() {
+                Token::Colon => None,
+                _ => Some(Box::new(self.parse_expr())),
+            };
+            if self.expect(Token::Colon) {
+                let end = match self.current() {
+                    Token::RightBracket => None,
+                    _ => Some(Box::new(self.parse_expr())),
+                };
+                self.require(Token::RightBracket);
+                expr = Expr::Slice {
+                    expr: Box::new(expr),
+                    start,
+                    end,
+                    optional,
+                };
+                continue;
             }
+            self.require(Token::RightBracket);
+            expr = Expr::Index {
+                expr: Box::new(expr),
+                index: start.unwrap_or_else(|| Box::new(Expr::Int(0))),
+                optional,
+            };
         }
 
         expr
     }
 
     fn parse_primary(&mut self) -> Expr {
+        let start = self.current_span();
         match self.current().clone() {
             Token::Int(n) => {
                 self.advance();
                 Expr::Int(n)
             }
+            Token::Float(f) => {
+                self.advance();
+                Expr::Float(f)
+            }
+            Token::True | Token::False => {
+                let value = self.current() == &Token::True;
+                self.advance();
+                Expr::Bool(value)
+            }
             Token::String(s) => {
                 self.advance();
                 Expr::String(s)
             }
-            Token::Regex(pat) => {
+            Token::Regex { pat, flags } => {
                 self.advance();
-                Expr::Regex(pat)
+                Expr::Regex { pat, flags }
             }
             Token::Subst { pat, repl, flags } => {
                 self.advance();
-                if self.expect(Token::LeftParen) {
+                if self.require(Token::LeftParen) {
                     let input = self.parse_expr();
-                    self.expect(Token::RightParen);
+                    self.require(Token::RightParen);
                     Expr::SubstCall { pat, repl, flags, input: Box::new(input) }
                 } else {
                     Expr::String(String::new())
@@ -1093,12 +1647,13 @@ Some(Statement::Assignment {
             }
             Token::Len => {
                 self.advance();
-                if self.expect(Token::LeftParen) {
+                if self.require(Token::LeftParen) {
                     let arg = self.parse_expr();
-                    self.expect(Token::RightParen);
+                    self.require(Token::RightParen);
                     Expr::FunctionCall {
                         name: "len".to_string(),
                         args: vec![arg],
+                        span: self.span_from(start),
                     }
                 } else {
                     Expr::Int(0)
@@ -1106,12 +1661,18 @@ Some(Statement::Assignment {
             }
             Token::Shell => {
                 self.advance();
-                if self.expect(Token::LeftParen) {
-                    let arg = self.parse_expr();
-                    self.expect(Token::RightParen);
+                if self.require(Token::LeftParen) {
+                    let mut args = vec![self.parse_expr()];
+                    // Optional stdin input and timeout in seconds
+                    while self.current() == &Token::Comma {
+                        self.advance();
+                        args.push(self.parse_expr());
+                    }
+                    self.require(Token::RightParen);
                     Expr::FunctionCall {
                         name: "shell".to_string(),
-                        args: vec![arg],
+                        args,
+                        span: self.span_from(start),
                     }
                 } else {
                     Expr::String(String::new())
@@ -1119,12 +1680,13 @@ Some(Statement::Assignment {
             }
             Token::Number => {
                 self.advance();
-                if self.expect(Token::LeftParen) {
+                if self.require(Token::LeftParen) {
                     let arg = self.parse_expr();
-                    self.expect(Token::RightParen);
+                    self.require(Token::RightParen);
                     Expr::FunctionCall {
                         name: "number".to_string(),
                         args: vec![arg],
+                        span: self.span_from(start),
                     }
                 } else {
                     Expr::Int(0)
@@ -1132,12 +1694,13 @@ Some(Statement::Assignment {
             }
             Token::Lower => {
                 self.advance();
-                if self.expect(Token::LeftParen) {
+                if self.require(Token::LeftParen) {
                     let arg = self.parse_expr();
-                    self.expect(Token::RightParen);
+                    self.require(Token::RightParen);
                     Expr::FunctionCall {
                         name: "lower".to_string(),
                         args: vec![arg],
+                        span: self.span_from(start),
                     }
                 } else {
                     Expr::String(String::new())
@@ -1145,12 +1708,13 @@ Some(Statement::Assignment {
             }
             Token::Upper => {
                 self.advance();
-                if self.expect(Token::LeftParen) {
+                if self.require(Token::LeftParen) {
                     let arg = self.parse_expr();
-                    self.expect(Token::RightParen);
+                    self.require(Token::RightParen);
                     Expr::FunctionCall {
                         name: "upper".to_string(),
                         args: vec![arg],
+                        span: self.span_from(start),
                     }
                 } else {
                     Expr::String(String::new())
@@ -1158,17 +1722,33 @@ Some(Statement::Assignment {
             }
             Token::Sleep => {
                 self.advance();
-                if self.expect(Token::LeftParen) {
+                if self.require(Token::LeftParen) {
                     let arg = self.parse_expr();
-                    self.expect(Token::RightParen);
+                    self.require(Token::RightParen);
                     Expr::FunctionCall {
                         name: "sleep".to_string(),
                         args: vec![arg],
+                        span: self.span_from(start),
                     }
                 } else {
                     Expr::Int(0)
                 }
             }
+            // pop($arr) / shift($arr): remove an element and return it
+            Token::Pop | Token::Shift => {
+                let name = if self.current() == &Token::Pop { "pop" } else { "shift" };
+                self.advance();
+                if !self.require(Token::LeftParen) {
+                    return Expr::Int(0);
+                }
+                let arg = self.parse_expr();
+                self.require(Token::RightParen);
+                Expr::FunctionCall {
+                    name: name.to_string(),
+                    args: vec![arg],
+                    span: self.span_from(start),
+                }
+            }
             Token::Variable(name) => {
                 self.advance();
 
@@ -1183,9 +1763,13 @@ Some(Statement::Assignment {
                         }
                     }
 
-                    self.expect(Token::RightParen);
+                    self.require(Token::RightParen);
 
-                    Expr::FunctionCall { name, args }
+                    Expr::FunctionCall {
+                        name,
+                        args,
+                        span: self.span_from(start),
+                    }
                 } else {
                     Expr::Variable(name)
                 }
@@ -1193,9 +1777,18 @@ Some(Statement::Assignment {
             Token::LeftParen => {
                 self.advance();
                 let expr = self.parse_expr();
-                self.expect(Token::RightParen);
+                self.require(Token::RightParen);
                 expr
             }
+            Token::Function => {
+                self.advance();
+                let params = match self.parse_params() {
+                    Some(params) if self.require(Token::LeftBrace) => params,
+                    _ => return Expr::Int(0),
+                };
+                let body = Rc::new(self.parse_block());
+                Expr::Lambda { params, body }
+            }
             Token::LeftBracket => {
                 self.advance();
                 let mut elements = Vec::new();
@@ -1207,10 +1800,29 @@ Some(Statement::Assignment {
                     }
                 }
 
-                self.expect(Token::RightBracket);
+                self.require(Token::RightBracket);
                 Expr::Array(elements)
             }
+            Token::LeftBrace => {
+                self.advance();
+                let mut entries = Vec::new();
+                self.skip_newlines();
+
+                while self.current() != &Token::RightBrace && self.current() != &Token::Eof {
+                    let key = self.parse_expr();
+                    if !self.require(Token::Colon) {
+                        break;
+                    }
+                    entries.push((key, self.parse_expr()));
+                    self.expect(Token::Comma);
+                    self.skip_newlines();
+                }
+
+                self.require(Token::RightBrace);
+                Expr::Map(entries)
+            }
             _ => {
+                self.error(format!("expected an expression, found {}", self.describe_current()));
                 self.advance();
                 Expr::Int(0)
             }
@@ -0,0 +1,250 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Manifest file kept next to the installed packages
+const MANIFEST: &str = "packages.txt";
+
+/// An installed package as recorded in the manifest
+struct Package {
+    name: String,
+    version: String,
+    source: String,
+}
+
+/// Per-user directory packages are installed into: `$MINILUX_HOME/modules`,
+/// or `~/.minilux/modules` when MINILUX_HOME is not set.
+pub fn user_modules_dir() -> Option<PathBuf> {
+    minilux_home().map(|home| home.join("modules"))
+}
+
+/// Per-user directory remote includes are downloaded into:
+/// `$MINILUX_HOME/cache`, or `~/.minilux/cache`.
+pub fn user_cache_dir() -> Option<PathBuf> {
+    minilux_home().map(|home| home.join("cache"))
+}
+
+fn minilux_home() -> Option<PathBuf> {
+    if let Some(home) = env::var_os("MINILUX_HOME").filter(|v| !v.is_empty()) {
+        return Some(PathBuf::from(home));
+    }
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .filter(|v| !v.is_empty())
+        .map(|home| PathBuf::from(home).join(".minilux"))
+}
+
+/// Entry point for `minilux pkg <install|list|remove> ...`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let dir = user_modules_dir()
+        .ok_or_else(|| "Cannot locate the user modules directory (set MINILUX_HOME)".to_string())?;
+
+    match args.first().map(String::as_str) {
+        Some("install") => {
+            let mut source: Option<&str> = None;
+            let mut name: Option<&str> = None;
+            let mut version: Option<&str> = None;
+            let mut i = 1;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--name" | "--version" => {
+                        let value = args
+                            .get(i + 1)
+                            .ok_or_else(|| format!("{} requires a value", args[i]))?;
+                        if args[i] == "--name" {
+                            name = Some(value);
+                        } else {
+                            version = Some(value);
+                        }
+                        i += 1;
+                    }
+                    s if source.is_none() && !s.starts_with('-') => source = Some(s),
+                    s => return Err(format!("unexpected argument: {}", s)),
+                }
+                i += 1;
+            }
+            let source = source.ok_or("install requires a git URL or tarball")?;
+            install(&dir, source, name, version)
+        }
+        Some("list") => {
+            for package in read_manifest(&dir)? {
+                println!("{} {} {}", package.name, package.version, package.source);
+            }
+            Ok(())
+        }
+        Some("remove") => match args.get(1) {
+            Some(name) => remove(&dir, name),
+            None => Err("remove requires a package name".to_string()),
+        },
+        Some(other) => Err(format!("unknown pkg command: {}", other)),
+        None => Err("pkg requires a command: install, list or remove".to_string()),
+    }
+}
+
+fn install(dir: &Path, source: &str, name: Option<&str>, version: Option<&str>) -> Result<(), String> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => package_name(source),
+    };
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(format!("invalid package name '{}' (use --name)", name));
+    }
+
+    let mut packages = read_manifest(dir)?;
+    let target = dir.join(&name);
+    if target.exists() || packages.iter().any(|p| p.name == name) {
+        return Err(format!("package '{}' is already installed", name));
+    }
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let result = if is_tarball(source) {
+        fetch_tarball(dir, source, &target)
+    } else {
+        fetch_git(source, version, &target)
+    };
+    let version = match result {
+        Ok(fetched) => version.map(String::from).or(fetched).unwrap_or_else(|| "-".to_string()),
+        Err(e) => {
+            fs::remove_dir_all(&target).ok();
+            return Err(e);
+        }
+    };
+
+    println!("Installed {} {} into {}", name, version, target.display());
+    packages.push(Package {
+        name,
+        version,
+        source: source.to_string(),
+    });
+    write_manifest(dir, &packages)
+}
+
+fn remove(dir: &Path, name: &str) -> Result<(), String> {
+    let mut packages = read_manifest(dir)?;
+    let before = packages.len();
+    packages.retain(|p| p.name != name);
+    if packages.len() == before {
+        return Err(format!("package '{}' is not installed", name));
+    }
+
+    let target = dir.join(name);
+    if target.exists() {
+        fs::remove_dir_all(&target)
+            .map_err(|e| format!("Failed to remove {}: {}", target.display(), e))?;
+    }
+    println!("Removed {}", name);
+    write_manifest(dir, &packages)
+}
+
+/// `https://host/user/strings.git` -> `strings`, `lists-1.2.tar.gz` -> `lists-1.2`
+fn package_name(source: &str) -> String {
+    let base = source.trim_end_matches('/').rsplit(['/', ':']).next().unwrap_or("");
+    [".git", ".tar.gz", ".tgz", ".tar"]
+        .iter()
+        .find_map(|ext| base.strip_suffix(ext))
+        .unwrap_or(base)
+        .to_string()
+}
+
+fn is_tarball(source: &str) -> bool {
+    source.ends_with(".tar.gz") || source.ends_with(".tgz") || source.ends_with(".tar")
+}
+
+/// Clone a git repository; returns the checked out commit as the version.
+fn fetch_git(source: &str, version: Option<&str>, target: &Path) -> Result<Option<String>, String> {
+    let mut command = Command::new("git");
+    command.args(["clone", "--quiet", "--depth", "1"]);
+    if let Some(version) = version {
+        command.args(["--branch", version]);
+    }
+    command.arg(source).arg(target);
+    run_command(&mut command, "git")?;
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(target)
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok(Some(commit).filter(|c| !c.is_empty()))
+}
+
+/// Download (when given a URL) and unpack a tarball whose files sit in one
+/// top-level directory, as release archives usually do.
+fn fetch_tarball(dir: &Path, source: &str, target: &Path) -> Result<Option<String>, String> {
+    let archive = if source.contains("://") {
+        let download = dir.join(".download");
+        run_command(
+            Command::new("curl").args(["-fsSL", "-o"]).arg(&download).arg(source),
+            "curl",
+        )?;
+        download
+    } else {
+        PathBuf::from(source)
+    };
+
+    fs::create_dir_all(target)
+        .map_err(|e| format!("Failed to create {}: {}", target.display(), e))?;
+    let result = run_command(
+        Command::new("tar")
+            .arg("-xf")
+            .arg(&archive)
+            .arg("--strip-components=1")
+            .arg("-C")
+            .arg(target),
+        "tar",
+    );
+    if source.contains("://") {
+        fs::remove_file(&archive).ok();
+    }
+    result.map(|_| None)
+}
+
+fn run_command(command: &mut Command, program: &str) -> Result<(), String> {
+    let status = command
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed ({})", program, status))
+    }
+}
+
+/// The manifest has one `name version source` line per installed package.
+fn read_manifest(dir: &Path) -> Result<Vec<Package>, String> {
+    let path = dir.join(MANIFEST);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) if !path.exists() => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(Package {
+                name: fields.next()?.to_string(),
+                version: fields.next()?.to_string(),
+                source: fields.next()?.to_string(),
+            })
+        })
+        .collect())
+}
+
+fn write_manifest(dir: &Path, packages: &[Package]) -> Result<(), String> {
+    let content: String = packages
+        .iter()
+        .map(|p| format!("{} {} {}\n", p.name, p.version, p.source))
+        .collect();
+    let path = dir.join(MANIFEST);
+    fs::write(&path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
@@ -0,0 +1,119 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::interpreter::HostFunction;
+use crate::value::Value;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+
+#[cfg(not(target_family = "wasm"))]
+pub use native::load;
+
+/// A function a plugin exports to scripts. Arguments arrive as NUL-terminated
+/// strings; the returned string must stay valid until the next call, and
+/// NULL means nil.
+pub type PluginFn = unsafe extern "C" fn(
+    argc: c_int,
+    argv: *const *const c_char,
+    userdata: *mut c_void,
+) -> *const c_char;
+
+/// Wrap a C function so scripts can call it; also used by the embedding API.
+///
+/// # Safety
+/// `function` must stay callable with `userdata` for as long as the returned
+/// closure is used.
+pub unsafe fn host_function(name: String, function: PluginFn, userdata: *mut c_void) -> HostFunction {
+    Box::new(move |args: &[Value]| {
+        let strings = args
+            .iter()
+            .map(|v| CString::new(v.to_string()))
+            .collect::<Result<Vec<CString>, _>>()
+            .map_err(|_| format!("{}: argument contains a NUL byte", name))?;
+        let argv: Vec<*const c_char> = strings.iter().map(|s| s.as_ptr()).collect();
+
+        // SAFETY: argv and the strings it points to outlive the call, and
+        // the callee promised the result stays valid until its next call.
+        let result = unsafe { function(argv.len() as c_int, argv.as_ptr(), userdata) };
+        if result.is_null() {
+            return Ok(Value::Nil);
+        }
+        let text = unsafe { CStr::from_ptr(result) };
+        Ok(Value::String(text.to_string_lossy().into_owned()))
+    })
+}
+
+/// Loading shared libraries needs an operating system.
+#[cfg(not(target_family = "wasm"))]
+mod native {
+    use super::{host_function, PluginFn};
+    use crate::interpreter::HostFunction;
+    use libloading::{Library, Symbol};
+    use std::ffi::{c_char, c_int, c_void, CStr};
+    use std::path::Path;
+
+    /// Callback handed to the plugin to register each of its functions
+    type RegisterFn = unsafe extern "C" fn(
+        host: *mut c_void,
+        name: *const c_char,
+        function: PluginFn,
+        userdata: *mut c_void,
+    );
+
+    /// `int minilux_plugin_init(void *host, register_fn register)`, returning 0 on success
+    type InitFn = unsafe extern "C" fn(host: *mut c_void, register: RegisterFn) -> c_int;
+
+    const INIT_SYMBOL: &[u8] = b"minilux_plugin_init";
+
+    struct Registration {
+        name: String,
+        function: PluginFn,
+        userdata: *mut c_void,
+    }
+
+    unsafe extern "C" fn register(
+        host: *mut c_void,
+        name: *const c_char,
+        function: PluginFn,
+        userdata: *mut c_void,
+    ) {
+        if host.is_null() || name.is_null() {
+            return;
+        }
+        let registrations = &mut *(host as *mut Vec<Registration>);
+        registrations.push(Registration {
+            name: CStr::from_ptr(name).to_string_lossy().into_owned(),
+            function,
+            userdata,
+        });
+    }
+
+    /// Load a plugin and collect the functions it registers. The library must be
+    /// kept alive for as long as the returned functions may be called.
+    pub fn load(path: &Path) -> Result<(Library, Vec<(String, HostFunction)>), String> {
+        let display = path.display();
+        // SAFETY: loading runs the library's initializers; plugins are trusted
+        // native code, just like the interpreter itself.
+        let library = unsafe { Library::new(path) }
+            .map_err(|e| format!("Failed to load plugin {}: {}", display, e))?;
+
+        let mut registrations: Vec<Registration> = Vec::new();
+        let status = unsafe {
+            let init: Symbol<InitFn> = library
+                .get(INIT_SYMBOL)
+                .map_err(|e| format!("Plugin {} has no minilux_plugin_init: {}", display, e))?;
+            init(&mut registrations as *mut Vec<Registration> as *mut c_void, register)
+        };
+        if status != 0 {
+            return Err(format!("Plugin {} failed to initialize ({})", display, status));
+        }
+
+        let functions = registrations
+            .into_iter()
+            .map(|r| (r.name.clone(), unsafe { host_function(r.name, r.function, r.userdata) }))
+            .collect();
+        Ok((library, functions))
+    }
+}
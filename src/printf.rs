@@ -0,0 +1,256 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! `%` directives for printf() and sprintf(): `%s`, `%d`, `%x`, `%05d`,
+//! `%-10s`, `%.2f` and friends.
+
+use crate::value::Value;
+use num_bigint::Sign;
+use num_traits::ToPrimitive;
+use std::fmt;
+
+/// One `%[flags][width][.precision]conversion` directive
+struct Directive {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    width: usize,
+    precision: Option<usize>,
+    conversion: char,
+}
+
+const CONVERSIONS: &str = "sdixXobfeEc";
+
+/// Parse the directive starting just after a '%'. Returns it with the number
+/// of characters it spans, or None if the text is not a directive.
+fn parse_directive(chars: &[char]) -> Option<(Directive, usize)> {
+    let mut directive = Directive {
+        left: false,
+        zero: false,
+        plus: false,
+        space: false,
+        width: 0,
+        precision: None,
+        conversion: 's',
+    };
+    let mut i = 0;
+    while let Some(&c) = chars.get(i) {
+        match c {
+            '-' => directive.left = true,
+            '0' => directive.zero = true,
+            '+' => directive.plus = true,
+            ' ' => directive.space = true,
+            _ => break,
+        }
+        i += 1;
+    }
+    while let Some(digit) = chars.get(i).and_then(|c| c.to_digit(10)) {
+        directive.width = directive.width * 10 + digit as usize;
+        i += 1;
+    }
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        let mut precision = 0;
+        while let Some(digit) = chars.get(i).and_then(|c| c.to_digit(10)) {
+            precision = precision * 10 + digit as usize;
+            i += 1;
+        }
+        directive.precision = Some(precision);
+    }
+    let conversion = *chars.get(i).filter(|c| CONVERSIONS.contains(**c))?;
+    directive.conversion = conversion;
+    Some((directive, i + 1))
+}
+
+/// How printf shows a value: arrays and maps as a summary, nil as nothing
+pub fn text(value: &Value) -> String {
+    match value {
+        Value::Array(arr) => format!("[Array({})]", arr.borrow().len()),
+        Value::Map(map) => format!("[Map({})]", map.borrow().len()),
+        Value::Nil => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Expand the directives in `format` with `args`, in order, and `%%` to `%`.
+/// A directive with no argument left is kept as written, and arguments left
+/// over are appended, so formats without directives concatenate as before.
+pub fn format(format: &str, args: &[Value]) -> Result<String, String> {
+    let chars: Vec<char> = format.chars().collect();
+    let mut args = args.iter();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '%' {
+            out.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        if chars.get(i + 1) == Some(&'%') {
+            out.push('%');
+            i += 2;
+            continue;
+        }
+        match parse_directive(&chars[i + 1..]) {
+            Some((directive, len)) => {
+                match args.next() {
+                    Some(arg) => out.push_str(&apply(&directive, arg)?),
+                    None => out.extend(&chars[i..=i + len]),
+                }
+                i += len + 1;
+            }
+            None => {
+                out.push('%');
+                i += 1;
+            }
+        }
+    }
+    for arg in args {
+        out.push_str(&text(arg));
+    }
+    Ok(out)
+}
+
+/// Whether `format` has any directive besides `%%`
+pub fn has_directives(format: &str) -> bool {
+    let chars: Vec<char> = format.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '%' {
+            if chars.get(i + 1) == Some(&'%') {
+                i += 2;
+                continue;
+            }
+            if parse_directive(&chars[i + 1..]).is_some() {
+                return true;
+            }
+        }
+        i += 1;
+    }
+    false
+}
+
+fn apply(directive: &Directive, arg: &Value) -> Result<String, String> {
+    let (sign, body, numeric) = match directive.conversion {
+        's' => {
+            let text = text(arg);
+            let text = match directive.precision {
+                Some(max) => text.chars().take(max).collect(),
+                None => text,
+            };
+            ("", text, false)
+        }
+        'c' => {
+            let c = match arg {
+                Value::String(s) => s.chars().next(),
+                other => char::from_u32(integer(other)? as u32),
+            };
+            ("", c.map(String::from).unwrap_or_default(), false)
+        }
+        'd' | 'i' => {
+            let (negative, mut digits) = match arg {
+                Value::BigInt(n) => (n.sign() == Sign::Minus, n.magnitude().to_string()),
+                other => {
+                    let n = integer(other)?;
+                    (n < 0, n.unsigned_abs().to_string())
+                }
+            };
+            if let Some(min) = directive.precision {
+                digits = format!("{:0>width$}", digits, width = min);
+            }
+            (sign_of(directive, negative), digits, true)
+        }
+        'f' | 'e' | 'E' => {
+            let x = float(arg)?;
+            let precision = directive.precision.unwrap_or(6);
+            let body = match directive.conversion {
+                'f' => format!("{:.*}", precision, x.abs()),
+                'e' => exponent(x.abs(), precision),
+                _ => exponent(x.abs(), precision).to_uppercase(),
+            };
+            (sign_of(directive, x.is_sign_negative() && x != 0.0), body, true)
+        }
+        conversion => {
+            let body = match arg {
+                Value::BigInt(n) => radix(conversion, n),
+                other => radix(conversion, integer(other)?),
+            };
+            ("", body, true)
+        }
+    };
+
+    let len = sign.chars().count() + body.chars().count();
+    let fill = directive.width.saturating_sub(len);
+    Ok(if directive.left {
+        format!("{}{}{}", sign, body, " ".repeat(fill))
+    } else if directive.zero && numeric {
+        format!("{}{}{}", sign, "0".repeat(fill), body)
+    } else {
+        format!("{}{}{}", " ".repeat(fill), sign, body)
+    })
+}
+
+/// `n` in hex (`x` or `X`), octal (`o`) or binary (`b`)
+fn radix<T: fmt::LowerHex + fmt::UpperHex + fmt::Octal + fmt::Binary>(conversion: char, n: T) -> String {
+    match conversion {
+        'x' => format!("{:x}", n),
+        'X' => format!("{:X}", n),
+        'o' => format!("{:o}", n),
+        _ => format!("{:b}", n),
+    }
+}
+
+fn sign_of(directive: &Directive, negative: bool) -> &'static str {
+    if negative {
+        "-"
+    } else if directive.plus {
+        "+"
+    } else if directive.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+/// `%e` as C writes it: at least two exponent digits, always signed
+fn exponent(x: f64, precision: usize) -> String {
+    let formatted = format!("{:.*e}", precision, x);
+    match formatted.split_once('e') {
+        Some((mantissa, exp)) => {
+            let exp: i32 = exp.parse().unwrap_or(0);
+            let sign = if exp < 0 { '-' } else { '+' };
+            format!("{}e{}{:02}", mantissa, sign, exp.abs())
+        }
+        None => formatted,
+    }
+}
+
+fn integer(value: &Value) -> Result<i64, String> {
+    match value {
+        Value::Int(n) => Ok(*n),
+        Value::Bool(b) => Ok(*b as i64),
+        Value::String(s) => match s.trim().parse::<i64>() {
+            Ok(n) => Ok(n),
+            Err(_) => float(value).map(|x| x as i64),
+        },
+        _ => float(value).map(|x| x as i64),
+    }
+}
+
+fn float(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Int(n) => Ok(*n as f64),
+        Value::BigInt(n) => Ok(n.to_f64().unwrap_or(f64::NAN)),
+        Value::Float(x) => Ok(*x),
+        Value::Bool(b) => Ok(*b as i64 as f64),
+        Value::String(s) => s
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("'{}' is not a number", s)),
+        other => Err(format!("'{}' is not a number", other)),
+    }
+}
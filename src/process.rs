@@ -0,0 +1,261 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! External commands run by the `shell()`, `run()` and `spawn()` builtins.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often a command with a timeout is checked for exit
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long the output of a killed command is still read for
+const DRAIN_GRACE: Duration = Duration::from_millis(100);
+
+/// What a finished (or killed) command left behind
+pub struct Output {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Exit code, None when the command was killed by a signal
+    pub code: Option<i32>,
+    /// The command ran past its timeout and was killed
+    pub timed_out: bool,
+}
+
+/// Run `command` with the system shell (`sh -c`, or `cmd /C` on Windows).
+/// `input`, if any, is written to its stdin, which is otherwise empty.
+/// With a `timeout`, a command still running when it expires is killed
+/// together with everything it started, and the output so far is returned
+/// with `timed_out` set.
+pub fn run_shell(command: &str, input: Option<Vec<u8>>, timeout: Option<Duration>) -> io::Result<Output> {
+    run(shell_command(command), input, timeout)
+}
+
+/// Run the program named by `argv[0]` with the rest as its arguments, as
+/// they are: no shell sees them, so nothing needs quoting. `input` and
+/// `timeout` work as for run_shell().
+pub fn run_program(argv: &[String], input: Option<Vec<u8>>, timeout: Option<Duration>) -> io::Result<Output> {
+    run(program_command(argv)?, input, timeout)
+}
+
+fn shell_command(command: &str) -> Command {
+    if cfg!(target_os = "windows") {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+}
+
+fn program_command(argv: &[String]) -> io::Result<Command> {
+    let Some((program, args)) = argv.split_first() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no program given"));
+    };
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    Ok(cmd)
+}
+
+fn run(mut cmd: Command, input: Option<Vec<u8>>, timeout: Option<Duration>) -> io::Result<Output> {
+    let stdin = if input.is_some() { Stdio::piped() } else { Stdio::null() };
+    cmd.stdin(stdin).stdout(Stdio::piped()).stderr(Stdio::piped());
+    // A process group of its own, so that a pipeline's children die with it.
+    #[cfg(unix)]
+    if timeout.is_some() {
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    }
+
+    let mut child = cmd.spawn()?;
+    if let (Some(mut pipe), Some(input)) = (child.stdin.take(), input) {
+        // Written on the side, as the command may print before reading it all.
+        // A command that exits without reading it just closes the pipe.
+        thread::spawn(move || {
+            let _ = pipe.write_all(&input);
+        });
+    }
+    // Drain both pipes while waiting, or a chatty command blocks on a full one.
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let Some(timeout) = timeout else {
+        let status = child.wait()?;
+        return Ok(Output {
+            stdout: stdout.finish(),
+            stderr: stderr.finish(),
+            code: status.code(),
+            timed_out: false,
+        });
+    };
+
+    // The command is done once the shell has exited and its pipes are closed:
+    // something it left running in the background may still be writing.
+    let deadline = Instant::now() + timeout;
+    let mut status = None;
+    let timed_out = loop {
+        if status.is_none() {
+            status = child.try_wait()?;
+        }
+        if status.is_some() && stdout.is_finished() && stderr.is_finished() {
+            break false;
+        }
+        if Instant::now() >= deadline {
+            kill(&mut child);
+            if status.is_none() {
+                child.wait()?;
+            }
+            break true;
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    if timed_out {
+        // A process that left the group can keep the pipes open; don't wait for it.
+        let grace = Instant::now() + DRAIN_GRACE;
+        while !(stdout.is_finished() && stderr.is_finished()) && Instant::now() < grace {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    Ok(Output {
+        stdout: stdout.take(),
+        stderr: stderr.take(),
+        code: if timed_out { None } else { status.and_then(|status| status.code()) },
+        timed_out,
+    })
+}
+
+/// A command started by `spawn()` that runs alongside the script, which
+/// writes to its stdin and reads its stdout a line at a time. Its stderr
+/// goes to the script's.
+pub struct Process {
+    child: Child,
+    /// None once closed, so the command sees the end of its input
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Process {
+    /// Start `command` with the system shell, as run_shell() does
+    pub fn spawn_shell(command: &str) -> io::Result<Process> {
+        Self::start(shell_command(command))
+    }
+
+    /// Start a program without a shell, as run_program() does
+    pub fn spawn_program(argv: &[String]) -> io::Result<Process> {
+        Self::start(program_command(argv)?)
+    }
+
+    fn start(mut cmd: Command) -> io::Result<Process> {
+        cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+        let mut child = cmd.spawn()?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().map(BufReader::new);
+        let stdout = stdout.ok_or_else(|| io::Error::other("no stdout to read"))?;
+        Ok(Process { child, stdin, stdout })
+    }
+
+    /// The next line of output without its newline, waiting for it if
+    /// need be; None once the command has closed its output
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = Vec::new();
+        if self.stdout.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+    }
+
+    /// Close the command's stdin, so it sees the end of its input. Its
+    /// output can still be read.
+    pub fn close_input(&mut self) {
+        self.stdin = None;
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let stdin = self.stdin.as_mut().ok_or(io::ErrorKind::BrokenPipe)?;
+        stdin.write_all(data)?;
+        stdin.flush()
+    }
+
+    /// Close the command's stdin and wait for it to exit. The exit code is
+    /// None when a signal ended it.
+    pub fn wait(mut self) -> io::Result<Option<i32>> {
+        self.stdin = None;
+        Ok(self.child.wait()?.code())
+    }
+
+    /// Stop the command and wait for it to go
+    pub fn kill(mut self) -> io::Result<()> {
+        // Killing one that already exited fails, which is fine.
+        let _ = self.child.kill();
+        self.child.wait().map(|_| ())
+    }
+}
+
+/// A pipe read to the end on a thread of its own
+struct Drain {
+    buf: Arc<Mutex<Vec<u8>>>,
+    thread: JoinHandle<()>,
+}
+
+impl Drain {
+    fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Wait for the end of the pipe and return all of it
+    fn finish(self) -> Vec<u8> {
+        let Drain { buf, thread } = self;
+        let _ = thread.join();
+        Drain::contents(&buf)
+    }
+
+    /// What was read so far, whether or not the pipe has closed
+    fn take(self) -> Vec<u8> {
+        Drain::contents(&self.buf)
+    }
+
+    fn contents(buf: &Mutex<Vec<u8>>) -> Vec<u8> {
+        std::mem::take(&mut *buf.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+fn drain(pipe: Option<impl Read + Send + 'static>) -> Drain {
+    let buf = Arc::new(Mutex::new(Vec::new()));
+    let shared = Arc::clone(&buf);
+    let thread = thread::spawn(move || {
+        let Some(mut pipe) = pipe else { return };
+        let mut chunk = [0; 8192];
+        while let Ok(n @ 1..) = pipe.read(&mut chunk) {
+            shared.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(&chunk[..n]);
+        }
+    });
+    Drain { buf, thread }
+}
+
+#[cfg(unix)]
+fn kill(child: &mut Child) {
+    // SAFETY: kill(2) has no memory-safety preconditions; a negative pid
+    // signals the process group created by process_group(0).
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+}
@@ -0,0 +1,99 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! `include "https://..."`: modules downloaded into a per-user cache,
+//! optionally pinned to a SHA-256 checksum with a `#sha256=<hex>` suffix.
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("https://") || path.starts_with("http://")
+}
+
+/// `https://host/lib.mi#sha256=ab12` -> (`https://host/lib.mi`, Some(`ab12`))
+pub fn split_pin(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once("#sha256=") {
+        Some((url, sum)) => (url, Some(sum)),
+        None => (spec, None),
+    }
+}
+
+/// URL of `path` included from the remote module at `base`
+pub fn join(base: &str, path: &str) -> String {
+    if is_url(path) {
+        return path.to_string();
+    }
+    let scheme_end = base.find("://").map_or(0, |i| i + 3);
+    let host_end = base[scheme_end..]
+        .find('/')
+        .map_or(base.len(), |i| scheme_end + i);
+    if path.starts_with('/') {
+        return format!("{}{}", &base[..host_end], path);
+    }
+    let dir_end = base.rfind('/').filter(|&i| i >= host_end).unwrap_or(host_end);
+    format!("{}/{}", &base[..dir_end], path)
+}
+
+/// Where the download of `url` is kept
+pub fn cache_path(url: &str) -> Option<PathBuf> {
+    crate::pkg::user_cache_dir().map(|dir| dir.join(format!("{}.mi", sha256_hex(url.as_bytes()))))
+}
+
+/// Local copy of `url`, downloaded with curl on first use. With a `pin`,
+/// the copy must have that SHA-256 checksum; a cached copy that does not
+/// is downloaded again.
+pub fn fetch(url: &str, pin: Option<&str>) -> Result<PathBuf, String> {
+    let path = cache_path(url)
+        .ok_or_else(|| "Cannot locate the cache directory (set MINILUX_HOME)".to_string())?;
+    if let Ok(content) = fs::read(&path) {
+        match pin {
+            Some(sum) if !sha256_hex(&content).eq_ignore_ascii_case(sum) => {}
+            _ => return Ok(path),
+        }
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let download = path.with_extension("download");
+    let status = Command::new("curl")
+        .args(["-fsSL", "-o"])
+        .arg(&download)
+        .arg(url)
+        .status()
+        .map_err(|e| format!("Failed to run curl: {}", e))?;
+    if !status.success() {
+        fs::remove_file(&download).ok();
+        return Err(format!("Failed to download {} (curl {})", url, status));
+    }
+
+    let content = fs::read(&download)
+        .map_err(|e| format!("Failed to read {}: {}", download.display(), e))?;
+    if let Some(sum) = pin {
+        let actual = sha256_hex(&content);
+        if !actual.eq_ignore_ascii_case(sum) {
+            fs::remove_file(&download).ok();
+            return Err(format!(
+                "Checksum mismatch for {}: expected sha256 {}, got {}",
+                url, sum, actual
+            ));
+        }
+    }
+    fs::rename(&download, &path)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -0,0 +1,366 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! The interactive console started by `minilux` without a script, with line
+//! editing, tab completion and a history kept in `~/.minilux_history`.
+
+use crate::diagnostics;
+use crate::interpreter::{self, Interpreter, BUILTIN_FUNCTIONS};
+use crate::lsp::KEYWORDS;
+use crate::parser::{Expr, Parser};
+use crate::runtime::VERSION;
+use crate::value::Value;
+use rustyline::completion::Completer;
+use rustyline::config::{CompletionType, Config};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// Completes names at the prompt from what the session has defined so far
+#[derive(Default)]
+struct Completion {
+    variables: Vec<String>,
+    functions: Vec<String>,
+}
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        if let Some(start) = include_path_start(before) {
+            return Ok((start, complete_path(&before[start..])));
+        }
+        let start = before
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let word = &before[start..];
+        let names: Vec<&str> = if before[..start].ends_with('$') {
+            self.variables.iter().map(String::as_str).collect()
+        } else if word.is_empty() {
+            return Ok((pos, Vec::new()));
+        } else {
+            let defined = self.functions.iter().map(String::as_str);
+            defined.chain(BUILTIN_FUNCTIONS.iter().copied()).chain(KEYWORDS.iter().copied()).collect()
+        };
+        let mut candidates: Vec<String> = names
+            .into_iter()
+            .filter(|name| name.starts_with(word))
+            .map(String::from)
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+/// Where the path starts when the cursor is inside the string of an
+/// `include "...`, not yet closed
+fn include_path_start(before: &str) -> Option<usize> {
+    let keyword = before.rfind("include")?;
+    let word_start = before[..keyword]
+        .chars()
+        .next_back()
+        .is_none_or(|c| !(c.is_alphanumeric() || c == '_' || c == '$'));
+    if !word_start {
+        return None;
+    }
+    let rest = &before[keyword + "include".len()..];
+    let quote = rest.find('"')?;
+    let path = &rest[quote + 1..];
+    let only_spaces = rest[..quote].chars().all(char::is_whitespace);
+    (only_spaces && !path.contains('"')).then(|| before.len() - path.len())
+}
+
+/// Files and directories whose path starts with `partial`; directories end
+/// in `/` so completion can go on inside them
+fn complete_path(partial: &str) -> Vec<String> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(i) => (&partial[..=i], &partial[i + 1..]),
+        None => ("", partial),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Commands typed at the prompt that are not Minilux code, for `:help`
+const COMMANDS: &[(&str, &str)] = &[
+    (":load <file>", "Run a script in this session"),
+    (":vars", "List the variables and their values"),
+    (":funcs", "List the functions defined so far"),
+    (":type <expr>", "Show the type of an expression's value"),
+    (":reset", "Forget all variables and functions"),
+    (":help", "Show this list"),
+];
+
+/// Run lines typed at the prompt until `exit` or end of input, in an
+/// interpreter from `new_interpreter` (a fresh one after `:reset`)
+pub fn run(new_interpreter: impl Fn() -> Interpreter, color: bool) {
+    println!("Minilux Interpreter Console (REPL)");
+    println!("Version {} on {} -- [Rust]", VERSION, crate::get_system_info());
+    println!("Type \"exit\" to quit or \":help\" for console commands");
+    println!();
+
+    let config = Config::builder().completion_type(CompletionType::List).build();
+    let mut editor = match Editor::<Completion, _>::with_config(config) {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("Error: cannot start the console: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // Lines piped in are not worth remembering.
+    let history = history_file().filter(|_| io::stdin().is_terminal());
+    if let Some(path) = &history {
+        editor.load_history(path).ok();
+    }
+    editor.set_helper(Some(Completion::default()));
+    // After the editor, which installs a SIGINT handler of its own.
+    crate::handle_interrupts();
+
+    let mut interpreter = new_interpreter();
+    let mut exit_status = None;
+    loop {
+        if let Some(completion) = editor.helper_mut() {
+            completion.variables = interpreter.variable_names();
+            completion.functions = interpreter.function_names();
+        }
+        let input = match editor.readline("> ") {
+            Ok(input) => input,
+            // Ctrl-C abandons the line being typed.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                break;
+            }
+        };
+        let trimmed = input.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(trimmed).ok();
+        if trimmed == "exit" {
+            break;
+        }
+        exit_status = match trimmed.strip_prefix(':') {
+            Some("reset") => {
+                interpreter = new_interpreter();
+                println!("Session cleared");
+                None
+            }
+            Some(command) => run_command(&mut interpreter, command, color),
+            None => run_line(&mut interpreter, trimmed, color),
+        };
+        if exit_status.is_some() {
+            break;
+        }
+        // Ctrl-C stopped the line, which has been reported; the session goes on.
+        interpreter::clear_interrupt();
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Warning: could not save history to {}: {}", path.display(), e);
+        }
+    }
+    if let Some(status) = exit_status {
+        std::process::exit(status);
+    }
+}
+
+/// Run one line, reporting any error in place. A line that is just an
+/// expression shows its value, unless that is nil. Returns the status when
+/// the line called exit().
+fn run_line(interpreter: &mut Interpreter, line: &str, color: bool) -> Option<i32> {
+    if is_expression(line) {
+        match interpreter.eval_source(line) {
+            Ok(Value::Nil) => {}
+            Ok(value) => println!("{}", show(&value)),
+            Err(e) => return report(interpreter, &e, line, color),
+        }
+    } else if let Err(e) = interpreter.execute_source(line) {
+        return report(interpreter, &e, line, color);
+    }
+    None
+}
+
+/// Run a console command such as `vars` or `load file.mi` (typed with a `:`);
+/// like run_line(), returns the status if the code run called exit()
+fn run_command(interpreter: &mut Interpreter, command: &str, color: bool) -> Option<i32> {
+    let (name, arg) = match command.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (command, ""),
+    };
+    match (name, arg) {
+        ("help", _) => {
+            for (usage, description) in COMMANDS {
+                println!("  {:<14} {}", usage, description);
+            }
+        }
+        ("load", "") => eprintln!("Usage: :load <file>"),
+        ("load", path) => {
+            if let Err(e) = crate::run_file(interpreter, path, color) {
+                let status = interpreter.take_exit_status();
+                if status.is_none() {
+                    eprintln!("{}", e);
+                }
+                return status;
+            }
+        }
+        ("vars", _) => {
+            let mut names = interpreter.variable_names();
+            names.sort();
+            for name in names {
+                println!("${} = {}", name, show(&interpreter.get_var(&name)));
+            }
+        }
+        ("funcs", _) => {
+            let mut names = interpreter.function_names();
+            names.sort();
+            for name in names {
+                println!("{}", name);
+            }
+        }
+        ("type", "") => eprintln!("Usage: :type <expr>"),
+        ("type", expr) => match interpreter.eval_source(expr) {
+            Ok(value) => println!("{}", value.type_name()),
+            Err(e) => return report(interpreter, &e, expr, color),
+        },
+        _ => eprintln!("Unknown command ':{}'; type :help for the list", name),
+    }
+    None
+}
+
+/// Whether `line` is a single expression, to be shown rather than run
+fn is_expression(line: &str) -> bool {
+    match Parser::new(line).parse_expression() {
+        // A bare name calls the function of that name, as it does in scripts.
+        Ok((Expr::Variable(_), _)) => line.starts_with('$'),
+        Ok(_) => true,
+        Err(_) => false,
+    }
+}
+
+/// `value` as it would be written in Minilux: strings quoted, and arrays
+/// and maps with their items
+fn show(value: &Value) -> String {
+    let mut out = String::new();
+    show_into(value, &mut Vec::new(), &mut out);
+    out
+}
+
+/// Append `value` to `out`; `open` holds the arrays and maps being shown, so
+/// one that contains itself shows as `[...]` or `{...}` inside
+fn show_into(value: &Value, open: &mut Vec<*const ()>, out: &mut String) {
+    match value {
+        Value::String(s) => out.push_str(&format!("{:?}", s)),
+        Value::Array(items) => {
+            let ptr = Rc::as_ptr(items) as *const ();
+            if open.contains(&ptr) {
+                out.push_str("[...]");
+                return;
+            }
+            open.push(ptr);
+            out.push('[');
+            for (i, item) in items.borrow().iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                show_into(item, open, out);
+            }
+            out.push(']');
+            open.pop();
+        }
+        Value::Map(entries) => {
+            let ptr = Rc::as_ptr(entries) as *const ();
+            if open.contains(&ptr) {
+                out.push_str("{...}");
+                return;
+            }
+            open.push(ptr);
+            out.push('{');
+            for (i, (key, item)) in entries.borrow().iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(&format!("{:?}: ", key));
+                show_into(item, open, out);
+            }
+            out.push('}');
+            open.pop();
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+/// Print an error raised running `line`, unless it was a call to exit(),
+/// whose status is returned instead
+fn report(interpreter: &mut Interpreter, message: &str, line: &str, color: bool) -> Option<i32> {
+    if let Some(status) = interpreter.take_exit_status() {
+        return Some(status);
+    }
+    let trace = interpreter.take_error_trace();
+    // Inside a function the span may be on an earlier line, so only
+    // point into this line for errors raised directly by it.
+    let location = interpreter
+        .take_error_location()
+        .filter(|_| trace.is_empty())
+        .map(|(_, span)| diagnostics::Location {
+            name: "<repl>",
+            source: line,
+            span,
+        });
+    eprintln!(
+        "{}{}",
+        diagnostics::render_error(message, location, color),
+        diagnostics::render_trace(&trace, "<repl>", color)
+    );
+    None
+}
+
+/// Where the history is kept: `$MINILUX_HISTORY` if set (empty to keep
+/// none), or else `~/.minilux_history`
+fn history_file() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("MINILUX_HISTORY") {
+        return Some(PathBuf::from(path)).filter(|p| !p.as_os_str().is_empty());
+    }
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .filter(|v| !v.is_empty())
+        .map(|home| PathBuf::from(home).join(".minilux_history"))
+}
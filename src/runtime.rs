@@ -4,44 +4,138 @@
 // License: MPL 2.0
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::parser::Statement;
+use crate::host::{FileSystem, Input, Network, OsFileSystem, OsNetwork, OsStdin, Socket};
+use crate::parser::Stmt;
+use crate::process::Process;
 use crate::value::Value;
-use std::collections::HashMap;
-use std::net::TcpStream;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
 
-pub struct Runtime {
+/// The interpreter's version, also seen by scripts as `$MINILUX_VERSION`
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A user-defined function
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub params: Vec<String>,
+    /// Shared with the parsed script, so looking a function up is cheap
+    pub body: Rc<Vec<Stmt>>,
+    /// Included file the function was defined in, None for the main script
+    pub file: Option<PathBuf>,
+    /// Locals a lambda captured where it was created; empty for named functions
+    pub captured: HashMap<String, Value>,
+}
+
+/// Variables local to one function call
+#[derive(Default)]
+struct Scope {
     variables: HashMap<String, Value>,
-    sockets: HashMap<String, TcpStream>,
-    functions: HashMap<String, (Vec<String>, Vec<Statement>)>,
+    /// Names declared with `global` in this call
+    globals: HashSet<String>,
+}
+
+pub struct Runtime {
+    globals: HashMap<String, Value>,
+    /// One scope per active function call, innermost last
+    scopes: Vec<Scope>,
+    sockets: HashMap<String, Box<dyn Socket>>,
+    serial_ports: HashMap<String, Box<dyn Socket>>,
+    /// Commands started by `spawn()`, by the handle it returned
+    processes: HashMap<i64, Process>,
+    next_process: i64,
+    functions: HashMap<String, Function>,
+    stdin: Box<dyn Input>,
+    stdout: Box<dyn Write>,
+    stderr: Box<dyn Write>,
+    file_system: Box<dyn FileSystem>,
+    network: Box<dyn Network>,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Runtime {
     pub fn new() -> Self {
         Runtime {
-            variables: HashMap::new(),
+            globals: HashMap::from([("MINILUX_VERSION".to_string(), Value::String(VERSION.to_string()))]),
+            scopes: Vec::new(),
             sockets: HashMap::new(),
+            serial_ports: HashMap::new(),
+            processes: HashMap::new(),
+            next_process: 1,
             functions: HashMap::new(),
+            stdin: Box::new(OsStdin),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            file_system: Box::new(OsFileSystem),
+            network: Box::new(OsNetwork),
         }
     }
 
+    /// Innermost function scope, unless `name` was declared global there
+    fn local_scope(&mut self, name: &str) -> Option<&mut Scope> {
+        self.scopes
+            .last_mut()
+            .filter(|scope| !scope.globals.contains(name))
+    }
+
+    /// Reads a local first, falling back to the global of the same name
     pub fn get_var(&self, name: &str) -> Value {
-        self.variables.get(name).cloned().unwrap_or(Value::Nil)
+        self.var_ref(name).cloned().unwrap_or(Value::Nil)
     }
 
+    /// The variable's value without copying it, None when unset
+    pub fn var_ref(&self, name: &str) -> Option<&Value> {
+        self.scopes
+            .last()
+            .filter(|scope| !scope.globals.contains(name))
+            .and_then(|scope| scope.variables.get(name))
+            .or_else(|| self.globals.get(name))
+    }
+
+    /// Writes a local inside a function call, a global otherwise
     pub fn set_var(&mut self, name: String, value: Value) {
-        self.variables.insert(name, value);
+        match self.local_scope(&name) {
+            Some(scope) => scope.variables.insert(name, value),
+            None => self.globals.insert(name, value),
+        };
     }
 
     pub fn remove_var(&mut self, name: &str) {
-        self.variables.remove(name);
+        match self.local_scope(name) {
+            Some(scope) => scope.variables.remove(name),
+            None => self.globals.remove(name),
+        };
     }
 
+    /// Starts the local scope of a function call
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
 
-    pub fn get_socket(&mut self, name: &str) -> Option<&mut TcpStream> {
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Makes `name` refer to the global variable for the rest of the current call
+    pub fn declare_global(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.variables.remove(name);
+            scope.globals.insert(name.to_string());
+        }
+    }
+
+
+    pub fn get_socket(&mut self, name: &str) -> Option<&mut Box<dyn Socket>> {
         self.sockets.get_mut(name)
     }
 
-    pub fn set_socket(&mut self, name: String, stream: TcpStream) {
+    pub fn set_socket(&mut self, name: String, stream: Box<dyn Socket>) {
         self.sockets.insert(name, stream);
     }
 
@@ -49,21 +143,110 @@ impl Runtime {
         self.sockets.remove(name);
     }
 
+    pub fn get_serial_port(&mut self, name: &str) -> Option<&mut Box<dyn Socket>> {
+        self.serial_ports.get_mut(name)
+    }
+
+    pub fn set_serial_port(&mut self, name: String, port: Box<dyn Socket>) {
+        self.serial_ports.insert(name, port);
+    }
+
+    pub fn remove_serial_port(&mut self, name: &str) {
+        self.serial_ports.remove(name);
+    }
+
     #[allow(dead_code)]
+    /// Keep a spawned process, returning the handle scripts know it by
+    pub fn add_process(&mut self, process: Process) -> i64 {
+        let handle = self.next_process;
+        self.next_process += 1;
+        self.processes.insert(handle, process);
+        handle
+    }
+
+    pub fn get_process(&mut self, handle: i64) -> Option<&mut Process> {
+        self.processes.get_mut(&handle)
+    }
+
+    pub fn remove_process(&mut self, handle: i64) -> Option<Process> {
+        self.processes.remove(&handle)
+    }
+
     pub fn has_socket(&self, name: &str) -> bool {
         self.sockets.contains_key(name)
     }
 
-    pub fn define_function(&mut self, name: String, params: Vec<String>, body: Vec<Statement>) {
-        self.functions.insert(name, (params, body));
+    pub fn define_function(&mut self, name: String, function: Function) {
+        self.functions.insert(name, function);
     }
 
-    pub fn get_function(&self, name: &str) -> Option<(Vec<String>, Vec<Statement>)> {
+    pub fn get_function(&self, name: &str) -> Option<Function> {
         self.functions.get(name).cloned()
     }
 
-    #[allow(dead_code)]
-    pub fn variables(&self) -> &HashMap<String, Value> {
-        &self.variables
+    pub fn has_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Names of the functions defined so far
+    pub fn function_names(&self) -> Vec<String> {
+        self.functions.keys().cloned().collect()
+    }
+
+    /// Copy of the current function call's locals (empty outside functions)
+    pub fn local_variables(&self) -> HashMap<String, Value> {
+        self.scopes
+            .last()
+            .map(|scope| scope.variables.clone())
+            .unwrap_or_default()
+    }
+
+    /// Globals overlaid with the locals of the current function call
+    pub fn visible_variables(&self) -> HashMap<String, Value> {
+        let mut variables = self.globals.clone();
+        if let Some(scope) = self.scopes.last() {
+            variables.extend(scope.variables.clone());
+        }
+        variables
+    }
+
+    pub fn stdin(&mut self) -> &mut dyn Input {
+        self.stdin.as_mut()
+    }
+
+    pub fn set_stdin(&mut self, stdin: Box<dyn Input>) {
+        self.stdin = stdin;
+    }
+
+    pub fn stdout(&mut self) -> &mut dyn Write {
+        self.stdout.as_mut()
+    }
+
+    pub fn set_stdout(&mut self, stdout: Box<dyn Write>) {
+        self.stdout = stdout;
+    }
+
+    pub fn stderr(&mut self) -> &mut dyn Write {
+        self.stderr.as_mut()
+    }
+
+    pub fn set_stderr(&mut self, stderr: Box<dyn Write>) {
+        self.stderr = stderr;
+    }
+
+    pub fn file_system(&self) -> &dyn FileSystem {
+        self.file_system.as_ref()
+    }
+
+    pub fn set_file_system(&mut self, file_system: Box<dyn FileSystem>) {
+        self.file_system = file_system;
+    }
+
+    pub fn network(&mut self) -> &mut dyn Network {
+        self.network.as_mut()
+    }
+
+    pub fn set_network(&mut self, network: Box<dyn Network>) {
+        self.network = network;
     }
 }
@@ -0,0 +1,21 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+/// Modules compiled into the binary, included as `include "std/<name>.mi"`
+const MODULES: &[(&str, &str)] = &[
+    ("std/assert.mi", include_str!("../stdlib/assert.mi")),
+    ("std/list.mi", include_str!("../stdlib/list.mi")),
+    ("std/strings.mi", include_str!("../stdlib/strings.mi")),
+];
+
+/// Source of an embedded module. The `.mi` extension may be left out.
+pub fn source(path: &str) -> Option<&'static str> {
+    MODULES
+        .iter()
+        .find(|(name, _)| *name == path || name.strip_suffix(".mi") == Some(path))
+        .map(|(_, source)| *source)
+}
+
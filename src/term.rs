@@ -0,0 +1,218 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! Direct terminal input, used when stdin is an interactive terminal, and
+//! terminal output helpers.
+
+use std::env;
+use std::io::{self, IsTerminal};
+
+#[cfg(not(target_family = "wasm"))]
+mod native {
+    use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+    use crossterm::terminal;
+    use std::io;
+
+    /// Raw mode for as long as it lives, so errors can't leave the terminal
+    /// without echo.
+    struct RawMode;
+
+    impl RawMode {
+        fn enable() -> io::Result<RawMode> {
+            terminal::enable_raw_mode()?;
+            Ok(RawMode)
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            terminal::disable_raw_mode().ok();
+        }
+    }
+
+    fn interrupted() -> io::Error {
+        io::Error::new(io::ErrorKind::Interrupted, "interrupted")
+    }
+
+    pub fn read_password() -> io::Result<String> {
+        let _raw = RawMode::enable()?;
+        let mut line = String::new();
+        loop {
+            let Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind,
+                ..
+            }) = event::read()?
+            else {
+                continue;
+            };
+            if kind == KeyEventKind::Release {
+                continue;
+            }
+            let control = modifiers.contains(KeyModifiers::CONTROL);
+            match code {
+                KeyCode::Enter => break,
+                KeyCode::Char('c') if control => return Err(interrupted()),
+                KeyCode::Char('d') if control && line.is_empty() => break,
+                KeyCode::Char('u') if control => line.clear(),
+                KeyCode::Char(c) if !control => line.push(c),
+                KeyCode::Backspace => {
+                    line.pop();
+                }
+                _ => {}
+            }
+        }
+        Ok(line)
+    }
+
+    pub fn read_key() -> io::Result<String> {
+        let _raw = RawMode::enable()?;
+        loop {
+            let Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind,
+                ..
+            }) = event::read()?
+            else {
+                continue;
+            };
+            if kind == KeyEventKind::Release {
+                continue;
+            }
+            let key = match code {
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Err(interrupted())
+                }
+                KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+                    format!("ctrl-{}", c)
+                }
+                KeyCode::Char(c) => c.to_string(),
+                KeyCode::Enter => "enter".to_string(),
+                KeyCode::Tab => "tab".to_string(),
+                KeyCode::BackTab => "backtab".to_string(),
+                KeyCode::Backspace => "backspace".to_string(),
+                KeyCode::Esc => "esc".to_string(),
+                KeyCode::Up => "up".to_string(),
+                KeyCode::Down => "down".to_string(),
+                KeyCode::Left => "left".to_string(),
+                KeyCode::Right => "right".to_string(),
+                KeyCode::Home => "home".to_string(),
+                KeyCode::End => "end".to_string(),
+                KeyCode::PageUp => "pageup".to_string(),
+                KeyCode::PageDown => "pagedown".to_string(),
+                KeyCode::Insert => "insert".to_string(),
+                KeyCode::Delete => "delete".to_string(),
+                KeyCode::F(n) => format!("f{}", n),
+                _ => continue,
+            };
+            return Ok(key);
+        }
+    }
+}
+
+/// Read a line from the terminal without echoing it.
+pub fn read_password() -> io::Result<String> {
+    #[cfg(not(target_family = "wasm"))]
+    return native::read_password();
+    #[cfg(target_family = "wasm")]
+    Err(io::Error::new(io::ErrorKind::Unsupported, "no terminal"))
+}
+
+/// Wait for one keypress without echo or Enter. Printable keys are returned
+/// as themselves, others by name ("enter", "up", "f1", "ctrl-a", ...).
+pub fn read_key() -> io::Result<String> {
+    #[cfg(not(target_family = "wasm"))]
+    return native::read_key();
+    #[cfg(target_family = "wasm")]
+    Err(io::Error::new(io::ErrorKind::Unsupported, "no terminal"))
+}
+
+/// Terminal width and height in characters. Falls back to COLUMNS/LINES,
+/// then 80x24, when stdout is not a terminal.
+pub fn size() -> (u16, u16) {
+    #[cfg(not(target_family = "wasm"))]
+    if let Ok(size @ (1.., 1..)) = crossterm::terminal::size() {
+        return size;
+    }
+    let from_env = |name: &str, default: u16| {
+        env::var(name)
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(default)
+    };
+    (from_env("COLUMNS", 80), from_env("LINES", 24))
+}
+
+/// Whether script output should use ANSI colors: stdout is a terminal,
+/// NO_COLOR (https://no-color.org) is unset and TERM is not "dumb".
+pub fn color_enabled() -> bool {
+    env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        && env::var("TERM").map_or(true, |term| term != "dumb")
+        && io::stdout().is_terminal()
+}
+
+/// SGR code of a foreground color name; add 10 for the background.
+pub fn color_code(name: &str) -> Option<u8> {
+    let (bright, base) = match name.strip_prefix("bright_") {
+        Some(base) => (60, base),
+        None => (0, name),
+    };
+    let code = match base {
+        "black" => 30,
+        "red" => 31,
+        "green" => 32,
+        "yellow" => 33,
+        "blue" => 34,
+        "magenta" => 35,
+        "cyan" => 36,
+        "white" => 37,
+        _ => return None,
+    };
+    Some(code + bright)
+}
+
+/// SGR code of a text style name
+pub fn style_code(name: &str) -> Option<u8> {
+    Some(match name {
+        "bold" => 1,
+        "dim" => 2,
+        "italic" => 3,
+        "underline" => 4,
+        "blink" => 5,
+        "reverse" => 7,
+        "strikethrough" => 9,
+        _ => return None,
+    })
+}
+
+/// Whether stdout is a terminal that understands cursor movement
+pub fn is_interactive() -> bool {
+    io::stdout().is_terminal() && env::var("TERM").map_or(true, |term| term != "dumb")
+}
+
+/// Move the cursor to a 1-based column and row.
+pub fn cursor_to(column: i64, row: i64) -> String {
+    format!("\x1b[{};{}H", row.max(1), column.max(1))
+}
+
+pub const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+pub const HIDE_CURSOR: &str = "\x1b[?25l";
+pub const SHOW_CURSOR: &str = "\x1b[?25h";
+/// Back to the start of the line and erase it
+pub const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// Wrap `text` in the given SGR codes, or return it unchanged when there
+/// are none.
+pub fn paint(text: &str, codes: &[u8]) -> String {
+    if codes.is_empty() {
+        return text.to_string();
+    }
+    let codes: Vec<String> = codes.iter().map(u8::to_string).collect();
+    format!("\x1b[{}m{}\x1b[0m", codes.join(";"), text)
+}
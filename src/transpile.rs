@@ -0,0 +1,485 @@
+// The Minilux Programming Language
+// Version: 0.1.0
+// Author: Alexia Michelle <https://minilux.org>
+// License: MPL 2.0
+// SPDX-License-Identifier: MPL-2.0
+
+//! `minilux emit-sh`: lowers a subset of Minilux to POSIX shell.
+//!
+//! Supported: assignments, `inc`/`dec`, `read`, `if`/`elseif`/`else`,
+//! `while`, `for`, `printf`, and the `shell`, `number`, `len`, `strlen`, `lower`,
+//! `upper` and `sleep` builtins. Anything else is reported as unsupported.
+//!
+//! Shell has no types, so `+` (add or join), `==` and truthiness are decided
+//! statically from what each variable is assigned.
+
+use crate::parser::{BinOp, Expr, Statement, Stmt, UnaryOp};
+use crate::printf;
+use crate::value::Value;
+use std::collections::HashMap;
+
+/// Static type of an expression, as far as it can be told
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Int,
+    Str,
+    Unknown,
+}
+
+const PRINTF_HELPER: &str = "\
+# printf as Minilux does it: join the arguments, end with one newline.
+mlx_printf() {
+    mlx_s=''
+    for mlx_a in \"$@\"; do
+        mlx_s=$mlx_s$mlx_a
+    done
+    case $mlx_s in
+        *'
+') printf '%s' \"$mlx_s\" ;;
+        *) printf '%s\\n' \"$mlx_s\" ;;
+    esac
+}
+";
+
+/// Translate a parsed script into a POSIX shell script.
+pub fn emit_sh(statements: &[Stmt], name: &str) -> Result<String, String> {
+    let mut emitter = Emitter {
+        out: String::new(),
+        kinds: HashMap::new(),
+        uses_printf: false,
+        line: 0,
+    };
+    emitter.infer_kinds(statements);
+    emitter.block(statements, 0)?;
+
+    let mut script = format!("#!/bin/sh\n# Generated by `minilux emit-sh` from {}\n\n", name);
+    if emitter.uses_printf {
+        script.push_str(PRINTF_HELPER);
+        script.push('\n');
+    }
+    script.push_str(&emitter.out);
+    Ok(script)
+}
+
+struct Emitter {
+    out: String,
+    kinds: HashMap<String, Kind>,
+    uses_printf: bool,
+    /// Line of the statement being translated, for error messages
+    line: usize,
+}
+
+impl Emitter {
+    /// Settle the kind of every variable from its assignments. Loops can
+    /// assign a variable from itself, so iterate until nothing changes.
+    fn infer_kinds(&mut self, statements: &[Stmt]) {
+        loop {
+            let before = self.kinds.clone();
+            self.collect_kinds(statements);
+            if self.kinds == before {
+                break;
+            }
+        }
+    }
+
+    fn collect_kinds(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            match &stmt.kind {
+                Statement::Assignment { var, value } => {
+                    if let Some(kind) = self.kind_of(value) {
+                        self.merge_kind(var, kind);
+                    }
+                }
+                Statement::Inc { var, .. } | Statement::Dec { var, .. } => {
+                    self.merge_kind(var, Kind::Int)
+                }
+                Statement::Read { var } => self.merge_kind(var, Kind::Str),
+                Statement::If {
+                    then_body,
+                    elseif_parts,
+                    else_body,
+                    ..
+                } => {
+                    self.collect_kinds(then_body);
+                    for (_, body) in elseif_parts {
+                        self.collect_kinds(body);
+                    }
+                    if let Some(body) = else_body {
+                        self.collect_kinds(body);
+                    }
+                }
+                Statement::While { body, .. } => self.collect_kinds(body),
+                Statement::For {
+                    init, step, body, ..
+                } => {
+                    self.collect_kinds(init.as_deref().map_or(&[], std::slice::from_ref));
+                    self.collect_kinds(step.as_deref().map_or(&[], std::slice::from_ref));
+                    self.collect_kinds(body);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn merge_kind(&mut self, var: &str, kind: Kind) {
+        let merged = match self.kinds.get(var) {
+            Some(&known) if known != kind => Kind::Unknown,
+            _ => kind,
+        };
+        self.kinds.insert(var.to_string(), merged);
+    }
+
+    /// Kind of an expression; None while a variable's kind is still unsettled.
+    fn kind_of(&self, expr: &Expr) -> Option<Kind> {
+        match expr {
+            Expr::Int(_) | Expr::Bool(_) => Some(Kind::Int),
+            Expr::String(_) => Some(Kind::Str),
+            Expr::Variable(name) => self.kinds.get(name).copied(),
+            Expr::Binary {
+                left,
+                op: BinOp::Add,
+                right,
+            } => match (self.kind_of(left), self.kind_of(right)) {
+                (Some(Kind::Str), _) | (_, Some(Kind::Str)) => Some(Kind::Str),
+                (Some(Kind::Int), Some(Kind::Int)) => Some(Kind::Int),
+                (Some(Kind::Unknown), _) | (_, Some(Kind::Unknown)) => Some(Kind::Unknown),
+                _ => None,
+            },
+            Expr::Binary { .. } | Expr::Unary { .. } => Some(Kind::Int),
+            Expr::FunctionCall { name, .. } => match name.as_str() {
+                "shell" | "lower" | "upper" => Some(Kind::Str),
+                "number" | "len" | "strlen" => Some(Kind::Int),
+                _ => Some(Kind::Unknown),
+            },
+            _ => Some(Kind::Unknown),
+        }
+    }
+
+    fn kind(&self, expr: &Expr) -> Kind {
+        self.kind_of(expr).unwrap_or(Kind::Unknown)
+    }
+
+    fn unsupported<T>(&self, what: &str) -> Result<T, String> {
+        Err(format!("line {}: {} is not supported by emit-sh", self.line, what))
+    }
+
+    fn block(&mut self, statements: &[Stmt], depth: usize) -> Result<(), String> {
+        if statements.is_empty() {
+            self.emit(depth, ":");
+        }
+        for stmt in statements {
+            self.line = stmt.span.line;
+            self.statement(&stmt.kind, depth)?;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, depth: usize, line: &str) {
+        self.out.push_str(&"    ".repeat(depth));
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn statement(&mut self, statement: &Statement, depth: usize) -> Result<(), String> {
+        match statement {
+            Statement::Assignment { var, value } => {
+                let word = self.word(value)?;
+                self.emit(depth, &format!("{}={}", var, word));
+            }
+            Statement::Inc { var, value } | Statement::Dec { var, value } => {
+                let op = if matches!(statement, Statement::Inc { .. }) { '+' } else { '-' };
+                let amount = self.operand(value)?;
+                self.emit(depth, &format!("{0}=$((${{{0}:-0}} {1} {2}))", var, op, amount));
+            }
+            Statement::Read { var } => self.emit(depth, &format!("IFS= read -r {}", var)),
+            Statement::Printf { format, args } => {
+                self.uses_printf = true;
+                if printf::has_directives(format) {
+                    return self.unsupported("printf format directives");
+                }
+                let mut line = String::from("mlx_printf");
+                if !format.is_empty() {
+                    line.push(' ');
+                    line.push_str(&quote(&format.replace("%%", "%")));
+                }
+                for arg in args {
+                    line.push(' ');
+                    line.push_str(&self.word(arg)?);
+                }
+                self.emit(depth, &line);
+            }
+            Statement::If {
+                condition,
+                then_body,
+                elseif_parts,
+                else_body,
+            } => {
+                let test = self.condition(condition)?;
+                self.emit(depth, &format!("if {}; then", test));
+                self.block(then_body, depth + 1)?;
+                for (condition, body) in elseif_parts {
+                    let test = self.condition(condition)?;
+                    self.emit(depth, &format!("elif {}; then", test));
+                    self.block(body, depth + 1)?;
+                }
+                if let Some(body) = else_body {
+                    self.emit(depth, "else");
+                    self.block(body, depth + 1)?;
+                }
+                self.emit(depth, "fi");
+            }
+            Statement::While { condition, body } => {
+                let test = self.condition(condition)?;
+                self.emit(depth, &format!("while {}; do", test));
+                self.block(body, depth + 1)?;
+                self.emit(depth, "done");
+            }
+            Statement::For {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                if let Some(init) = init {
+                    self.statement(&init.kind, depth)?;
+                }
+                let test = match condition {
+                    Some(condition) => self.condition(condition)?,
+                    None => "true".to_string(),
+                };
+                self.emit(depth, &format!("while {}; do", test));
+                self.block(body, depth + 1)?;
+                if let Some(step) = step {
+                    self.statement(&step.kind, depth + 1)?;
+                }
+                self.emit(depth, "done");
+            }
+            Statement::FunctionCall { name, args } => match (name.as_str(), args.as_slice()) {
+                // Like the interpreter, a bare shell() call discards the output.
+                ("shell", [command]) => {
+                    let command = self.word(command)?;
+                    self.emit(depth, &format!("sh -c {} >/dev/null", command));
+                }
+                ("sleep", [seconds]) => {
+                    let seconds = self.word(seconds)?;
+                    self.emit(depth, &format!("sleep {}", seconds));
+                }
+                ("exit", []) => self.emit(depth, "exit 0"),
+                ("exit", [status]) => {
+                    let status = self.word(status)?;
+                    self.emit(depth, &format!("exit {}", status));
+                }
+                _ => return self.unsupported(&format!("calling '{}'", name)),
+            },
+            Statement::ForIn { .. } => return self.unsupported("for-in loops"),
+            Statement::ArrayAssignment { .. }
+            | Statement::Push { .. }
+            | Statement::Pop { .. }
+            | Statement::Shift { .. }
+            | Statement::Unshift { .. } => return self.unsupported("arrays"),
+            Statement::Sockopen { .. }
+            | Statement::Sockclose { .. }
+            | Statement::Sockwrite { .. }
+            | Statement::Sockread { .. } => return self.unsupported("sockets"),
+            Statement::Serialopen { .. }
+            | Statement::Serialclose { .. }
+            | Statement::Serialwrite { .. }
+            | Statement::Serialread { .. } => return self.unsupported("serial ports"),
+            Statement::Include { .. } => return self.unsupported("include"),
+            // Shell variables are already global.
+            Statement::Global { .. } | Statement::Strict | Statement::Overflow(_) => {}
+            Statement::Try { .. } | Statement::Throw { .. } => return self.unsupported("try/catch"),
+            Statement::FunctionDef { .. } | Statement::Return { .. } => {
+                return self.unsupported("defining functions")
+            }
+        }
+        Ok(())
+    }
+
+    /// A quoted shell word holding the expression's value.
+    fn word(&self, expr: &Expr) -> Result<String, String> {
+        match expr {
+            Expr::Int(n) => Ok(n.to_string()),
+            Expr::Float(f) => Ok(Value::Float(*f).to_string()),
+            // Shell conditions work on numbers, so booleans are 1 and 0.
+            Expr::Bool(b) => Ok((*b as i64).to_string()),
+            Expr::String(s) => Ok(quote(s)),
+            Expr::Variable(name) => Ok(format!("\"${{{}}}\"", name)),
+            Expr::Binary {
+                left,
+                op: BinOp::Add,
+                right,
+            } if self.kind(expr) == Kind::Str => Ok(format!("{}{}", self.word(left)?, self.word(right)?)),
+            Expr::FunctionCall { name, args, .. } => match (name.as_str(), args.as_slice()) {
+                ("shell", [command]) => Ok(format!("\"$(sh -c {})\"", self.word(command)?)),
+                ("lower", [text]) => Ok(format!(
+                    "\"$(printf '%s' {} | tr '[:upper:]' '[:lower:]')\"",
+                    self.word(text)?
+                )),
+                ("upper", [text]) => Ok(format!(
+                    "\"$(printf '%s' {} | tr '[:lower:]' '[:upper:]')\"",
+                    self.word(text)?
+                )),
+                ("number" | "len" | "strlen", [_]) => Ok(format!("$(({}))", self.arith(expr)?)),
+                _ => self.unsupported(&format!("calling '{}'", name)),
+            },
+            Expr::Binary { .. } | Expr::Unary { .. } => match self.kind(expr) {
+                Kind::Int => Ok(format!("$(({}))", self.arith(expr)?)),
+                _ => self.unsupported("'+' on values of unknown type"),
+            },
+            Expr::Array(_) | Expr::Index { .. } | Expr::Slice { .. } => self.unsupported("arrays"),
+            Expr::Map(_) => self.unsupported("maps"),
+            Expr::Lambda { .. } => self.unsupported("function values"),
+            Expr::Regex { .. } | Expr::SubstCall { .. } => self.unsupported("regex substitution"),
+        }
+    }
+
+    /// An expression usable inside `$(( ))`.
+    fn arith(&self, expr: &Expr) -> Result<String, String> {
+        match expr {
+            Expr::Int(n) => Ok(n.to_string()),
+            Expr::Float(_) => self.unsupported("floating-point arithmetic"),
+            Expr::Bool(b) => Ok((*b as i64).to_string()),
+            Expr::String(s) => Ok(s.trim().parse::<i64>().unwrap_or(0).to_string()),
+            Expr::Variable(name) => Ok(format!("${{{}:-0}}", name)),
+            Expr::Binary { left, op, right } => {
+                let symbol = match op {
+                    BinOp::Add if self.kind(expr) == Kind::Int => "+",
+                    BinOp::Add => return self.unsupported("using a joined string as a number"),
+                    BinOp::Subtract => "-",
+                    BinOp::Multiply => "*",
+                    BinOp::Divide => "/",
+                    BinOp::Modulo => "%",
+                    BinOp::Power => return self.unsupported("'**'"),
+                    BinOp::Coalesce => return self.unsupported("'??'"),
+                    BinOp::RangeInclusive | BinOp::RangeExclusive => {
+                        return self.unsupported("ranges")
+                    }
+                    _ => {
+                        return Ok(format!(
+                            "$(if {}; then echo 1; else echo 0; fi)",
+                            self.condition(expr)?
+                        ))
+                    }
+                };
+                Ok(format!("{} {} {}", self.operand(left)?, symbol, self.operand(right)?))
+            }
+            Expr::Unary {
+                op: UnaryOp::Negate,
+                expr,
+            } => Ok(format!("-{}", self.operand(expr)?)),
+            Expr::Unary { op: UnaryOp::Not, .. } => Ok(format!(
+                "$(if {}; then echo 1; else echo 0; fi)",
+                self.condition(expr)?
+            )),
+            Expr::FunctionCall { name, args, .. } => match (name.as_str(), args.as_slice()) {
+                ("number", [Expr::Variable(var)]) => Ok(format!("${{{}:-0}}", var)),
+                ("number", [value]) if self.kind(value) == Kind::Int => self.arith(value),
+                ("len" | "strlen", [Expr::Variable(var)]) => Ok(format!("${{#{}}}", var)),
+                ("number", [value]) => Ok(format!("$(printf '%s' {})", self.word(value)?)),
+                ("len" | "strlen", [value]) => {
+                    Ok(format!("$(printf '%s' {} | wc -m)", self.word(value)?))
+                }
+                ("shell", [command]) => Ok(format!("$(sh -c {})", self.word(command)?)),
+                _ => self.unsupported(&format!("using '{}' as a number", name)),
+            },
+            Expr::Array(_) | Expr::Index { .. } | Expr::Slice { .. } => self.unsupported("arrays"),
+            Expr::Map(_) => self.unsupported("maps"),
+            Expr::Lambda { .. } => self.unsupported("function values"),
+            Expr::Regex { .. } | Expr::SubstCall { .. } => self.unsupported("regex substitution"),
+        }
+    }
+
+    /// An arithmetic operand, parenthesized when it is itself an operation.
+    fn operand(&self, expr: &Expr) -> Result<String, String> {
+        let arith = self.arith(expr)?;
+        match expr {
+            Expr::Binary {
+                op: BinOp::Add | BinOp::Subtract | BinOp::Multiply | BinOp::Divide | BinOp::Modulo,
+                ..
+            } => Ok(format!("({})", arith)),
+            _ => Ok(arith),
+        }
+    }
+
+    /// A command list whose exit status is the expression's truth.
+    fn condition(&self, expr: &Expr) -> Result<String, String> {
+        match expr {
+            Expr::Binary { left, op, right } => {
+                let numeric =
+                    self.kind(left) == Kind::Int || self.kind(right) == Kind::Int;
+                let test = match op {
+                    BinOp::Equal if numeric => "-eq",
+                    BinOp::NotEqual if numeric => "-ne",
+                    BinOp::Equal => "=",
+                    BinOp::NotEqual => "!=",
+                    BinOp::Less if numeric => "-lt",
+                    BinOp::LessEqual if numeric => "-le",
+                    BinOp::Greater if numeric => "-gt",
+                    BinOp::GreaterEqual if numeric => "-ge",
+                    BinOp::Less | BinOp::LessEqual | BinOp::Greater | BinOp::GreaterEqual => {
+                        return self.unsupported("ordering strings")
+                    }
+                    BinOp::And => {
+                        return Ok(format!(
+                            "{} && {}",
+                            group(self.condition(left)?),
+                            group(self.condition(right)?)
+                        ))
+                    }
+                    BinOp::Or => {
+                        return Ok(format!(
+                            "{} || {}",
+                            group(self.condition(left)?),
+                            group(self.condition(right)?)
+                        ))
+                    }
+                    BinOp::Match => {
+                        let (pattern, options) = match right.as_ref() {
+                            Expr::Regex { pat, flags } if flags.is_empty() => (quote(pat), "-Eq"),
+                            Expr::Regex { pat, flags } if flags == "i" => (quote(pat), "-Eiq"),
+                            Expr::Regex { .. } => return self.unsupported("regex flags other than i"),
+                            Expr::String(pattern) => (quote(pattern), "-Eq"),
+                            _ => return self.unsupported("matching a computed pattern"),
+                        };
+                        return Ok(format!(
+                            "printf '%s\\n' {} | grep {} -- {}",
+                            self.word(left)?,
+                            options,
+                            pattern
+                        ));
+                    }
+                    _ => return self.truthy(expr),
+                };
+                Ok(format!("[ {} {} {} ]", self.word(left)?, test, self.word(right)?))
+            }
+            Expr::Unary {
+                op: UnaryOp::Not,
+                expr,
+            } => Ok(format!("! {}", group(self.condition(expr)?))),
+            _ => self.truthy(expr),
+        }
+    }
+
+    fn truthy(&self, expr: &Expr) -> Result<String, String> {
+        match self.kind(expr) {
+            Kind::Int => Ok(format!("[ {} -ne 0 ]", self.word(expr)?)),
+            Kind::Str => Ok(format!("[ -n {} ]", self.word(expr)?)),
+            Kind::Unknown => self.unsupported("testing a value of unknown type"),
+        }
+    }
+}
+
+/// Wrap a compound command list so it can be combined with && or ||.
+fn group(commands: String) -> String {
+    if commands.contains("&&") || commands.contains("||") {
+        format!("{{ {}; }}", commands)
+    } else {
+        commands
+    }
+}
+
+/// Single-quote a string for the shell.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
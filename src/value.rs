@@ -4,31 +4,183 @@
 // License: MPL 2.0
 // SPDX-License-Identifier: MPL-2.0
 
+use crate::runtime::Function;
+use num_bigint::{BigInt, Sign};
+use num_traits::{ToPrimitive, Zero};
+use std::cell::RefCell;
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::rc::Rc;
 
 /// Represents a value in the minilux language
+///
+/// Arrays and maps are shared: assigning one or passing it to a function
+/// refers to the same items, so changes made through either name show in both.
 #[derive(Debug, Clone)]
 pub enum Value {
     Int(i64),
+    /// An integer too large for Int, made by `overflow promote` or bigint()
+    BigInt(BigInt),
+    Float(f64),
+    Bool(bool),
     String(String),
-    Array(Vec<Value>),
+    Array(Rc<RefCell<Vec<Value>>>),
+    /// Associative array with string keys, kept in key order
+    Map(Rc<RefCell<BTreeMap<String, Value>>>),
     Regex(String),
+    Function(Callable),
     Nil,
 }
 
+/// What integer arithmetic does when a result does not fit in 64 bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Wrap around, as two's complement hardware does
+    Wrap,
+    /// Stop at the largest or smallest integer
+    Saturate,
+    /// Raise an error, which `try`/`catch` can handle
+    Error,
+    /// Carry on with an arbitrary precision integer
+    Promote,
+}
+
+impl Overflow {
+    pub fn from_name(name: &str) -> Option<Overflow> {
+        match name {
+            "wrap" => Some(Overflow::Wrap),
+            "saturate" => Some(Overflow::Saturate),
+            "error" => Some(Overflow::Error),
+            "promote" => Some(Overflow::Promote),
+            _ => None,
+        }
+    }
+}
+
+/// A function used as a value
+#[derive(Debug, Clone)]
+pub enum Callable {
+    /// A function defined with `function name(...)` or by the host, looked up when called
+    Named(String),
+    /// An anonymous `function (...) { ... }` expression
+    Lambda(Rc<Function>),
+}
+
 impl Value {
-    /// Convert to string representation
-    pub fn to_string(&self) -> String {
+    /// A new array holding `items`
+    pub fn array(items: Vec<Value>) -> Value {
+        Value::Array(Rc::new(RefCell::new(items)))
+    }
+
+    /// A new map holding `entries`
+    pub fn map(entries: BTreeMap<String, Value>) -> Value {
+        Value::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    /// An integer, as an Int when it fits in 64 bits and a BigInt otherwise
+    pub fn big(n: BigInt) -> Value {
+        match n.to_i64() {
+            Some(n) => Value::Int(n),
+            None => Value::BigInt(n),
+        }
+    }
+
+    /// The value as an arbitrary precision integer, for Int, BigInt and Bool
+    pub fn to_bigint(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(n) => Some(BigInt::from(*n)),
+            Value::BigInt(n) => Some(n.clone()),
+            Value::Bool(b) => Some(BigInt::from(*b as i64)),
+            _ => None,
+        }
+    }
+
+    /// A copy that shares nothing with `self`: nested arrays and maps are
+    /// copied too, and one that contains itself gives a copy that does.
+    pub fn deep_copy(&self) -> Value {
+        self.copy_with(&mut HashMap::new())
+    }
+
+    fn copy_with(&self, copies: &mut HashMap<*const (), Value>) -> Value {
         match self {
-            Value::Int(n) => n.to_string(),
-            Value::String(s) => s.clone(),
             Value::Array(arr) => {
-                let items: Vec<String> = arr.iter().map(|v| v.to_string()).collect();
-                format!("[{}]", items.join(", "))
+                let key = Rc::as_ptr(arr) as *const ();
+                if let Some(copy) = copies.get(&key) {
+                    return copy.clone();
+                }
+                let copy = Rc::new(RefCell::new(Vec::new()));
+                copies.insert(key, Value::Array(Rc::clone(&copy)));
+                let items = arr.borrow().iter().map(|v| v.copy_with(copies)).collect();
+                *copy.borrow_mut() = items;
+                Value::Array(copy)
             }
-            Value::Regex(p) => format!("/{}/", p),
-            Value::Nil => "nil".to_string(),
+            Value::Map(map) => {
+                let key = Rc::as_ptr(map) as *const ();
+                if let Some(copy) = copies.get(&key) {
+                    return copy.clone();
+                }
+                let copy = Rc::new(RefCell::new(BTreeMap::new()));
+                copies.insert(key, Value::Map(Rc::clone(&copy)));
+                let entries = map
+                    .borrow()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.copy_with(copies)))
+                    .collect();
+                *copy.borrow_mut() = entries;
+                Value::Map(copy)
+            }
+            other => other.clone(),
+        }
+    }
+
+    /// Parse a number as written in source or typed by a user: an optional
+    /// sign, then `0x1F`, `0o17`, `0b1010`, or a decimal such as `1_000_000`,
+    /// `2.5` or `1e-3`. Underscores may only separate digits. Decimal
+    /// integers too large for an Int become a Float.
+    pub fn parse_number(text: &str) -> Option<Value> {
+        let (negative, radix, digits) = split_number(text)?;
+        let value = if radix != 10 {
+            Value::Int(i64::from_str_radix(&digits, radix).ok()?)
+        } else if let Ok(n) = digits.parse::<i64>() {
+            Value::Int(n)
+        } else if digits
+            .bytes()
+            .all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-'))
+        {
+            // Checked first, as Rust would also read "inf" and "NaN".
+            Value::Float(digits.parse::<f64>().ok()?)
+        } else {
+            return None;
+        };
+        Some(match (negative, value) {
+            (false, value) => value,
+            (true, Value::Int(n)) => Value::Int(-n),
+            (true, Value::Float(x)) => Value::Float(-x),
+            (true, other) => other,
+        })
+    }
+
+    /// Parse an integer of any size, written as parse_number() reads them
+    pub fn parse_integer(text: &str) -> Option<Value> {
+        let (negative, radix, digits) = split_number(text)?;
+        let n = BigInt::parse_bytes(digits.as_bytes(), radix)?;
+        Some(Value::big(if negative { -n } else { n }))
+    }
+
+    /// The name of the value's type: int, float, bool, string, array, map,
+    /// regex, function or nil. Integers of any size are ints.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) | Value::BigInt(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Map(_) => "map",
+            Value::Regex(_) => "regex",
+            Value::Function(_) => "function",
+            Value::Nil => "nil",
         }
     }
 
@@ -36,9 +188,15 @@ impl Value {
     pub fn to_int(&self) -> i64 {
         match self {
             Value::Int(n) => *n,
+            // Out of range, so the nearest Int.
+            Value::BigInt(n) => if n.sign() == Sign::Minus { i64::MIN } else { i64::MAX },
+            Value::Float(f) => *f as i64,
+            Value::Bool(b) => *b as i64,
             Value::String(s) => s.parse().unwrap_or(0),
             Value::Array(_) => 0,
+            Value::Map(_) => 0,
             Value::Regex(_) => 0,
+            Value::Function(_) => 0,
             Value::Nil => 0,
         }
     }
@@ -47,31 +205,124 @@ impl Value {
     pub fn is_truthy(&self) -> bool {
         match self {
             Value::Int(n) => *n != 0,
+            Value::BigInt(n) => !n.is_zero(),
+            Value::Float(f) => *f != 0.0,
+            Value::Bool(b) => *b,
             Value::String(s) => !s.is_empty(),
-            Value::Array(arr) => !arr.is_empty(),
+            Value::Array(arr) => !arr.borrow().is_empty(),
+            Value::Map(map) => !map.borrow().is_empty(),
             Value::Regex(p) => !p.is_empty(),
+            Value::Function(_) => true,
             Value::Nil => false,
         }
     }
 
-    /// Compare two values for equality
+    /// Compare two values for equality. Arrays are equal when their elements
+    /// are, in order, and maps when they have the same keys with equal values.
     pub fn equals(&self, other: &Value) -> bool {
+        self.equals_within(other, &mut Vec::new())
+    }
+
+    /// equals(), where `open` holds the pairs of arrays and maps being
+    /// compared further up. Meeting one of them again means both contain
+    /// themselves, and such values are never equal unless they are the same.
+    fn equals_within(&self, other: &Value, open: &mut Vec<(*const (), *const ())>) -> bool {
         match (self, other) {
+            (Value::Array(a), Value::Array(b)) => {
+                let pair = (Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ());
+                if Rc::ptr_eq(a, b) {
+                    return true;
+                }
+                if open.contains(&pair) {
+                    return false;
+                }
+                open.push(pair);
+                let equal = {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| x.equals_within(y, open))
+                };
+                open.pop();
+                equal
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                let pair = (Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ());
+                if Rc::ptr_eq(a, b) {
+                    return true;
+                }
+                if open.contains(&pair) {
+                    return false;
+                }
+                open.push(pair);
+                let equal = {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len()
+                        && a.iter()
+                            .zip(b.iter())
+                            .all(|((ka, va), (kb, vb))| ka == kb && va.equals_within(vb, open))
+                };
+                open.pop();
+                equal
+            }
             (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::BigInt(_), _) | (_, Value::BigInt(_)) => {
+                matches!(self.compare(other), Some(Ordering::Equal))
+            }
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Int(a), Value::Float(b)) | (Value::Float(b), Value::Int(a)) => *a as f64 == *b,
             (Value::String(a), Value::String(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            // Comparisons used to give 1 and 0, so booleans still equal those.
+            (Value::Bool(a), Value::Int(b)) | (Value::Int(b), Value::Bool(a)) => *a as i64 == *b,
             (Value::Nil, Value::Nil) => true,
             (Value::Int(a), Value::String(b)) => a.to_string() == *b,
             (Value::String(a), Value::Int(b)) => a == &b.to_string(),
             (Value::Regex(a), Value::Regex(b)) => a == b,
+            (Value::Function(Callable::Named(a)), Value::Function(Callable::Named(b))) => a == b,
+            (Value::Function(Callable::Lambda(a)), Value::Function(Callable::Lambda(b))) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
 
-    /// Compare two values
+    /// Compare two values. Strings and arrays compare lexicographically, an
+    /// array that is a prefix of another coming first; None when unordered.
     pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        self.compare_within(other, &mut Vec::new())
+    }
+
+    /// compare(), with `open` as in equals_within(); arrays that contain
+    /// themselves are unordered.
+    fn compare_within(&self, other: &Value, open: &mut Vec<(*const (), *const ())>) -> Option<Ordering> {
         match (self, other) {
+            (Value::Array(a), Value::Array(b)) => {
+                let pair = (Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ());
+                if Rc::ptr_eq(a, b) {
+                    return Some(Ordering::Equal);
+                }
+                if open.contains(&pair) {
+                    return None;
+                }
+                open.push(pair);
+                let order = {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.iter()
+                        .zip(b.iter())
+                        .map(|(x, y)| x.compare_within(y, open))
+                        .find(|order| *order != Some(Ordering::Equal))
+                        .unwrap_or(Some(a.len().cmp(&b.len())))
+                };
+                open.pop();
+                order
+            }
             (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::BigInt(a), Value::Float(b)) => a.to_f64()?.partial_cmp(b),
+            (Value::Float(a), Value::BigInt(b)) => a.partial_cmp(&b.to_f64()?),
+            (Value::BigInt(_), Value::Int(_) | Value::BigInt(_))
+            | (Value::Int(_), Value::BigInt(_)) => Some(self.to_bigint()?.cmp(&other.to_bigint()?)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
             (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
             (Value::Int(a), Value::String(b)) => {
                 if let Ok(b_int) = b.parse::<i64>() {
                     Some(a.cmp(&b_int))
@@ -90,41 +341,102 @@ impl Value {
         }
     }
 
+    /// Booleans take part in arithmetic as 1 and 0.
+    fn numeric(&self) -> &Value {
+        match self {
+            Value::Bool(true) => &Value::Int(1),
+            Value::Bool(false) => &Value::Int(0),
+            _ => self,
+        }
+    }
+
+    /// Both operands as floats, when at least one is a float and the other
+    /// a number. Mixed Int/Float arithmetic is done in floating point.
+    fn float_operands(&self, other: &Value) -> Option<(f64, f64)> {
+        match (self.numeric(), other.numeric()) {
+            (Value::Float(a), Value::Float(b)) => Some((*a, *b)),
+            (Value::Int(a), Value::Float(b)) => Some((*a as f64, *b)),
+            (Value::Float(a), Value::Int(b)) => Some((*a, *b as f64)),
+            (Value::BigInt(a), Value::Float(b)) => Some((a.to_f64()?, *b)),
+            (Value::Float(a), Value::BigInt(b)) => Some((*a, b.to_f64()?)),
+            _ => None,
+        }
+    }
+
+    /// Both operands as arbitrary precision integers, when at least one is
+    /// a BigInt and the other an integer. That arithmetic is always exact.
+    fn big_operands(&self, other: &Value) -> Option<(BigInt, BigInt)> {
+        if !matches!(self, Value::BigInt(_)) && !matches!(other, Value::BigInt(_)) {
+            return None;
+        }
+        Some((self.to_bigint()?, other.to_bigint()?))
+    }
+
     /// Add two values
     pub fn add(&self, other: &Value) -> Value {
+        if let Some((a, b)) = self.float_operands(other) {
+            return Value::Float(a + b);
+        }
+        if let Some((a, b)) = self.big_operands(other) {
+            return Value::big(a + b);
+        }
         match (self, other) {
-            (Value::Int(a), Value::Int(b)) => Value::Int(a + b),
             (Value::String(a), Value::String(b)) => Value::String(format!("{}{}", a, b)),
-            (Value::Int(a), Value::String(b)) => Value::String(format!("{}{}", a, b)),
-            (Value::String(a), Value::Int(b)) => Value::String(format!("{}{}", a, b)),
-            _ => Value::Nil,
+            (Value::Int(_) | Value::BigInt(_) | Value::Float(_) | Value::Bool(_), Value::String(b)) => {
+                Value::String(format!("{}{}", self, b))
+            }
+            (Value::String(a), Value::Int(_) | Value::BigInt(_) | Value::Float(_) | Value::Bool(_)) => {
+                Value::String(format!("{}{}", a, other))
+            }
+            _ => match (self.numeric(), other.numeric()) {
+                (Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_add(*b)),
+                _ => Value::Nil,
+            },
         }
     }
 
     /// Subtract two values
     pub fn subtract(&self, other: &Value) -> Value {
-        match (self, other) {
-            (Value::Int(a), Value::Int(b)) => Value::Int(a - b),
+        if let Some((a, b)) = self.float_operands(other) {
+            return Value::Float(a - b);
+        }
+        if let Some((a, b)) = self.big_operands(other) {
+            return Value::big(a - b);
+        }
+        match (self.numeric(), other.numeric()) {
+            (Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_sub(*b)),
             _ => Value::Nil,
         }
     }
 
     /// Multiply two values
     pub fn multiply(&self, other: &Value) -> Value {
-        match (self, other) {
-            (Value::Int(a), Value::Int(b)) => Value::Int(a * b),
+        if let Some((a, b)) = self.float_operands(other) {
+            return Value::Float(a * b);
+        }
+        if let Some((a, b)) = self.big_operands(other) {
+            return Value::big(a * b);
+        }
+        match (self.numeric(), other.numeric()) {
+            (Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_mul(*b)),
             _ => Value::Nil,
         }
     }
 
-    /// Divide two values
+    /// Divide two values. Two integers give an integer (truncated) result.
     pub fn divide(&self, other: &Value) -> Value {
-        match (self, other) {
+        if let Some((a, b)) = self.float_operands(other) {
+            return if b == 0.0 { Value::Nil } else { Value::Float(a / b) };
+        }
+        if let Some((a, b)) = self.big_operands(other) {
+            return if b.is_zero() { Value::Nil } else { Value::big(a / b) };
+        }
+        match (self.numeric(), other.numeric()) {
             (Value::Int(a), Value::Int(b)) => {
                 if *b == 0 {
                     Value::Nil
                 } else {
-                    Value::Int(a / b)
+                    Value::Int(a.wrapping_div(*b))
                 }
             }
             _ => Value::Nil,
@@ -133,12 +445,18 @@ impl Value {
 
     /// Modulo two values
     pub fn modulo(&self, other: &Value) -> Value {
-        match (self, other) {
+        if let Some((a, b)) = self.float_operands(other) {
+            return if b == 0.0 { Value::Nil } else { Value::Float(a % b) };
+        }
+        if let Some((a, b)) = self.big_operands(other) {
+            return if b.is_zero() { Value::Nil } else { Value::big(a % b) };
+        }
+        match (self.numeric(), other.numeric()) {
             (Value::Int(a), Value::Int(b)) => {
                 if *b == 0 {
                     Value::Nil
                 } else {
-                    Value::Int(a % b)
+                    Value::Int(a.wrapping_rem(*b))
                 }
             }
             _ => Value::Nil,
@@ -146,8 +464,106 @@ impl Value {
     }
 }
 
+impl Value {
+    /// Raise to a power. Integers stay integers while the result fits.
+    pub fn power(&self, other: &Value) -> Value {
+        if let Some((a, b)) = self.float_operands(other) {
+            return Value::Float(a.powf(b));
+        }
+        if let Some((a, b)) = self.big_operands(other) {
+            return match (a.to_f64(), b.to_f64()) {
+                (Some(a), Some(b)) => Value::Float(a.powf(b)),
+                _ => Value::Nil,
+            };
+        }
+        match (self.numeric(), other.numeric()) {
+            (Value::Int(a), Value::Int(b)) => {
+                match u32::try_from(*b).ok().and_then(|b| a.checked_pow(b)) {
+                    Some(n) => Value::Int(n),
+                    None => Value::Float((*a as f64).powf(*b as f64)),
+                }
+            }
+            _ => Value::Nil,
+        }
+    }
+}
+
+thread_local! {
+    /// Arrays and maps being displayed, so one that contains itself shows as `[...]`
+    static DISPLAYING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Display the items of the array or map at `ptr` with `write`, or `elided`
+/// if it is already being displayed further up.
+fn display_once(
+    ptr: *const (),
+    elided: &str,
+    f: &mut fmt::Formatter,
+    write: impl FnOnce(&mut fmt::Formatter) -> fmt::Result,
+) -> fmt::Result {
+    if DISPLAYING.with(|shown| shown.borrow().contains(&ptr)) {
+        return write!(f, "{}", elided);
+    }
+    DISPLAYING.with(|shown| shown.borrow_mut().push(ptr));
+    let result = write(f);
+    DISPLAYING.with(|shown| shown.borrow_mut().pop());
+    result
+}
+
 impl fmt::Display for Value {
+    /// Convert to string representation
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.to_string())
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::BigInt(n) => write!(f, "{}", n),
+            // Whole floats keep a ".0" so they read differently from integers.
+            Value::Float(x) if x.is_finite() && x.fract() == 0.0 => write!(f, "{:.1}", x),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Array(arr) => display_once(Rc::as_ptr(arr) as *const (), "[...]", f, |f| {
+                let items: Vec<String> = arr.borrow().iter().map(|v| v.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }),
+            Value::Map(map) => display_once(Rc::as_ptr(map) as *const (), "{...}", f, |f| {
+                let items: Vec<String> =
+                    map.borrow().iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+                write!(f, "{{{}}}", items.join(", "))
+            }),
+            Value::Regex(p) => write!(f, "/{}/", p),
+            Value::Function(Callable::Named(name)) => write!(f, "[Function {}]", name),
+            Value::Function(Callable::Lambda(_)) => write!(f, "[Function]"),
+            Value::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+/// The sign, radix and digits (without underscores) of a number as
+/// parse_number() reads them
+fn split_number(text: &str) -> Option<(bool, u32, String)> {
+    let (negative, unsigned) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+    let radix = match unsigned.get(..2) {
+        Some("0x" | "0X") => 16,
+        Some("0o" | "0O") => 8,
+        Some("0b" | "0B") => 2,
+        _ => 10,
+    };
+    let digits = if radix == 10 { unsigned } else { &unsigned[2..] };
+    // Underscores must sit between two digits.
+    let bytes = digits.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        let digit = |j: usize| bytes.get(j).is_some_and(|c| (*c as char).is_digit(radix));
+        if b == b'_' && (i == 0 || !digit(i - 1) || !digit(i + 1)) {
+            return None;
+        }
+    }
+    let digits = digits.replace('_', "");
+    if digits.is_empty() || digits.starts_with(['+', '-']) {
+        return None;
     }
+    Some((negative, radix, digits))
 }